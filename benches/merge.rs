@@ -0,0 +1,67 @@
+//! Bulk set operations on two large maps. Each iteration clones the left
+//! map first, so `rudymap_clone` is the baseline to subtract.
+
+extern crate rudy;
+
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use rudy::rudymap::RudyMap;
+
+const SIZE: u32 = 1 << 20;
+
+/// Every `step`th key in a range twice the size of the map, so two maps
+/// with different steps share some leaves and branches and not others.
+fn map(step: u32) -> RudyMap<u32, ()> {
+    RudyMap::from_sorted_iter((0..SIZE).map(|n| (n * step, ())))
+}
+
+fn rudymap_clone(b: &mut Bencher) {
+    let left = map(2);
+    b.iter(|| left.clone().len())
+}
+
+fn rudymap_union_with(b: &mut Bencher) {
+    let (left, right) = (map(2), map(3));
+    b.iter(|| {
+        let mut merged = left.clone();
+        merged.union_with(right.clone());
+        merged.len()
+    })
+}
+
+fn rudymap_union_with_by_entry(b: &mut Bencher) {
+    let (left, right) = (map(2), map(3));
+    b.iter(|| {
+        let mut merged = left.clone();
+        for (key, &()) in right.iter() {
+            merged.entry(key).or_insert(());
+        }
+        merged.len()
+    })
+}
+
+fn rudymap_intersect_with(b: &mut Bencher) {
+    let (left, right) = (map(2), map(3));
+    b.iter(|| {
+        let mut common = left.clone();
+        common.intersect_with(&right);
+        common.len()
+    })
+}
+
+fn rudymap_difference_with(b: &mut Bencher) {
+    let (left, right) = (map(2), map(3));
+    b.iter(|| {
+        let mut rest = left.clone();
+        rest.difference_with(&right);
+        rest.len()
+    })
+}
+
+benchmark_group!(benches,
+    rudymap_clone, rudymap_union_with, rudymap_union_with_by_entry,
+    rudymap_intersect_with, rudymap_difference_with
+);
+benchmark_main!(benches);
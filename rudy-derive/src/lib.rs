@@ -0,0 +1,195 @@
+//! `#[derive(Key)]` for rudy, enabled through rudy's `derive` feature.
+//!
+//! Two shapes of type are supported:
+//!
+//! - Structs, including newtypes. The key bytes are the bytes of each field
+//!   in declaration order, so keys sort like a derived `Ord`.
+//! - Fieldless enums with a `#[repr]` integer type. The key bytes are those
+//!   of the discriminant.
+//!
+//! The type must also implement `Copy`, `Ord`, `Default` and `Debug`, and
+//! its `Ord` has to agree with the key bytes, which the derived one does.
+//!
+//! A struct is keyed like a tuple of its fields, so its key bytes are
+//! limited to the 64 bytes a tuple key holds. Using a longer one as a key
+//! fails to compile.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, Type};
+
+#[proc_macro_derive(Key)]
+pub fn derive_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let expanded = match input.data {
+        Data::Struct(ref data) => derive_struct(&input, &data.fields),
+        Data::Enum(ref data) => derive_enum(&input, data),
+        Data::Union(_) => Err(Error::new(Span::call_site(), "Key cannot be derived for unions")),
+    };
+    expanded.unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+/// Fold `items` into right-nested pairs, `(a, (b, c))`. Nested tuples lay
+/// their bytes out the same way as a flat one, so any number of fields fit
+/// as long as their bytes stay within the tuple limit.
+fn nest(items: &[TokenStream2]) -> TokenStream2 {
+    match items {
+        [] => unreachable!(),
+        [last] => last.clone(),
+        [first, rest @ ..] => {
+            let rest = nest(rest);
+            quote!((#first, #rest))
+        }
+    }
+}
+
+fn derive_struct(input: &DeriveInput, fields: &Fields) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    if fields.is_empty() {
+        return Err(Error::new_spanned(name, "Key cannot be derived for a struct without fields"));
+    }
+
+    let types: Vec<TokenStream2> = fields.iter().map(|field| {
+        let ty = &field.ty;
+        quote!(#ty)
+    }).collect();
+    let bindings: Vec<Ident> = (0..fields.len())
+        .map(|index| format_ident!("field{}", index))
+        .collect();
+    let accessors: Vec<TokenStream2> = fields.iter().enumerate().map(|(index, field)| {
+        match field.ident {
+            Some(ref ident) => quote!(self.#ident),
+            None => {
+                let index = syn::Index::from(index);
+                quote!(self.#index)
+            }
+        }
+    }).collect();
+    let construct = match *fields {
+        Fields::Named(_) => {
+            let idents = fields.iter().map(|field| field.ident.as_ref().unwrap());
+            quote!(#name { #(#idents: #bindings),* })
+        }
+        _ => quote!(#name(#(#bindings),*)),
+    };
+
+    let inner = nest(&types);
+    let values = nest(&accessors);
+    let pattern = nest(&bindings.iter().map(|binding| quote!(#binding)).collect::<Vec<_>>());
+
+    Ok(quote! {
+        impl #impl_generics ::rudy::Key for #name #ty_generics #where_clause {
+            type Bytes = <#inner as ::rudy::Key>::Bytes;
+            const LEN: usize = <#inner as ::rudy::Key>::LEN;
+
+            fn into_bytes(self) -> Self::Bytes {
+                ::rudy::Key::into_bytes(#values)
+            }
+
+            fn from_bytes(bytes: Self::Bytes) -> Self {
+                let #pattern = <#inner as ::rudy::Key>::from_bytes(bytes);
+                #construct
+            }
+
+            fn round_up(bytes: &mut [u8]) -> bool {
+                <#inner as ::rudy::Key>::round_up(bytes)
+            }
+
+            fn round_down(bytes: &mut [u8]) -> bool {
+                <#inner as ::rudy::Key>::round_down(bytes)
+            }
+        }
+    })
+}
+
+/// The integer type named by the enum's `#[repr]`.
+fn repr_type(input: &DeriveInput) -> Result<Type, Error> {
+    let mut repr = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            const INTEGERS: &[&str] = &["u8", "u16", "u32", "u64", "u128", "usize",
+                                        "i8", "i16", "i32", "i64", "i128", "isize"];
+            if let Some(ident) = meta.path.get_ident() {
+                if INTEGERS.iter().any(|integer| ident == integer) {
+                    repr = Some(ident.clone());
+                }
+            }
+            Ok(())
+        })?;
+    }
+    match repr {
+        Some(ident) => Ok(syn::parse_quote!(#ident)),
+        None => Err(Error::new_spanned(
+            &input.ident,
+            "Key can only be derived for enums with an integer #[repr], like #[repr(u8)]")),
+    }
+}
+
+fn derive_enum(input: &DeriveInput, data: &syn::DataEnum) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    if data.variants.is_empty() {
+        return Err(Error::new_spanned(name, "Key cannot be derived for an enum without variants"));
+    }
+    for variant in data.variants.iter() {
+        if !variant.fields.is_empty() {
+            return Err(Error::new_spanned(variant, "Key can only be derived for fieldless enums"));
+        }
+    }
+    let repr = repr_type(input)?;
+    let variants: Vec<&Ident> = data.variants.iter().map(|variant| &variant.ident).collect();
+
+    Ok(quote! {
+        impl #impl_generics ::rudy::Key for #name #ty_generics #where_clause {
+            type Bytes = <#repr as ::rudy::Key>::Bytes;
+
+            fn into_bytes(self) -> Self::Bytes {
+                ::rudy::Key::into_bytes(self as #repr)
+            }
+
+            fn from_bytes(bytes: Self::Bytes) -> Self {
+                let discriminant = <#repr as ::rudy::Key>::from_bytes(bytes);
+                #(
+                    if discriminant == #name::#variants as #repr {
+                        return #name::#variants;
+                    }
+                )*
+                panic!("bytes are not a {}", stringify!(#name))
+            }
+
+            // Discriminants can leave gaps, so round to the nearest one
+            fn round_up(bytes: &mut [u8]) -> bool {
+                let mut discriminants = [#(#name::#variants as #repr),*];
+                discriminants.sort();
+                let found = discriminants.iter()
+                    .map(|&discriminant| ::rudy::Key::into_bytes(discriminant))
+                    .find(|found| found.as_ref() >= &*bytes);
+                match found {
+                    Some(found) => {
+                        bytes.copy_from_slice(found.as_ref());
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            fn round_down(bytes: &mut [u8]) -> bool {
+                let mut discriminants = [#(#name::#variants as #repr),*];
+                discriminants.sort();
+                let found = discriminants.iter()
+                    .rev()
+                    .map(|&discriminant| ::rudy::Key::into_bytes(discriminant))
+                    .find(|found| found.as_ref() <= &*bytes);
+                match found {
+                    Some(found) => {
+                        bytes.copy_from_slice(found.as_ref());
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    })
+}
@@ -1,27 +1,100 @@
 use std::fmt::Debug;
+use std::mem;
+use std::ops::Bound;
+use util::{increment, decrement};
 
+/// A type that can be stored as a key, by way of the big-endian bytes that
+/// order it.
+///
+/// `Bytes` must be mutable and `Clone` so that iterators and searches can
+/// rebuild a key from the bytes along its path and step them to its
+/// neighbours. Released versions only asked for `AsRef<[u8]>`, so
+/// implementations outside this crate may need a new `Bytes` type; every
+/// `[u8; N]` already qualifies.
 pub trait Key: Copy + PartialEq + Ord + Default + Debug {
-    type Bytes: AsRef<[u8]>;
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Clone;
+
+    /// The number of key bytes. The default is right when `Bytes` is a byte
+    /// array, and any other `Bytes` type needs to set it. Tuple keys add up
+    /// the lengths of their components, so that a tuple too long to lay out
+    /// fails to compile rather than panicking:
+    ///
+    /// ```compile_fail
+    /// use rudy::Key;
+    /// ([0u8; 32], [0u8; 32], [0u8; 32]).into_bytes();
+    /// ```
+    const LEN: usize = mem::size_of::<Self::Bytes>();
+
     fn into_bytes(self) -> Self::Bytes;
     fn from_bytes(bytes: Self::Bytes) -> Self;
+
+    /// Move `bytes` up to the bytes of the smallest key at or above them,
+    /// returning `false` if there is no such key. Searches for absent keys
+    /// can land on byte strings that no key encodes to, like a `bool` of 2,
+    /// so types that leave gaps must override this.
+    fn round_up(bytes: &mut [u8]) -> bool {
+        true
+    }
+
+    /// Move `bytes` down to the bytes of the largest key at or below them,
+    /// returning `false` if there is no such key.
+    fn round_down(bytes: &mut [u8]) -> bool {
+        true
+    }
+}
+
+/// The bytes of the smallest key inside the lower bound `bound`, or `None` if
+/// the bound excludes every key.
+pub fn lower_bytes<K: Key>(bound: Bound<K>) -> Option<K::Bytes> {
+    match bound {
+        Bound::Included(key) => Some(key.into_bytes()),
+        Bound::Excluded(key) => {
+            let mut bytes = key.into_bytes();
+            if increment(bytes.as_mut()) {
+                Some(bytes)
+            } else {
+                None
+            }
+        },
+        Bound::Unbounded => {
+            let mut bytes = K::default().into_bytes();
+            bytes.as_mut().fill(0);
+            Some(bytes)
+        }
+    }
 }
 
+/// The bytes of the largest key inside the upper bound `bound`, or `None` if
+/// the bound excludes every key.
+pub fn upper_bytes<K: Key>(bound: Bound<K>) -> Option<K::Bytes> {
+    match bound {
+        Bound::Included(key) => Some(key.into_bytes()),
+        Bound::Excluded(key) => {
+            let mut bytes = key.into_bytes();
+            if decrement(bytes.as_mut()) {
+                Some(bytes)
+            } else {
+                None
+            }
+        },
+        Bound::Unbounded => {
+            let mut bytes = K::default().into_bytes();
+            bytes.as_mut().fill(0xff);
+            Some(bytes)
+        }
+    }
+}
 
 macro_rules! impl_key {
     ($type:ident, $size:expr) => {
         impl Key for $type {
             type Bytes = [u8; $size];
             fn into_bytes(self) -> Self::Bytes {
-                unsafe {
-                    ::std::mem::transmute(self.to_be())
-                }
+                self.to_be_bytes()
             }
 
             fn from_bytes(bytes: Self::Bytes) -> Self {
-                let integer = unsafe {
-                    ::std::mem::transmute(bytes)
-                };
-                $type::from_be(integer)
+                $type::from_be_bytes(bytes)
             }
         }
     }
@@ -37,3 +110,338 @@ impl_key!(u128, 16);
 impl_key!(usize, 4);
 #[cfg(target_pointer_width = "64")]
 impl_key!(usize, 8);
+
+// Signed keys are stored with the sign bit flipped, so that negative numbers
+// come first in byte order and the trie sorts them numerically.
+macro_rules! impl_signed_key {
+    ($type:ident, $unsigned:ident, $size:expr) => {
+        impl Key for $type {
+            type Bytes = [u8; $size];
+            fn into_bytes(self) -> Self::Bytes {
+                (self as $unsigned ^ !($unsigned::MAX >> 1)).to_be_bytes()
+            }
+
+            fn from_bytes(bytes: Self::Bytes) -> Self {
+                ($unsigned::from_be_bytes(bytes) ^ !($unsigned::MAX >> 1)) as $type
+            }
+        }
+    }
+}
+
+impl_signed_key!(i8, u8, 1);
+impl_signed_key!(i16, u16, 2);
+impl_signed_key!(i32, u32, 4);
+impl_signed_key!(i64, u64, 8);
+#[cfg(feature = "i128")]
+impl_signed_key!(i128, u128, 16);
+#[cfg(target_pointer_width = "32")]
+impl_signed_key!(isize, usize, 4);
+#[cfg(target_pointer_width = "64")]
+impl_signed_key!(isize, usize, 8);
+
+impl<const N: usize> Key for [u8; N] where [u8; N]: Default {
+    type Bytes = [u8; N];
+    fn into_bytes(self) -> Self::Bytes {
+        self
+    }
+
+    fn from_bytes(bytes: Self::Bytes) -> Self {
+        bytes
+    }
+}
+
+impl Key for bool {
+    type Bytes = [u8; 1];
+    fn into_bytes(self) -> Self::Bytes {
+        [self as u8]
+    }
+
+    fn from_bytes(bytes: Self::Bytes) -> Self {
+        bytes[0] != 0
+    }
+
+    fn round_up(bytes: &mut [u8]) -> bool {
+        bytes[0] <= 1
+    }
+
+    fn round_down(bytes: &mut [u8]) -> bool {
+        bytes[0] = bytes[0].min(1);
+        true
+    }
+}
+
+/// The surrogates, which are not `char`s.
+const SURROGATES: ::std::ops::Range<u32> = 0xd800..0xe000;
+
+// Every `char` fits in three bytes. The surrogates and everything above
+// `char::MAX` are gaps that searches round over.
+impl Key for char {
+    type Bytes = [u8; 3];
+    fn into_bytes(self) -> Self::Bytes {
+        let bytes = (self as u32).to_be_bytes();
+        [bytes[1], bytes[2], bytes[3]]
+    }
+
+    fn from_bytes(bytes: Self::Bytes) -> Self {
+        let code = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        char::from_u32(code).expect("bytes are not a char")
+    }
+
+    fn round_up(bytes: &mut [u8]) -> bool {
+        let code = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        if code > char::MAX as u32 {
+            return false;
+        }
+        if SURROGATES.contains(&code) {
+            bytes.copy_from_slice(&SURROGATES.end.to_be_bytes()[1..]);
+        }
+        true
+    }
+
+    fn round_down(bytes: &mut [u8]) -> bool {
+        let code = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        if code > char::MAX as u32 {
+            bytes.copy_from_slice(&(char::MAX as u32).to_be_bytes()[1..]);
+        } else if SURROGATES.contains(&code) {
+            bytes.copy_from_slice(&(SURROGATES.start - 1).to_be_bytes()[1..]);
+        }
+        true
+    }
+}
+
+/// The longest a tuple key may be once its components are laid end to end.
+/// Longer tuples are rejected when `into_bytes` is compiled for them, or when
+/// it runs if a component's `LEN` understates its bytes.
+const MAX_TUPLE_BYTES: usize = 64;
+
+/// The bytes of a tuple key: the bytes of each component one after another,
+/// so tuples sort by their first component, then their second and so on.
+#[derive(Clone, Copy)]
+pub struct TupleBytes {
+    len: usize,
+    bytes: [u8; MAX_TUPLE_BYTES]
+}
+
+impl TupleBytes {
+    fn new() -> TupleBytes {
+        TupleBytes {
+            len: 0,
+            bytes: [0; MAX_TUPLE_BYTES]
+        }
+    }
+
+    fn push(&mut self, part: &[u8]) {
+        assert!(part.len() <= MAX_TUPLE_BYTES - self.len,
+                "tuple keys are limited to 64 bytes");
+        self.bytes[self.len..self.len + part.len()].copy_from_slice(part);
+        self.len += part.len();
+    }
+}
+
+impl AsRef<[u8]> for TupleBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl AsMut<[u8]> for TupleBytes {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+}
+
+/// Decode the component of type `K` starting at `*offset`, moving the offset
+/// past it.
+fn take_part<K: Key>(bytes: &[u8], offset: &mut usize) -> K {
+    let mut part = K::default().into_bytes();
+    let len = part.as_ref().len();
+    part.as_mut().copy_from_slice(&bytes[*offset..*offset + len]);
+    *offset += len;
+    K::from_bytes(part)
+}
+
+/// How to round one component of a tuple key.
+struct Part {
+    len: usize,
+    round_up: fn(&mut [u8]) -> bool,
+    round_down: fn(&mut [u8]) -> bool
+}
+
+impl Part {
+    fn of<K: Key>() -> Part {
+        Part {
+            len: K::default().into_bytes().as_ref().len(),
+            round_up: K::round_up,
+            round_down: K::round_down
+        }
+    }
+}
+
+/// Round the bytes of a tuple key up or down one component at a time. When a
+/// component has to move, the ones after it restart from their smallest (or
+/// largest) key; when a later component has nowhere to go, the earlier one
+/// steps past its current key instead.
+fn round_parts(bytes: &mut [u8], parts: &[Part], up: bool) -> bool {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return true
+    };
+    let (round, fill): (fn(&mut [u8]) -> bool, u8) = if up {
+        (part.round_up, 0)
+    } else {
+        (part.round_down, 0xff)
+    };
+    let (head, tail) = bytes.split_at_mut(part.len);
+    let mut original = [0; MAX_TUPLE_BYTES];
+    original[..head.len()].copy_from_slice(head);
+    if !round(head) {
+        return false;
+    }
+    if *head != original[..head.len()] {
+        tail.fill(fill);
+        return round_parts(tail, rest, up);
+    }
+    if round_parts(tail, rest, up) {
+        return true;
+    }
+    let stepped = if up { increment(head) } else { decrement(head) };
+    if !stepped || !round(head) {
+        return false;
+    }
+    tail.fill(fill);
+    round_parts(tail, rest, up)
+}
+
+macro_rules! impl_tuple_key {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Key),+> Key for ($($name,)+) {
+            type Bytes = TupleBytes;
+            const LEN: usize = $($name::LEN +)+ 0;
+
+            fn into_bytes(self) -> Self::Bytes {
+                const {
+                    assert!(Self::LEN <= MAX_TUPLE_BYTES, "tuple keys are limited to 64 bytes");
+                }
+                let mut bytes = TupleBytes::new();
+                $(bytes.push(self.$index.into_bytes().as_ref());)+
+                bytes
+            }
+
+            fn from_bytes(bytes: Self::Bytes) -> Self {
+                let mut offset = 0;
+                ($(take_part::<$name>(bytes.as_ref(), &mut offset),)+)
+            }
+
+            fn round_up(bytes: &mut [u8]) -> bool {
+                round_parts(bytes, &[$(Part::of::<$name>()),+], true)
+            }
+
+            fn round_down(bytes: &mut [u8]) -> bool {
+                round_parts(bytes, &[$(Part::of::<$name>()),+], false)
+            }
+        }
+    }
+}
+
+impl_tuple_key!(A 0, B 1);
+impl_tuple_key!(A 0, B 1, C 2);
+impl_tuple_key!(A 0, B 1, C 2, D 3);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_up<K: Key>(key: K) -> Option<K> {
+        let mut bytes = key.into_bytes();
+        if K::round_up(bytes.as_mut()) {
+            Some(K::from_bytes(bytes))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let keys = [(0u16, 5u64), (0, u64::MAX), (1, 0), (u16::MAX, 0)];
+        for pair in keys.windows(2) {
+            assert!(pair[0].into_bytes().as_ref() < pair[1].into_bytes().as_ref());
+        }
+        for &key in keys.iter() {
+            assert_eq!(<(u16, u64)>::from_bytes(key.into_bytes()), key);
+        }
+        assert_eq!((1u8, 2u8, 3u8, 4u8).into_bytes().as_ref(), &[1, 2, 3, 4]);
+        assert_eq!(<(u16, u64)>::LEN, 10);
+        assert_eq!(<(char, (bool, u32))>::LEN, 8);
+        assert_eq!('\u{10ffff}'.into_bytes(), [0x10, 0xff, 0xff]);
+        assert_eq!(<[u8; 3]>::from_bytes(*b"abc"), *b"abc");
+    }
+
+    /// A key whose `Bytes` is not an array and which leaves `LEN` at its
+    /// default, the size of a `Vec`.
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+    struct Wide;
+
+    impl Key for Wide {
+        type Bytes = Vec<u8>;
+
+        fn into_bytes(self) -> Self::Bytes {
+            vec![0; 40]
+        }
+
+        fn from_bytes(bytes: Self::Bytes) -> Self {
+            Wide
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "tuple keys are limited to 64 bytes")]
+    fn test_tuple_limit_at_runtime() {
+        (Wide, Wide).into_bytes();
+    }
+
+    #[test]
+    fn test_round_gaps() {
+        let mut bytes = [2];
+        assert!(!bool::round_up(&mut bytes));
+        assert!(bool::round_down(&mut bytes));
+        assert_eq!(bytes, [1]);
+
+        let mut bytes = [0x00, 0xd9, 0x00];
+        assert!(char::round_up(&mut bytes));
+        assert_eq!(char::from_bytes(bytes), '\u{e000}');
+        let mut bytes = [0x00, 0xd9, 0x00];
+        assert!(char::round_down(&mut bytes));
+        assert_eq!(char::from_bytes(bytes), '\u{d7ff}');
+        let mut bytes = [0x11, 0x00, 0x00];
+        assert!(!char::round_up(&mut bytes));
+        assert!(char::round_down(&mut bytes));
+        assert_eq!(char::from_bytes(bytes), char::MAX);
+        assert_eq!(round_up('a'), Some('a'));
+    }
+
+    #[test]
+    fn test_round_tuples() {
+        type Pair = (bool, bool);
+        // a gap in the second component carries into the first
+        let mut bytes = Pair::default().into_bytes();
+        bytes.as_mut().copy_from_slice(&[0, 2]);
+        assert!(Pair::round_up(bytes.as_mut()));
+        assert_eq!(Pair::from_bytes(bytes), (true, false));
+        bytes.as_mut().copy_from_slice(&[1, 2]);
+        assert!(!Pair::round_up(bytes.as_mut()));
+
+        // a gap in the first component resets the second
+        bytes.as_mut().copy_from_slice(&[5, 0]);
+        assert!(Pair::round_down(bytes.as_mut()));
+        assert_eq!(Pair::from_bytes(bytes), (true, true));
+        bytes.as_mut().copy_from_slice(&[1, 7]);
+        assert!(Pair::round_down(bytes.as_mut()));
+        assert_eq!(Pair::from_bytes(bytes), (true, true));
+
+        let mut bytes = (0u8, 'a').into_bytes();
+        bytes.as_mut().copy_from_slice(&[3, 0x11, 0, 0]);
+        assert!(<(u8, char)>::round_up(bytes.as_mut()));
+        assert_eq!(<(u8, char)>::from_bytes(bytes), (4, '\0'));
+        assert_eq!(round_up((7u32, 'x')), Some((7, 'x')));
+    }
+}
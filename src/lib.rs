@@ -2,15 +2,22 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-extern crate nodrop;
 extern crate num_traits;
+#[cfg(feature = "derive")]
+extern crate rudy_derive;
 
 mod util;
 mod key;
 
 pub mod rudymap;
 pub mod rudyset;
+pub mod rudystrmap;
+pub mod rudyhashmap;
 
 pub use key::Key;
+#[cfg(feature = "derive")]
+pub use rudy_derive::Key;
 pub use rudyset::RudySet;
 pub use rudymap::RudyMap;
+pub use rudystrmap::RudyStrMap;
+pub use rudyhashmap::RudyHashMap;
@@ -0,0 +1,328 @@
+//! A map from byte strings of any length, after JudyHS.
+//!
+//! Keys are bucketed first by their length and then by the top 32 bits of
+//! their hash, each level being a `RudyMap`. Keys that land in the same
+//! bucket are told apart by comparing them in full. Iteration visits
+//! shorter keys first, but is otherwise in no useful order; `RudyStrMap`
+//! keeps keys sorted.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::mem;
+use std::slice;
+use rudymap::{self, RudyMap};
+
+type Entry<V> = (Box<[u8]>, V);
+
+/// The keys sharing a length and hash prefix. Nearly every bucket holds a
+/// single key, so that case is kept out of a `Vec`.
+enum Bucket<V> {
+    One(Entry<V>),
+    Many(Vec<Entry<V>>)
+}
+
+impl<V> Bucket<V> {
+    fn entries(&self) -> &[Entry<V>] {
+        match *self {
+            Bucket::One(ref entry) => slice::from_ref(entry),
+            Bucket::Many(ref entries) => entries
+        }
+    }
+
+    fn entries_mut(&mut self) -> &mut [Entry<V>] {
+        match *self {
+            Bucket::One(ref mut entry) => slice::from_mut(entry),
+            Bucket::Many(ref mut entries) => entries
+        }
+    }
+
+    fn push(&mut self, entry: Entry<V>) {
+        let entries = match mem::replace(self, Bucket::Many(Vec::new())) {
+            Bucket::One(first) => vec![first, entry],
+            Bucket::Many(mut entries) => {
+                entries.push(entry);
+                entries
+            }
+        };
+        *self = Bucket::Many(entries);
+    }
+}
+
+fn hash_prefix(key: &[u8]) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(key);
+    (hasher.finish() >> 32) as u32
+}
+
+pub struct RudyHashMap<V> {
+    lengths: RudyMap<usize, RudyMap<u32, Bucket<V>>>,
+    len: usize
+}
+
+impl<V> RudyHashMap<V> {
+    pub fn new() -> RudyHashMap<V> {
+        RudyHashMap {
+            lengths: RudyMap::new(),
+            len: 0
+        }
+    }
+
+    /// Insert a value, returning the one it replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudyhashmap::RudyHashMap;
+    ///
+    /// let mut map = RudyHashMap::new();
+    /// assert_eq!(map.insert(b"blob", 1), None);
+    /// assert_eq!(map.insert("blob", 2), Some(1));
+    /// assert_eq!(map.get(b"blob"), Some(&2));
+    /// ```
+    pub fn insert<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q, value: V) -> Option<V> {
+        let key = key.as_ref();
+        let hash = hash_prefix(key);
+        let hashes = self.lengths.entry(key.len()).or_default();
+        match hashes.get_mut(hash) {
+            Some(bucket) => {
+                if let Some(entry) = bucket.entries_mut().iter_mut().find(|entry| *entry.0 == *key) {
+                    return Some(mem::replace(&mut entry.1, value));
+                }
+                bucket.push((key.into(), value));
+            },
+            None => {
+                hashes.insert(hash, Bucket::One((key.into(), value)));
+            }
+        }
+        self.len += 1;
+        None
+    }
+
+    /// Remove a key, returning its value.
+    pub fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Option<V> {
+        let key = key.as_ref();
+        let hash = hash_prefix(key);
+        let hashes = self.lengths.get_mut(key.len())?;
+        let value = {
+            let bucket = hashes.get_mut(hash)?;
+            let index = bucket.entries().iter().position(|entry| *entry.0 == *key)?;
+            match *bucket {
+                Bucket::Many(ref mut entries) if entries.len() > 1 => {
+                    let (_, value) = entries.swap_remove(index);
+                    if entries.len() == 1 {
+                        let last = entries.pop().unwrap();
+                        *bucket = Bucket::One(last);
+                    }
+                    Some(value)
+                },
+                _ => None
+            }
+        };
+        let value = match value {
+            Some(value) => value,
+            // The key was alone in its bucket
+            None => match hashes.remove(hash).unwrap() {
+                Bucket::One((_, value)) => value,
+                Bucket::Many(mut entries) => entries.pop().unwrap().1
+            }
+        };
+        if hashes.is_empty() {
+            self.lengths.remove(key.len());
+        }
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Option<&V> {
+        let key = key.as_ref();
+        self.lengths.get(key.len())?
+            .get(hash_prefix(key))?
+            .entries()
+            .iter()
+            .find(|entry| *entry.0 == *key)
+            .map(|entry| &entry.1)
+    }
+
+    pub fn get_mut<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Option<&mut V> {
+        let key = key.as_ref();
+        self.lengths.get_mut(key.len())?
+            .get_mut(hash_prefix(key))?
+            .entries_mut()
+            .iter_mut()
+            .find(|entry| *entry.0 == *key)
+            .map(|entry| &mut entry.1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the entries, shorter keys first and otherwise in hash
+    /// order.
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            lengths: self.lengths.iter(),
+            hashes: None,
+            entries: [].iter(),
+            remaining: self.len
+        }
+    }
+
+    /// The number of bytes used by the map, including the stored keys.
+    pub fn memory_usage(&self) -> usize {
+        let mut bytes = mem::size_of::<Self>() + self.lengths.memory_usage();
+        for (_, hashes) in self.lengths.iter() {
+            bytes += hashes.memory_usage();
+            for (_, bucket) in hashes.iter() {
+                if let Bucket::Many(ref entries) = *bucket {
+                    bytes += entries.capacity() * mem::size_of::<Entry<V>>();
+                }
+                for entry in bucket.entries() {
+                    bytes += entry.0.len();
+                }
+            }
+        }
+        bytes
+    }
+}
+
+impl<V> Default for RudyHashMap<V> {
+    fn default() -> RudyHashMap<V> {
+        RudyHashMap::new()
+    }
+}
+
+impl<'a, V: 'a> IntoIterator for &'a RudyHashMap<V> {
+    type Item = (&'a [u8], &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, V: 'a> {
+    lengths: rudymap::Iter<'a, usize, RudyMap<u32, Bucket<V>>>,
+    hashes: Option<rudymap::Iter<'a, u32, Bucket<V>>>,
+    entries: slice::Iter<'a, Entry<V>>,
+    remaining: usize
+}
+
+impl<'a, V: 'a> Iterator for Iter<'a, V> {
+    type Item = (&'a [u8], &'a V);
+
+    fn next(&mut self) -> Option<(&'a [u8], &'a V)> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                self.remaining -= 1;
+                return Some((&entry.0, &entry.1));
+            }
+            let bucket = match self.hashes.as_mut().and_then(|hashes| hashes.next()) {
+                Some((_, bucket)) => bucket,
+                None => {
+                    let (_, hashes) = self.lengths.next()?;
+                    self.hashes = Some(hashes.iter());
+                    continue;
+                }
+            };
+            self.entries = bucket.entries().iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, V: 'a> ExactSizeIterator for Iter<'a, V> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn blob(seed: usize) -> Vec<u8> {
+        (0..seed % 40).map(|byte| (seed * 31 + byte * 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = RudyHashMap::new();
+        let mut expected = HashMap::new();
+        for seed in 0..2000 {
+            let key = blob(seed);
+            assert_eq!(map.insert(&key, seed), expected.insert(key, seed));
+        }
+        assert_eq!(map.len(), expected.len());
+        for (key, value) in expected.iter() {
+            assert_eq!(map.get(key), Some(value));
+        }
+        assert_eq!(map.get(b"not a blob"), None);
+        *map.get_mut(&blob(5)).unwrap() += 1;
+        *expected.get_mut(&blob(5)).unwrap() += 1;
+        assert_eq!(map.get(&blob(5)), expected.get(&blob(5)));
+
+        let mut found: Vec<(Vec<u8>, usize)> = map.iter().map(|(k, &v)| (k.to_vec(), v)).collect();
+        let mut wanted: Vec<(Vec<u8>, usize)> = expected.clone().into_iter().collect();
+        found.sort();
+        wanted.sort();
+        assert_eq!(found, wanted);
+        let lengths: Vec<usize> = map.iter().map(|(k, _)| k.len()).collect();
+        assert!(lengths.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(map.iter().len(), map.len());
+
+        for seed in (0..2000).step_by(3) {
+            assert_eq!(map.remove(&blob(seed)), expected.remove(&blob(seed)));
+        }
+        assert_eq!(map.len(), expected.len());
+        for seed in 0..2000 {
+            assert_eq!(map.contains_key(&blob(seed)), expected.contains_key(&blob(seed)));
+            assert_eq!(map.remove(&blob(seed)), expected.remove(&blob(seed)));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+    }
+
+    /// Two keys of the same length whose hash prefixes collide, found by
+    /// the birthday bound after some tens of thousands of tries.
+    fn colliding_pair() -> (Vec<u8>, Vec<u8>) {
+        let mut seen = HashMap::new();
+        for candidate in 0u32.. {
+            let key = candidate.to_be_bytes().to_vec();
+            if let Some(other) = seen.insert(hash_prefix(&key), key.clone()) {
+                return (other, key);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_collisions() {
+        let (first, second) = colliding_pair();
+        let mut map = RudyHashMap::new();
+        map.insert(&first, 1);
+        assert_eq!(map.get(&second), None);
+        map.insert(&second, 2);
+        map.insert(&[0xff; 4], 3);
+        assert_eq!(map.get(&first), Some(&1));
+        assert_eq!(map.get(&second), Some(&2));
+        assert_eq!(map.insert(&second, 20), Some(2));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.iter().count(), 3);
+
+        assert_eq!(map.remove(&first), Some(1));
+        assert_eq!(map.get(&first), None);
+        assert_eq!(map.get(&second), Some(&20));
+        assert_eq!(map.remove(&second), Some(20));
+        assert_eq!(map.remove(&second), None);
+        assert_eq!(map.len(), 1);
+    }
+}
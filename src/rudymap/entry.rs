@@ -0,0 +1,130 @@
+use super::rootptr::RootPtr;
+use ::Key;
+use std::marker::PhantomData;
+
+/// A view into a single entry of a `RudyMap`, which may be vacant or
+/// occupied. Created by `RudyMap::entry`.
+pub enum Entry<'a, K: Key + 'a, V: 'a> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>)
+}
+
+/// An entry whose key is present in the map.
+///
+/// The value was found while creating the entry, so reading or updating it
+/// does not walk the trie again.
+pub struct OccupiedEntry<'a, K: Key + 'a, V: 'a> {
+    // Both pointers come from the same borrow of the map. Holding the root
+    // as a reference would let moving the entry invalidate `value`.
+    root: *mut RootPtr<K, V>,
+    key: K,
+    value: *mut V,
+    marker: PhantomData<&'a mut RootPtr<K, V>>
+}
+
+/// An entry whose key is absent from the map.
+pub struct VacantEntry<'a, K: Key + 'a, V: 'a> {
+    root: &'a mut RootPtr<K, V>,
+    key: K
+}
+
+impl<'a, K: Key + 'a, V: 'a> Entry<'a, K, V> {
+    pub fn new(root: &'a mut RootPtr<K, V>, key: K) -> Entry<'a, K, V> {
+        let root: *mut RootPtr<K, V> = root;
+        let value = unsafe { (*root).get_mut(key) }.map(|value| value as *mut V);
+        match value {
+            Some(value) => Entry::Occupied(OccupiedEntry {
+                root,
+                key,
+                value,
+                marker: PhantomData
+            }),
+            None => Entry::Vacant(VacantEntry { root: unsafe { &mut *root }, key })
+        }
+    }
+
+    pub fn key(&self) -> K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key()
+        }
+    }
+
+    /// Insert `default` if the entry is vacant, then return a mutable
+    /// reference to the value.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default)
+        }
+    }
+
+    /// Insert the result of `default` if the entry is vacant, then return a
+    /// mutable reference to the value. `default` is only called when needed.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default())
+        }
+    }
+
+    /// Update the value in place if the entry is occupied.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Entry<'a, K, V> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            },
+            Entry::Vacant(entry) => Entry::Vacant(entry)
+        }
+    }
+}
+
+impl<'a, K: Key + 'a, V: Default + 'a> Entry<'a, K, V> {
+    /// Insert `V::default()` if the entry is vacant, then return a mutable
+    /// reference to the value.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    pub fn get(&self) -> &V {
+        // The pointer came from `get_mut` on the root we are still borrowing
+        // mutably, and nothing has touched the map since.
+        unsafe { &*self.value }
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        unsafe { &mut *self.value }
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        unsafe { &mut *self.value }
+    }
+
+    /// Replace the value, returning the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        ::std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove the entry from the map, returning its value.
+    pub fn remove(self) -> V {
+        unsafe { (*self.root).remove(self.key) }.unwrap()
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> K {
+        self.key
+    }
+
+    /// Insert `value` and return a mutable reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.root.insert_entry(self.key, value)
+    }
+}
@@ -1,14 +1,257 @@
-enum IterState {
-    
+use std::iter::Zip;
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::slice;
+use super::rootptr::{RootPtr, RootRef, RootMut, RootOwned};
+use super::root_leaf::{RootLeaf, VecLeaf};
+use super::jpm;
+use super::jpm::innerptr::InnerPtr;
+use super::jpm::cursor::Cursor;
+use util::locksteparray;
+use util::below_upper;
+use util::above_lower;
+use key::{lower_bytes, upper_bytes};
+use ::Key;
+
+/// An ordered iterator over the entries of a `RudyMap`.
+///
+/// A map that has grown into a trie is walked with a stack of the nodes on
+/// the way down to each end, so every entry costs the nodes entered and left
+/// to reach it. Smaller maps keep their entries in sorted arrays, which are
+/// walked directly.
+pub struct Iter<'a, K: Key + 'a, V: 'a> {
+    inner: IterInner<'a, K, V>
 }
 
-pub struct Iter<'a, K: Key, V> {
-    stack: ArrayVec<K::IterStack<'a>>
+enum IterInner<'a, K: Key + 'a, V: 'a> {
+    Leaf(Zip<slice::Iter<'a, K>, slice::Iter<'a, V>>),
+    Jpm(Cursor<K, &'a InnerPtr<K, V>>)
 }
 
-impl<'a, K: Key, V> Iter {
-    pub fn new(root: RootPtr) -> Iter {
-        let mut stack = ArrayVec::new();
-        stack.insert()
+impl<'a, K: Key + 'a, V: 'a> Iter<'a, K, V> {
+    pub fn new(root: &'a RootPtr<K, V>) -> Iter<'a, K, V> {
+        Iter::range(root, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn range(root: &'a RootPtr<K, V>, front: Bound<K>, back: Bound<K>)
+                 -> Iter<'a, K, V> {
+        let inner = match root.as_ref() {
+            RootRef::Jpm(jpm) => {
+                let (front, back, remaining) = jpm_bounds(jpm, front, back);
+                IterInner::Jpm(Cursor::new(jpm.head(), front, back, remaining))
+            },
+            leaf => {
+                let (keys, values) = match leaf {
+                    RootRef::Empty(_) => (&[][..], &[][..]),
+                    RootRef::Leaf1(leaf) => leaf.entries(),
+                    RootRef::Leaf2(leaf) => leaf.entries(),
+                    RootRef::VecLeaf(leaf) => leaf.entries(),
+                    RootRef::Jpm(_) => unreachable!()
+                };
+                let (start, end) = leaf_bounds(keys, front, back);
+                IterInner::Leaf(keys[start..end].iter().zip(&values[start..end]))
+            }
+        };
+        Iter { inner }
+    }
+
+    pub fn empty() -> Iter<'a, K, V> {
+        Iter {
+            inner: IterInner::Leaf([].iter().zip(&[]))
+        }
     }
 }
+
+/// The key bytes to seek from either end of a trie and the number of
+/// entries in between.
+fn jpm_bounds<K: Key, V>(jpm: &jpm::Jpm<K, V>, front: Bound<K>, back: Bound<K>)
+                         -> (Option<K::Bytes>, Option<K::Bytes>, usize) {
+    match (front, back) {
+        (Bound::Unbounded, Bound::Unbounded) => (None, None, jpm.len()),
+        _ => (lower_bytes(front), upper_bytes(back), jpm.count(front, back))
+    }
+}
+
+/// The positions in a sorted array of keys that a range starts and ends
+/// at.
+fn leaf_bounds<K: Key>(keys: &[K], front: Bound<K>, back: Bound<K>) -> (usize, usize) {
+    let start = keys.partition_point(|key| !above_lower(front.as_ref(), key));
+    let end = keys.partition_point(|key| below_upper(back.as_ref(), key));
+    (start, end.max(start))
+}
+
+impl<'a, K: Key + 'a, V: 'a> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            IterInner::Leaf(ref mut iter) => iter.next().map(|(&key, value)| (key, value)),
+            IterInner::Jpm(ref mut cursor) => cursor.next_front()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.inner {
+            IterInner::Leaf(ref iter) => iter.size_hint(),
+            IterInner::Jpm(ref cursor) => (cursor.len(), Some(cursor.len()))
+        }
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Key + 'a, V: 'a> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            IterInner::Leaf(ref mut iter) => iter.next_back().map(|(&key, value)| (key, value)),
+            IterInner::Jpm(ref mut cursor) => cursor.next_back()
+        }
+    }
+}
+
+/// An ordered iterator over the entries of a `RudyMap` with mutable
+/// references to the values. It walks the map like `Iter`, and reaches each
+/// node only once, so the references it hands out never overlap.
+pub struct IterMut<'a, K: Key + 'a, V: 'a> {
+    inner: IterMutInner<'a, K, V>
+}
+
+enum IterMutInner<'a, K: Key + 'a, V: 'a> {
+    Leaf(Zip<slice::Iter<'a, K>, slice::IterMut<'a, V>>),
+    Jpm(Cursor<K, &'a mut InnerPtr<K, V>>)
+}
+
+impl<'a, K: Key + 'a, V: 'a> IterMut<'a, K, V> {
+    pub fn new(root: &'a mut RootPtr<K, V>) -> IterMut<'a, K, V> {
+        IterMut::range(root, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn range(root: &'a mut RootPtr<K, V>, front: Bound<K>, back: Bound<K>)
+                 -> IterMut<'a, K, V> {
+        let inner = match root.as_mut() {
+            RootMut::Jpm(jpm) => {
+                let (front, back, remaining) = jpm_bounds(jpm, front, back);
+                IterMutInner::Jpm(Cursor::new(jpm.head_mut(), front, back, remaining))
+            },
+            leaf => {
+                let (keys, values) = match leaf {
+                    RootMut::Empty(_) => (&[][..], &mut [][..]),
+                    RootMut::Leaf1(leaf) => leaf.entries_mut(),
+                    RootMut::Leaf2(leaf) => leaf.entries_mut(),
+                    RootMut::VecLeaf(leaf) => leaf.entries_mut(),
+                    RootMut::Jpm(_) => unreachable!()
+                };
+                let (start, end) = leaf_bounds(keys, front, back);
+                IterMutInner::Leaf(keys[start..end].iter().zip(&mut values[start..end]))
+            }
+        };
+        IterMut { inner }
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> Iterator for IterMut<'a, K, V> {
+    type Item = (K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            IterMutInner::Leaf(ref mut iter) => iter.next().map(|(&key, value)| (key, value)),
+            IterMutInner::Jpm(ref mut cursor) => cursor.next_front()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.inner {
+            IterMutInner::Leaf(ref iter) => iter.size_hint(),
+            IterMutInner::Jpm(ref cursor) => (cursor.len(), Some(cursor.len()))
+        }
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> ExactSizeIterator for IterMut<'a, K, V> {}
+
+impl<'a, K: Key + 'a, V: 'a> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.inner {
+            IterMutInner::Leaf(ref mut iter) => {
+                iter.next_back().map(|(&key, value)| (key, value))
+            },
+            IterMutInner::Jpm(ref mut cursor) => cursor.next_back()
+        }
+    }
+}
+
+/// An owning iterator over the entries of a `RudyMap` in key order.
+pub struct IntoIter<K: Key, V> {
+    inner: IntoIterInner<K, V>,
+    remaining: usize
+}
+
+enum IntoIterInner<K: Key, V> {
+    Leaf(locksteparray::IntoIter<[K; 31], [V; 31]>),
+    Jpm(jpm::jpm_root::IntoIter<K, V>)
+}
+
+impl<K: Key, V> IntoIter<K, V> {
+    pub fn new(root: RootPtr<K, V>) -> IntoIter<K, V> {
+        let remaining = root.len();
+        let inner = match root.into_owned() {
+            RootOwned::Empty(_) => IntoIterInner::Leaf(VecLeaf::new().into_iter()),
+            RootOwned::Leaf1(leaf) => IntoIterInner::Leaf(VecLeaf::from(*leaf).into_iter()),
+            RootOwned::Leaf2(leaf) => IntoIterInner::Leaf(VecLeaf::from(*leaf).into_iter()),
+            RootOwned::VecLeaf(leaf) => IntoIterInner::Leaf(leaf.into_iter()),
+            RootOwned::Jpm(jpm) => IntoIterInner::Jpm(jpm.into_iter())
+        };
+        IntoIter { inner, remaining }
+    }
+}
+
+impl<K: Key, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        let entry = match self.inner {
+            IntoIterInner::Leaf(ref mut iter) => iter.next(),
+            IntoIterInner::Jpm(ref mut iter) => iter.next()
+        };
+        if entry.is_some() {
+            self.remaining -= 1;
+        }
+        entry
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K: Key, V> ExactSizeIterator for IntoIter<K, V> {}
+
+/// A draining iterator over the entries of a `RudyMap` in key order. The map
+/// is emptied as soon as the iterator is created.
+pub struct Drain<'a, K: Key + 'a, V: 'a> {
+    iter: IntoIter<K, V>,
+    marker: PhantomData<&'a mut RootPtr<K, V>>
+}
+
+impl<'a, K: Key + 'a, V: 'a> Drain<'a, K, V> {
+    pub fn new(root: &'a mut RootPtr<K, V>) -> Drain<'a, K, V> {
+        Drain {
+            iter: IntoIter::new(root.take()),
+            marker: PhantomData
+        }
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> Iterator for Drain<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> ExactSizeIterator for Drain<'a, K, V> {}
@@ -1,12 +1,45 @@
 use ::Key;
+use ::util::Masked;
 use super::innerptr::{InnerPtr, IntoPtr};
-use super::traits::JpmNode;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use super::traits::{JpmNode, Child, branch_first_empty, branch_last_empty, branch_select};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use super::branch_uncompressed::BranchUncompressed;
-use std::iter::FromIterator;
+use super::branch_linear::{self, BranchLinear};
+use super::narrow::Narrow;
+use std::iter::{Enumerate, FlatMap, FromIterator};
 use std::mem;
+use std::slice;
 
-struct Subexpanse<K: Key, V> {
+/// Once a removal leaves this few children, the branch shrinks back to a
+/// `BranchLinear`.
+const SHRINK_TO_LINEAR: usize = 4;
+
+/// A branch with this many children grows into a `BranchUncompressed` when
+/// another is added.
+pub const GROW_TO_UNCOMPRESSED: usize = 128;
+
+/// Put `children`, in key order, under the node that inserting into them one
+/// at a time would have grown: a lone child goes behind a `Narrow`, and more
+/// take the smallest branch that holds them.
+pub fn branch_for<K: Key, V>(mut children: Vec<(u8, InnerPtr<K, V>)>) -> InnerPtr<K, V> {
+    match children.len() {
+        0 => InnerPtr::empty(),
+        1 => {
+            let (byte, child) = children.pop().unwrap();
+            Narrow::prepend(byte, child)
+        },
+        count if count <= branch_linear::CAPACITY => {
+            children.into_iter().collect::<BranchLinear<K, V>>().into_ptr()
+        },
+        count if count <= GROW_TO_UNCOMPRESSED => {
+            children.into_iter().collect::<BranchBitmap<K, V>>().into_ptr()
+        },
+        _ => children.into_iter().collect::<BranchUncompressed<K, V>>().into_ptr()
+    }
+}
+
+#[derive(Clone)]
+pub struct Subexpanse<K: Key, V> {
     pub bitmap: u32,
     pub ptr: Option<Box<[InnerPtr<K, V>; 32]>>
 }
@@ -25,6 +58,14 @@ impl<K: Key, V> Subexpanse<K, V> {
         self.bitmap & (1 << sub_byte as u32) != 0
     }
 
+    pub fn child(&self, sub_byte: u8) -> Option<&InnerPtr<K, V>> {
+        if self.is_set(sub_byte) {
+            self.ptr.as_ref().map(|ptrs| &ptrs[sub_byte as usize])
+        } else {
+            None
+        }
+    }
+
     pub fn get(&self, sub_byte: u8, subkey: &[u8]) -> Option<&V> {
         if self.is_set(sub_byte) {
             self.ptr.as_ref()
@@ -45,7 +86,76 @@ impl<K: Key, V> Subexpanse<K, V> {
         }
     }
 
-    pub fn insert(&mut self, sub_byte: u8, subkey: &[u8], value: V) -> InsertResult<V> {
+    /// Search the children at or above `sub_byte` for the first entry,
+    /// writing the chosen child's position back into `sub_byte`.
+    pub fn first(&self, sub_byte: &mut u8, subkey: &mut [u8]) -> Option<&V> {
+        let start = *sub_byte;
+        let mut candidates = self.bitmap & (!0u32 << start);
+        while candidates != 0 {
+            let index = candidates.trailing_zeros() as u8;
+            candidates &= !(1 << index);
+            if index != start {
+                *sub_byte = index;
+                subkey.fill(0);
+            }
+            let child = &self.ptr.as_ref().unwrap()[index as usize];
+            if let Some(value) = child.first(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Search the children at or below `sub_byte` for the last entry,
+    /// writing the chosen child's position back into `sub_byte`.
+    pub fn last(&self, sub_byte: &mut u8, subkey: &mut [u8]) -> Option<&V> {
+        let start = *sub_byte;
+        let mut candidates = self.bitmap & (!0u32 >> (31 - start));
+        while candidates != 0 {
+            let index = 31 - candidates.leading_zeros() as u8;
+            candidates &= !(1 << index);
+            if index != start {
+                *sub_byte = index;
+                subkey.fill(0xff);
+            }
+            let child = &self.ptr.as_ref().unwrap()[index as usize];
+            if let Some(value) = child.last(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// A bit for each child, laid out as a 256-slot bitmap.
+    fn mask(&self) -> [u8; 256 / 8] {
+        let mut mask = [0; 256 / 8];
+        mask[..4].copy_from_slice(&self.bitmap.to_le_bytes());
+        mask
+    }
+
+    /// The children along with their key bytes, for the subexpanse at
+    /// `index` within its branch.
+    fn iter(&self, index: usize) -> Masked<slice::Iter<'_, InnerPtr<K, V>>> {
+        let ptrs = self.ptr.as_ref().map_or(&[][..], |ptrs| &ptrs[..]);
+        Masked::new(self.mask(), index as u8 * 32, ptrs.iter())
+    }
+
+    fn iter_mut(&mut self, index: usize) -> Masked<slice::IterMut<'_, InnerPtr<K, V>>> {
+        let mask = self.mask();
+        let ptrs = self.ptr.as_mut().map_or(&mut [][..], |ptrs| &mut ptrs[..]);
+        Masked::new(mask, index as u8 * 32, ptrs.iter_mut())
+    }
+
+    /// The children of this subexpanse along with their positions.
+    pub fn children(&self) -> impl Iterator<Item=(u8, &InnerPtr<K, V>)> {
+        let bitmap = self.bitmap;
+        self.ptr.iter()
+            .flat_map(|ptrs| ptrs.iter().enumerate())
+            .filter(move |&(sub_byte, _)| bitmap & (1 << sub_byte) != 0)
+            .map(|(sub_byte, child)| (sub_byte as u8, child))
+    }
+
+    pub fn insert(&mut self, sub_byte: u8, subkey: &[u8], value: V) -> Option<V> {
         if self.ptr.is_none() {
             self.ptr = Some(Default::default());
         }
@@ -53,7 +163,12 @@ impl<K: Key, V> Subexpanse<K, V> {
             .unwrap()[sub_byte as usize]
             .insert(subkey, value);
         self.bitmap |= 1 << sub_byte as u32;
-        InsertResult::Success(evicted)
+        evicted
+    }
+
+    pub fn insert_entry(&mut self, sub_byte: u8, subkey: &[u8], value: V) -> &mut V {
+        self.bitmap |= 1 << sub_byte as u32;
+        self.ptr.get_or_insert_with(Default::default)[sub_byte as usize].insert_entry(subkey, value)
     }
 
     pub fn insert_ptr(&mut self, sub_byte: u8, ptr: InnerPtr<K, V>) {
@@ -64,21 +179,36 @@ impl<K: Key, V> Subexpanse<K, V> {
         self.bitmap |= 1 << sub_byte as u32;
     }
 
-    pub fn remove(&mut self, sub_byte: u8, subkey: &[u8]) -> RemoveResult<V> {
-        if self.ptr.is_none() {
-            self.ptr = Some(Default::default());
+    /// Remove `subkey` from the child at `sub_byte`, freeing the child and
+    /// then the pointer array once they are empty.
+    pub fn remove(&mut self, sub_byte: u8, subkey: &[u8]) -> Option<V> {
+        if !self.is_set(sub_byte) {
+            return None;
         }
-        let evicted = self.ptr.as_mut()
-            .unwrap()[sub_byte as usize]
-            .remove(subkey);
-        RemoveResult::Success(evicted)
+        let evicted = {
+            let child = &mut self.ptr.as_mut().unwrap()[sub_byte as usize];
+            let evicted = child.remove(subkey);
+            if child.population() == 0 {
+                self.bitmap &= !(1 << sub_byte as u32);
+            }
+            evicted
+        };
+        if self.bitmap == 0 {
+            self.ptr = None;
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.bitmap.count_ones() as usize
     }
 
     pub fn memory_usage(&self) -> usize {
         let mut bytes = mem::size_of::<Self>();
         if let Some(ref inner_ptrs) = self.ptr {
-            for i in 0..32 as usize {
-                if (self.bitmap & (1 << i)) as u32 != 0 {
+            bytes += mem::size_of_val(&**inner_ptrs);
+            for i in 0..32 {
+                if self.bitmap & (1 << i) != 0 {
                     bytes += inner_ptrs[i].target_memory_usage();
                 }
             }
@@ -87,15 +217,160 @@ impl<K: Key, V> Subexpanse<K, V> {
     }
 }
 
+#[derive(Clone)]
 pub struct BranchBitmap<K: Key, V> {
-    subexpanses: [Subexpanse<K, V>; 8]
+    subexpanses: [Subexpanse<K, V>; 8],
+    pop: usize
 }
 
 impl<K: Key, V> BranchBitmap<K, V> {
     pub fn new() -> BranchBitmap<K, V> {
         BranchBitmap {
-            subexpanses: Default::default()
+            subexpanses: Default::default(),
+            pop: 0
+        }
+    }
+}
+
+/// The key bytes and children of a `BranchBitmap` in key order.
+pub type Iter<'a, K, V> = FlatMap<Enumerate<slice::Iter<'a, Subexpanse<K, V>>>,
+                                  Masked<slice::Iter<'a, InnerPtr<K, V>>>,
+                                  fn((usize, &'a Subexpanse<K, V>))
+                                     -> Masked<slice::Iter<'a, InnerPtr<K, V>>>>;
+pub type IterMut<'a, K, V> = FlatMap<Enumerate<slice::IterMut<'a, Subexpanse<K, V>>>,
+                                     Masked<slice::IterMut<'a, InnerPtr<K, V>>>,
+                                     fn((usize, &'a mut Subexpanse<K, V>))
+                                        -> Masked<slice::IterMut<'a, InnerPtr<K, V>>>>;
+
+impl<K: Key, V> BranchBitmap<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        fn children<K: Key, V>((index, subexpanse): (usize, &Subexpanse<K, V>))
+                               -> Masked<slice::Iter<'_, InnerPtr<K, V>>> {
+            subexpanse.iter(index)
+        }
+        self.subexpanses.iter().enumerate().flat_map(children as _)
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        fn children<K: Key, V>((index, subexpanse): (usize, &mut Subexpanse<K, V>))
+                               -> Masked<slice::IterMut<'_, InnerPtr<K, V>>> {
+            subexpanse.iter_mut(index)
+        }
+        self.subexpanses.iter_mut().enumerate().flat_map(children as _)
+    }
+
+    pub fn child(&self, byte: u8) -> Option<&InnerPtr<K, V>> {
+        self.subexpanses[byte as usize / 32].child(byte % 32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.subexpanses.iter()
+            .map(|subexpanse| subexpanse.len())
+            .sum()
+    }
+
+    /// The child under `byte`, set up as an empty one if there is none. A
+    /// child left empty has to be pruned.
+    pub fn slot(&mut self, byte: u8) -> &mut InnerPtr<K, V> {
+        let subexpanse = &mut self.subexpanses[byte as usize / 32];
+        subexpanse.bitmap |= 1 << (byte % 32);
+        &mut subexpanse.ptr.get_or_insert_with(Default::default)[byte as usize % 32]
+    }
+
+    /// Move the children of `other` across, merging the ones both branches
+    /// have with `merge`. A subexpanse only `other` has moves across whole.
+    pub fn union_with<F>(&mut self, other: BranchBitmap<K, V>, mut merge: F)
+        where F: FnMut(InnerPtr<K, V>, InnerPtr<K, V>) -> InnerPtr<K, V> {
+        for (ours, theirs) in self.subexpanses.iter_mut().zip(other.subexpanses) {
+            let mut ptrs = match theirs.ptr {
+                Some(ptrs) => ptrs,
+                None => continue
+            };
+            let children = match ours.ptr {
+                Some(ref mut children) => children,
+                None => {
+                    *ours = Subexpanse { bitmap: theirs.bitmap, ptr: Some(ptrs) };
+                    continue;
+                }
+            };
+            for sub_byte in 0..32 {
+                if theirs.bitmap & (1 << sub_byte) == 0 {
+                    continue;
+                }
+                let child = ptrs[sub_byte].take();
+                children[sub_byte] = if ours.bitmap & (1 << sub_byte) != 0 {
+                    merge(children[sub_byte].take(), child)
+                } else {
+                    child
+                };
+            }
+            ours.bitmap |= theirs.bitmap;
+        }
+        self.pop = self.count_population();
+    }
+
+    /// Drop the children under key bytes `other` has no child for, clearing
+    /// whole subexpanses at a time.
+    pub fn retain_shared<W>(&mut self, other: &BranchBitmap<K, W>) {
+        for (ours, theirs) in self.subexpanses.iter_mut().zip(other.subexpanses.iter()) {
+            let dropped = ours.bitmap & !theirs.bitmap;
+            ours.bitmap &= theirs.bitmap;
+            if ours.bitmap == 0 {
+                ours.ptr = None;
+                continue;
+            }
+            if let Some(ref mut children) = ours.ptr {
+                for sub_byte in 0..32 {
+                    if dropped & (1 << sub_byte) != 0 {
+                        children[sub_byte] = InnerPtr::empty();
+                    }
+                }
+            }
+        }
+        self.pop = self.count_population();
+    }
+
+    /// Drop the children left empty by changes made through `iter_mut` or
+    /// `slot`, and recount the population.
+    pub fn prune(&mut self) {
+        for subexpanse in self.subexpanses.iter_mut() {
+            if let Some(ref children) = subexpanse.ptr {
+                for (sub_byte, child) in children.iter().enumerate() {
+                    if child.population() == 0 {
+                        subexpanse.bitmap &= !(1 << sub_byte);
+                    }
+                }
+            }
+            if subexpanse.bitmap == 0 {
+                subexpanse.ptr = None;
+            }
         }
+        self.pop = self.count_population();
+    }
+
+    /// Move the children out along with their key bytes.
+    fn into_ptrs(mut self) -> Vec<(u8, InnerPtr<K, V>)> {
+        let mut ptrs = Vec::new();
+        for (index, subexpanse) in self.subexpanses.iter_mut().enumerate() {
+            if let Some(ref mut children) = subexpanse.ptr {
+                for (sub_byte, child) in children.iter_mut().enumerate() {
+                    if subexpanse.bitmap & (1 << sub_byte) != 0 {
+                        let byte = (index * 32 + sub_byte) as u8;
+                        ptrs.push((byte, child.take()));
+                    }
+                }
+            }
+        }
+        ptrs
+    }
+
+    fn children(&self) -> impl Iterator<Item=(u8, &InnerPtr<K, V>)> {
+        self.subexpanses.iter()
+            .enumerate()
+            .flat_map(|(index, subexpanse)| {
+                subexpanse.children()
+                    .map(move |(sub_byte, child)| (index as u8 * 32 + sub_byte, child))
+            })
     }
 }
 
@@ -108,26 +383,158 @@ impl<K: Key, V> JpmNode<K, V> for BranchBitmap<K, V> {
         let (&byte, subkey) = key.split_first().unwrap();
         self.subexpanses[byte as usize / 32].get_mut(byte % 32, subkey)
     }
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let start = *byte;
+        for index in start as usize / 32..8 {
+            let mut sub_byte = if index == start as usize / 32 {
+                start % 32
+            } else {
+                subkey.fill(0);
+                0
+            };
+            let found = self.subexpanses[index].first(&mut sub_byte, subkey);
+            if found.is_some() {
+                *byte = index as u8 * 32 + sub_byte;
+                return found;
+            }
+        }
+        None
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let start = *byte;
+        for index in (0..start as usize / 32 + 1).rev() {
+            let mut sub_byte = if index == start as usize / 32 {
+                start % 32
+            } else {
+                subkey.fill(0xff);
+                31
+            };
+            let found = self.subexpanses[index].last(&mut sub_byte, subkey);
+            if found.is_some() {
+                *byte = index as u8 * 32 + sub_byte;
+                return found;
+            }
+        }
+        None
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let before: usize = self.children()
+            .take_while(|&(child_byte, _)| child_byte < byte)
+            .map(|(_, child)| child.population())
+            .sum();
+        let at = self.subexpanses[byte as usize / 32]
+            .child(byte % 32)
+            .map_or(0, |child| child.count_below(subkey));
+        before + at
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let (&byte, subprefix) = prefix.split_first().unwrap();
+        self.subexpanses[byte as usize / 32]
+            .child(byte % 32)
+            .map_or(0, |child| child.count_prefix(subprefix))
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        branch_select(index, key, self.children())
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        branch_first_empty(key, |byte| {
+            self.subexpanses[byte as usize / 32].child(byte % 32)
+        })
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        branch_last_empty(key, |byte| {
+            self.subexpanses[byte as usize / 32].child(byte % 32)
+        })
+    }
+
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
-        self.subexpanses[byte as usize / 32].insert(byte % 32, subkey, value)
+        if self.child(byte).is_none() && self.len() >= GROW_TO_UNCOMPRESSED {
+            return InsertResult::Resize(value);
+        }
+        let evicted = self.subexpanses[byte as usize / 32].insert(byte % 32, subkey, value);
+        if evicted.is_none() {
+            self.pop += 1;
+        }
+        InsertResult::Success(evicted)
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        let (&byte, subkey) = key.split_first().unwrap();
+        if self.child(byte).is_none() && self.len() >= GROW_TO_UNCOMPRESSED {
+            return EntryResult::Resize(value);
+        }
+        self.pop += 1;
+        let subexpanse = &mut self.subexpanses[byte as usize / 32];
+        EntryResult::Success(subexpanse.insert_entry(byte % 32, subkey, value))
     }
     fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
-        unreachable!()
+        let mut branch: BranchUncompressed<K, V> = self.into_ptrs()
+            .into_iter()
+            .collect();
+        branch.insert(key, value).success();
+        branch.into_ptr()
     }
+
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
-        self.subexpanses[byte as usize / 32].remove(byte % 32, subkey)
+        let empties_child = self.subexpanses[byte as usize / 32]
+            .child(byte % 32)
+            .is_some_and(|child| child.is_last(subkey));
+        if empties_child && self.len() <= SHRINK_TO_LINEAR + 1 {
+            return RemoveResult::Downsize;
+        }
+        let evicted = self.subexpanses[byte as usize / 32].remove(byte % 32, subkey);
+        if evicted.is_some() {
+            self.pop -= 1;
+        }
+        RemoveResult::Success(evicted)
+    }
+
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let value = self.subexpanses[byte as usize / 32]
+            .remove(byte % 32, subkey)
+            .unwrap();
+        if pop == 0 {
+            return (InnerPtr::empty(), value);
+        }
+        let branch: BranchLinear<K, V> = self.into_ptrs()
+            .into_iter()
+            .collect();
+        (branch.into_ptr(), value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        self.into_ptrs()
+            .into_iter()
+            .map(|(byte, child)| (byte, Child::Node(child)))
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.pop
     }
 
-    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
-        unreachable!()
+    fn count_population(&self) -> usize {
+        self.children()
+            .map(|(_, child)| child.population())
+            .sum()
     }
 
     fn memory_usage(&self) -> usize {
         let mut bytes = mem::size_of::<Self>();
-        for i in 0..32 as usize {
-            bytes += self.subexpanses[i].memory_usage();
+        for subexpanse in self.subexpanses.iter() {
+            bytes += subexpanse.memory_usage();
         }
         bytes
     }
@@ -138,6 +545,7 @@ impl<K: Key, V> FromIterator<(u8, InnerPtr<K, V>)> for BranchBitmap<K, V> {
         where I: IntoIterator<Item=(u8, InnerPtr<K, V>)> {
         let mut node = BranchBitmap::new();
         for (k, v) in iter {
+            node.pop += v.population();
             node.subexpanses[k as usize / 32].insert_ptr(k % 32, v);
         }
         node
@@ -3,21 +3,74 @@ use ::util::locksteparray;
 use ::util::SliceExt;
 use super::innerptr::{InnerPtr, IntoPtr};
 use ::Key;
-use super::traits::JpmNode;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use super::traits::{JpmNode, Child, branch_first_empty, branch_last_empty, branch_select,
+                    collect_suffixes};
+use super::leaf_multi::{LeafMulti, MAX_SUFFIX_LEN};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use super::branch_bitmap::BranchBitmap;
-use std::iter::FromIterator;
+use std::iter::{FromIterator, Zip};
 use std::mem;
+use std::slice;
 
+/// Once a removal leaves this few entries under a branch whose keys fit in a
+/// `LeafMulti`, the whole subtree folds back into one leaf.
+pub const COLLAPSE_TO_LEAF: usize = 4;
+
+/// The most children a `BranchLinear` holds.
+pub const CAPACITY: usize = 7;
+
+#[derive(Clone)]
 pub struct BranchLinear<K: Key, V> {
-    array: LockstepArray<[u8; 7], [InnerPtr<K, V>; 7]>
+    array: LockstepArray<[u8; CAPACITY], [InnerPtr<K, V>; CAPACITY]>,
+    pop: usize
 }
 
 impl<K: Key, V> BranchLinear<K, V> {
     pub fn new() -> BranchLinear<K, V> {
         BranchLinear {
-            array: Default::default()
+            array: Default::default(),
+            pop: 0
+        }
+    }
+}
+
+/// The key bytes and children of a `BranchLinear` in key order.
+pub type Iter<'a, K, V> = Zip<slice::Iter<'a, u8>, slice::Iter<'a, InnerPtr<K, V>>>;
+pub type IterMut<'a, K, V> = Zip<slice::Iter<'a, u8>, slice::IterMut<'a, InnerPtr<K, V>>>;
+
+impl<K: Key, V> BranchLinear<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.array.array1().iter().zip(self.array.array2())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        let (keys, children) = self.array.split_mut();
+        keys.iter().zip(children)
+    }
+
+    pub fn child(&self, byte: u8) -> Option<&InnerPtr<K, V>> {
+        self.array.array1()
+            .iter()
+            .position(|&b| b == byte)
+            .map(|index| &self.array.array2()[index])
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// Drop the children left empty by changes made through `iter_mut`, and
+    /// recount the population.
+    pub fn prune(&mut self) {
+        let mut index = 0;
+        while index < self.array.len() {
+            if self.array.array2()[index].population() == 0 {
+                self.array.remove(index);
+            } else {
+                index += 1;
+            }
         }
+        self.pop = self.count_population();
     }
 }
 
@@ -39,12 +92,90 @@ impl<K: Key, V> JpmNode<K, V> for BranchLinear<K, V> {
                       self.array.array2_mut()[index].get_mut(subkey))
     }
 
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let children = self.array.array1()
+            .iter()
+            .zip(self.array.array2().iter());
+        for (&child_byte, child) in children {
+            if child_byte < *byte {
+                continue;
+            } else if child_byte > *byte {
+                *byte = child_byte;
+                subkey.fill(0);
+            }
+            if let Some(value) = child.first(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let children = self.array.array1()
+            .iter()
+            .zip(self.array.array2().iter())
+            .rev();
+        for (&child_byte, child) in children {
+            if child_byte > *byte {
+                continue;
+            } else if child_byte < *byte {
+                *byte = child_byte;
+                subkey.fill(0xff);
+            }
+            if let Some(value) = child.last(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let mut count = 0;
+        let children = self.array.array1()
+            .iter()
+            .zip(self.array.array2().iter());
+        for (&child_byte, child) in children {
+            if child_byte < byte {
+                count += child.population();
+            } else {
+                if child_byte == byte {
+                    count += child.count_below(subkey);
+                }
+                break;
+            }
+        }
+        count
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let (&byte, subprefix) = prefix.split_first().unwrap();
+        self.child(byte).map_or(0, |child| child.count_prefix(subprefix))
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        let children = self.array.array1()
+            .iter()
+            .cloned()
+            .zip(self.array.array2().iter());
+        branch_select(index, key, children)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        branch_first_empty(key, |byte| self.child(byte))
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        branch_last_empty(key, |byte| self.child(byte))
+    }
+
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
-        match self.array.array1().linear_search(&byte) {
+        let evicted = match self.array.array1().linear_search(&byte) {
             Ok(found) => {
-                InsertResult::Success(
-                    self.array.array2_mut()[found].insert(subkey, value))
+                self.array.array2_mut()[found].insert(subkey, value)
             },
             Err(insert) => {
                 match self.array.insert(insert, byte, InnerPtr::empty()) {
@@ -56,10 +187,34 @@ impl<K: Key, V> JpmNode<K, V> for BranchLinear<K, V> {
                         unreachable!()
                     }
                 }
-                let node = &mut self.array.array2_mut()[insert];
-                InsertResult::Success(node.insert(subkey, value))
+                self.array.array2_mut()[insert].insert(subkey, value)
             }
+        };
+        if evicted.is_none() {
+            self.pop += 1;
         }
+        InsertResult::Success(evicted)
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let index = match self.array.array1().linear_search(&byte) {
+            Ok(found) => found,
+            Err(insert) => {
+                match self.array.insert(insert, byte, InnerPtr::empty()) {
+                    Ok(()) => {},
+                    Err(locksteparray::InsertError::Overflow(..)) => {
+                        return EntryResult::Resize(value);
+                    },
+                    Err(locksteparray::InsertError::OutOfBounds(..)) => {
+                        unreachable!()
+                    }
+                }
+                insert
+            }
+        };
+        self.pop += 1;
+        EntryResult::Success(self.array.array2_mut()[index].insert_entry(subkey, value))
     }
 
     fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
@@ -67,24 +222,63 @@ impl<K: Key, V> JpmNode<K, V> for BranchLinear<K, V> {
             .into_iter()
             .collect();
         branch.insert(key, value).success();
-        IntoPtr::into_ptr(Box::new(branch), pop)
+        branch.into_ptr()
     }
 
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
         match self.array.array1().linear_search(&byte) {
             Ok(found) => {
-                    RemoveResult::Success(
-                        self.array.array2_mut()[found].remove(subkey))
-                },
+                let child = &self.array.array2()[found];
+                let collapse = key.len() <= MAX_SUFFIX_LEN
+                    && self.population() <= COLLAPSE_TO_LEAF + 1;
+                let empties = self.array.len() == 1 && child.population() == 1;
+                if (collapse || empties) && child.get(subkey).is_some() {
+                    return RemoveResult::Downsize;
+                }
+                let evicted = self.array.array2_mut()[found].remove(subkey);
+                if evicted.is_some() {
+                    self.pop -= 1;
+                }
+                if self.array.array2()[found].population() == 0 {
+                    self.array.remove(found);
+                }
+                RemoveResult::Success(evicted)
+            },
             Err(insert) => {
                 RemoveResult::Success(None)
             }
         }
     }
 
-    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
-        unreachable!()
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let found = self.array.array1().linear_search(&byte).unwrap();
+        let value = self.array.array2_mut()[found].remove(subkey).unwrap();
+        if pop == 0 {
+            return (InnerPtr::empty(), value);
+        }
+        let mut entries = Vec::with_capacity(pop);
+        collect_suffixes(self.into_children(), key.len(), 0, &mut entries);
+        let leaf = LeafMulti::from_sorted(key.len(), entries);
+        (leaf.into_ptr(), value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        self.array.into_iter()
+            .map(|(byte, child)| (byte, Child::Node(child)))
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.pop
+    }
+
+    fn count_population(&self) -> usize {
+        self.array.array2()
+            .iter()
+            .map(|child| child.population())
+            .sum()
     }
 
     fn memory_usage(&self) -> usize {
@@ -101,7 +295,10 @@ impl<K: Key, V> FromIterator<(u8, InnerPtr<K, V>)> for BranchLinear<K, V> {
         where I: IntoIterator<Item=(u8, InnerPtr<K, V>)> {
         let mut node = BranchLinear::new();
         for (k, v) in iter {
-            node.array.push(k, v);
+            node.pop += v.population();
+            if node.array.push(k, v).is_err() {
+                panic!("Too many children for a linear branch");
+            }
         }
         node
     }
@@ -1,29 +1,93 @@
+use std::iter::FromIterator;
 use std::mem;
 use std::ptr;
+use std::slice;
 
-use super::innerptr::InnerPtr;
-use super::traits::JpmNode;
+use super::innerptr::{InnerPtr, IntoPtr};
+use super::branch_bitmap::BranchBitmap;
+use super::traits::{JpmNode, Child, branch_first_empty, branch_last_empty, branch_select};
 use ::Key;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use ::util::Masked;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 
+/// Once a removal leaves this few children, the branch shrinks back to a
+/// `BranchBitmap`.
+const SHRINK_TO_BITMAP: usize = 64;
+
+#[derive(Clone)]
 pub struct BranchUncompressed<K: Key, V> {
-    array: [InnerPtr<K, V>; 256]
+    array: [InnerPtr<K, V>; 256],
+    pop: usize
 }
 
 impl<K: Key, V> BranchUncompressed<K, V> {
     pub fn new() -> BranchUncompressed<K, V> {
+        let mut array: mem::MaybeUninit<[InnerPtr<K, V>; 256]> = mem::MaybeUninit::uninit();
         unsafe {
-            let mut branch = BranchUncompressed {
-                array: { mem::uninitialized() }
-            };
-            for position in branch.array.iter_mut() {
-                ptr::write(position, InnerPtr::empty());
+            let first = array.as_mut_ptr() as *mut InnerPtr<K, V>;
+            for index in 0..256 {
+                ptr::write(first.add(index), InnerPtr::empty());
+            }
+            BranchUncompressed {
+                array: array.assume_init(),
+                pop: 0
             }
-            branch
         }
     }
 }
 
+/// The key bytes and non-empty children of a `BranchUncompressed` in key
+/// order.
+pub type Iter<'a, K, V> = Masked<slice::Iter<'a, InnerPtr<K, V>>>;
+pub type IterMut<'a, K, V> = Masked<slice::IterMut<'a, InnerPtr<K, V>>>;
+
+impl<K: Key, V> BranchUncompressed<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Masked::new(self.occupied(), 0, self.array.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        Masked::new(self.occupied(), 0, self.array.iter_mut())
+    }
+
+    /// A bit for each non-empty child.
+    fn occupied(&self) -> [u8; 256 / 8] {
+        let mut bitmap = [0; 256 / 8];
+        for (index, child) in self.array.iter().enumerate() {
+            if child.population() != 0 {
+                bitmap[index / 8] |= 1 << (index % 8);
+            }
+        }
+        bitmap
+    }
+
+    pub fn child(&self, byte: u8) -> Option<&InnerPtr<K, V>> {
+        let child = &self.array[byte as usize];
+        if child.population() != 0 {
+            Some(child)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.iter()
+            .filter(|child| child.population() != 0)
+            .count()
+    }
+
+    /// The child under `byte`, which is empty if there is none.
+    pub fn slot(&mut self, byte: u8) -> &mut InnerPtr<K, V> {
+        &mut self.array[byte as usize]
+    }
+
+    /// Recount the population after changes made through `iter_mut` or
+    /// `slot`. Empty children need no other tidying up.
+    pub fn prune(&mut self) {
+        self.pop = self.count_population();
+    }
+}
+
 impl<K: Key, V> JpmNode<K, V> for BranchUncompressed<K, V> {
     fn get(&self, key: &[u8]) -> Option<&V> {
         let (&byte, subkey) = key.split_first().unwrap();
@@ -35,24 +99,127 @@ impl<K: Key, V> JpmNode<K, V> for BranchUncompressed<K, V> {
         self.array[byte as usize].get_mut(subkey)
     }
 
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let start = *byte as usize;
+        for index in start..256 {
+            if index != start {
+                *byte = index as u8;
+                subkey.fill(0);
+            }
+            if let Some(value) = self.array[index].first(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let (byte, subkey) = key.split_first_mut().unwrap();
+        let start = *byte as usize;
+        for index in (0..start + 1).rev() {
+            if index != start {
+                *byte = index as u8;
+                subkey.fill(0xff);
+            }
+            if let Some(value) = self.array[index].last(subkey) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let before: usize = self.array[..byte as usize]
+            .iter()
+            .map(|child| child.population())
+            .sum();
+        before + self.array[byte as usize].count_below(subkey)
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let (&byte, subprefix) = prefix.split_first().unwrap();
+        self.array[byte as usize].count_prefix(subprefix)
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        let children = self.array.iter()
+            .enumerate()
+            .map(|(byte, child)| (byte as u8, child));
+        branch_select(index, key, children)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        branch_first_empty(key, |byte| Some(&self.array[byte as usize]))
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        branch_last_empty(key, |byte| Some(&self.array[byte as usize]))
+    }
+
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
         let evicted = self.array[byte as usize].insert(subkey, value);
+        if evicted.is_none() {
+            self.pop += 1;
+        }
         InsertResult::Success(evicted)
     }
 
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        let (&byte, subkey) = key.split_first().unwrap();
+        self.pop += 1;
+        EntryResult::Success(self.array[byte as usize].insert_entry(subkey, value))
+    }
+
     fn expand(self, population: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
         unreachable!()
     }
 
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
         let (&byte, subkey) = key.split_first().unwrap();
+        // Emptied children turn back into `Empty` on their own, so only the
+        // branch itself is left to check
+        if self.array[byte as usize].is_last(subkey) && self.len() <= SHRINK_TO_BITMAP + 1 {
+            return RemoveResult::Downsize;
+        }
         let evicted = self.array[byte as usize].remove(subkey);
+        if evicted.is_some() {
+            self.pop -= 1;
+        }
         RemoveResult::Success(evicted)
     }
 
-    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
-        unreachable!()
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let (&byte, subkey) = key.split_first().unwrap();
+        let value = self.array[byte as usize].remove(subkey).unwrap();
+        if pop == 0 {
+            return (InnerPtr::empty(), value);
+        }
+        let branch: BranchBitmap<K, V> = self.array.iter_mut()
+            .enumerate()
+            .filter(|(_, child)| child.population() != 0)
+            .map(|(byte, child)| (byte as u8, child.take()))
+            .collect();
+        (branch.into_ptr(), value)
+    }
+
+    fn into_children(mut self) -> Vec<(u8, Child<K, V>)> {
+        self.array.iter_mut()
+            .enumerate()
+            .map(|(byte, child)| (byte as u8, Child::Node(child.take())))
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.pop
+    }
+
+    fn count_population(&self) -> usize {
+        self.array.iter()
+            .map(|child| child.population())
+            .sum()
     }
 
     fn memory_usage(&self) -> usize {
@@ -63,3 +230,87 @@ impl<K: Key, V> JpmNode<K, V> for BranchUncompressed<K, V> {
         bytes
     }
 }
+
+impl<K: Key, V> FromIterator<(u8, InnerPtr<K, V>)> for BranchUncompressed<K, V> {
+    fn from_iter<I>(iter: I) -> BranchUncompressed<K, V>
+        where I: IntoIterator<Item=(u8, InnerPtr<K, V>)> {
+        let mut node = BranchUncompressed::new();
+        for (byte, child) in iter {
+            node.pop += child.population();
+            node.array[byte as usize] = child;
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::innerptr::Ref;
+    use super::super::branch_bitmap::GROW_TO_UNCOMPRESSED;
+
+    #[test]
+    fn test_shrink_remove() {
+        let mut branch: BranchUncompressed<u16, u16> = BranchUncompressed::new();
+        for byte in 0..SHRINK_TO_BITMAP as u16 + 2 {
+            branch.insert(&[byte as u8, 0], byte).success();
+        }
+        let mut ptr = InnerPtr::new(branch);
+
+        // removing a missing key must not shrink anything
+        assert_eq!(ptr.remove(&[255, 0]), None);
+        assert_eq!(ptr.remove(&[0, 0]), Some(0));
+        match ptr.as_ref() {
+            Ref::BranchUncompressed(_) => {},
+            _ => panic!("branch shrank too early")
+        }
+
+        assert_eq!(ptr.remove(&[1, 0]), Some(1));
+        match ptr.as_ref() {
+            Ref::BranchBitmap(_) => {},
+            _ => panic!("branch should have shrunk to a bitmap")
+        }
+        assert_eq!(ptr.population(), SHRINK_TO_BITMAP);
+        for byte in 2..SHRINK_TO_BITMAP as u16 + 2 {
+            assert_eq!(ptr.get(&[byte as u8, 0]), Some(&byte));
+        }
+    }
+
+    #[test]
+    fn test_grow_from_bitmap() {
+        let mut branch: BranchBitmap<u16, u16> = BranchBitmap::new();
+        for byte in 0..GROW_TO_UNCOMPRESSED as u16 {
+            branch.insert(&[byte as u8, 0], byte).success();
+        }
+        let mut ptr = InnerPtr::new(branch);
+        // another entry under an existing child leaves the branch alone
+        assert_eq!(ptr.insert(&[0, 1], 0), None);
+        match ptr.as_ref() {
+            Ref::BranchBitmap(_) => {},
+            _ => panic!("branch grew too early")
+        }
+
+        let byte = GROW_TO_UNCOMPRESSED as u16;
+        assert_eq!(ptr.insert(&[byte as u8, 0], byte), None);
+        match ptr.as_ref() {
+            Ref::BranchUncompressed(_) => {},
+            _ => panic!("branch should have grown uncompressed")
+        }
+        assert_eq!(ptr.population(), GROW_TO_UNCOMPRESSED + 2);
+        for byte in 0..=GROW_TO_UNCOMPRESSED as u16 {
+            assert_eq!(ptr.get(&[byte as u8, 0]), Some(&byte));
+        }
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut branch: BranchUncompressed<u16, u16> = BranchUncompressed::new();
+        for byte in 0..=255u8 {
+            branch.insert(&[byte, byte], byte as u16).success();
+        }
+        // one word per child, and every child is an immediate, so the whole
+        // branch is its pointers and its population
+        assert_eq!(branch.memory_usage(), 257 * mem::size_of::<usize>());
+        assert_eq!(branch.population(), 256);
+    }
+}
@@ -0,0 +1,214 @@
+//! Building a trie bottom-up from entries in ascending key order. A subtree
+//! is only built once all of its entries have been read, straight into the
+//! node that insertions would have grown for that many entries, so nothing
+//! is expanded or split along the way.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use super::innerptr::{InnerPtr, IntoPtr};
+use super::immediate::Immediate;
+use super::empty;
+use super::leaf_linear::LeafLinear;
+use super::leaf_multi::{self, LeafMulti, MAX_SUFFIX_LEN};
+use super::leaf_bitmap::LeafBitmap;
+use super::branch_bitmap::branch_for;
+use super::traits::JpmNode;
+use ::util::partial_read;
+use ::Key;
+
+/// Entries read ahead from an iterator in ascending key order.
+pub struct Sorted<K: Key, V, I> {
+    iter: I,
+    ahead: VecDeque<(K::Bytes, V)>
+}
+
+impl<K: Key, V, I: Iterator<Item=(K, V)>> Sorted<K, V, I> {
+    pub fn new(iter: I) -> Sorted<K, V, I> {
+        Sorted {
+            iter,
+            ahead: VecDeque::new()
+        }
+    }
+
+    /// Read until more than `count` entries are buffered, or the iterator
+    /// runs out. A repeated key replaces the value read before it, so the
+    /// last entry is never handed out before the one after it is known.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is smaller than the one before it.
+    fn fill(&mut self, count: usize) {
+        while self.ahead.len() <= count {
+            let (key, value) = match self.iter.next() {
+                Some(entry) => entry,
+                None => return
+            };
+            let bytes = key.into_bytes();
+            if let Some(last) = self.ahead.back_mut() {
+                match bytes.as_ref().cmp(last.0.as_ref()) {
+                    Ordering::Less => panic!("keys are not in ascending order"),
+                    Ordering::Equal => {
+                        last.1 = value;
+                        continue;
+                    },
+                    Ordering::Greater => {}
+                }
+            }
+            self.ahead.push_back((bytes, value));
+        }
+    }
+
+    /// The key bytes of the entry `index` places ahead.
+    pub fn peek(&mut self, index: usize) -> Option<&[u8]> {
+        self.fill(index + 1);
+        self.ahead.get(index).map(|entry| entry.0.as_ref())
+    }
+
+    pub fn pop(&mut self) -> Option<(K::Bytes, V)> {
+        self.fill(1);
+        self.ahead.pop_front()
+    }
+
+    /// Whether the entry `index` places ahead starts with `prefix`.
+    fn matches(&mut self, index: usize, prefix: &[u8]) -> bool {
+        self.peek(index).is_some_and(|bytes| bytes.starts_with(prefix))
+    }
+
+    /// Build a trie from every entry left.
+    pub fn build(&mut self) -> InnerPtr<K, V> {
+        if self.peek(0).is_none() {
+            return InnerPtr::empty();
+        }
+        self.subtree(0)
+    }
+
+    /// Build the subtree for the entries sharing the first `depth` key
+    /// bytes of the next one.
+    fn subtree(&mut self, depth: usize) -> InnerPtr<K, V> {
+        let mut prefix = K::default().into_bytes();
+        prefix.as_mut().copy_from_slice(self.peek(0).unwrap());
+        let rest = prefix.as_ref().len() - depth;
+        let prefix = &prefix.as_ref()[..depth];
+
+        let capacity = Immediate::<K, V>::capacity(rest);
+        let mut count = 1;
+        while count <= capacity && self.matches(count, prefix) {
+            count += 1;
+        }
+        if count <= capacity {
+            let entries = (0..count).map(|_| {
+                let (bytes, value) = self.pop().unwrap();
+                (partial_read(&bytes.as_ref()[depth..]), value)
+            });
+            return Immediate::from_sorted(rest, entries);
+        }
+        if !self.matches(1, prefix) {
+            let (bytes, value) = self.pop().unwrap();
+            return empty::new_node(&bytes.as_ref()[depth..], value);
+        }
+        if rest <= MAX_SUFFIX_LEN {
+            let capacity = if rest == 1 {
+                LeafLinear::<K, V>::capacity()
+            } else {
+                leaf_multi::CAPACITY
+            };
+            let mut count = 2;
+            while count <= capacity && self.matches(count, prefix) {
+                count += 1;
+            }
+            if count <= capacity {
+                return self.leaf(depth, rest, count);
+            }
+            if rest == 1 {
+                let mut leaf = LeafBitmap::new();
+                while self.matches(0, prefix) {
+                    let (bytes, value) = self.pop().unwrap();
+                    leaf.insert(&bytes.as_ref()[depth..], value).success();
+                }
+                return leaf.into_ptr();
+            }
+        }
+
+        let mut children = Vec::new();
+        while self.matches(0, prefix) {
+            let byte = self.peek(0).unwrap()[depth];
+            children.push((byte, self.subtree(depth + 1)));
+        }
+        branch_for(children)
+    }
+
+    /// Build a leaf from the next `count` entries, which have `rest` key
+    /// bytes left after the first `depth`.
+    fn leaf(&mut self, depth: usize, rest: usize, count: usize) -> InnerPtr<K, V> {
+        let entries = (0..count).map(|_| self.pop().unwrap());
+        if rest == 1 {
+            entries.map(|(bytes, value)| (bytes.as_ref()[depth], value))
+                .collect::<LeafLinear<K, V>>()
+                .into_ptr()
+        } else {
+            let entries = entries.map(|(bytes, value)| (partial_read(&bytes.as_ref()[depth..]), value));
+            LeafMulti::from_sorted(rest, entries).into_ptr()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::innerptr::Ref;
+
+    fn build(keys: &[u32]) -> InnerPtr<u32, u8> {
+        Sorted::new(keys.iter().map(|&key| (key, key as u8))).build()
+    }
+
+    #[test]
+    fn test_node_types() {
+        match build(&[7]).as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("a lone entry should be inline")
+        }
+        let set: InnerPtr<u16, ()> = Sorted::new([1, 2, 3].iter().map(|&key: &u16| (key, ())))
+            .build();
+        match set.as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("a few entries without values should be inline")
+        }
+        match build(&[1, 2, 3]).as_ref() {
+            Ref::LeafMulti(_) => {},
+            _ => panic!("a few entries should share a leaf")
+        }
+        let dense: Vec<u32> = (0..256).collect();
+        match build(&dense).as_ref() {
+            Ref::Narrow(narrow) => match narrow.child().as_ref() {
+                Ref::LeafBitmap(_) => {},
+                _ => panic!("a full last byte should be a bitmap leaf")
+            },
+            _ => panic!("the first three bytes are shared")
+        }
+        let spread: Vec<u32> = (0..20).map(|n| n << 24).collect();
+        match build(&spread).as_ref() {
+            Ref::BranchBitmap(_) => {},
+            _ => panic!("twenty children need a bitmap branch")
+        }
+        let spread: Vec<u32> = (0..200).map(|n| n << 24).collect();
+        match build(&spread).as_ref() {
+            Ref::BranchUncompressed(_) => {},
+            _ => panic!("most of the children fill an uncompressed branch")
+        }
+    }
+
+    #[test]
+    fn test_repeated_keys() {
+        let ptr: InnerPtr<u32, u32> = Sorted::new(vec![(1, 1), (1, 2), (3, 3), (3, 4)].into_iter())
+            .build();
+        assert_eq!(ptr.population(), 2);
+        assert_eq!(ptr.get(&1u32.to_be_bytes()), Some(&2));
+        assert_eq!(ptr.get(&3u32.to_be_bytes()), Some(&4));
+    }
+
+    #[test]
+    #[should_panic(expected = "ascending")]
+    fn test_unsorted() {
+        build(&[1, 3, 2]);
+    }
+}
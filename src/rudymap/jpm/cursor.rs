@@ -0,0 +1,355 @@
+//! Walking a trie in key order from both ends at once, for the borrowing
+//! iterators. Each end keeps a stack of the nodes it is partway through, so
+//! a step only costs the nodes it enters and leaves rather than a search
+//! from the root.
+//!
+//! Both ends start out sharing the root. Whenever one of them steps down
+//! into a child, that child is its own from then on, and only the node
+//! where the two paths split is ever shared. Once the shared node runs out
+//! of children from one end, the other end's topmost node takes its place.
+//! No node is reached twice, so a mutable walk can hand out disjoint
+//! borrows of every value it passes.
+
+use std::cmp::Ordering;
+use std::option;
+use super::innerptr::{InnerPtr, Ref, Mut};
+use super::immediate;
+use super::leaf_linear;
+use super::leaf_multi;
+use super::leaf_bitmap;
+use super::branch_linear;
+use super::branch_bitmap;
+use super::branch_uncompressed;
+use ::util::partial_write;
+use ::Key;
+
+/// Where a step down the trie leads.
+pub enum Target<P, T> {
+    Node(P),
+    Value(T)
+}
+
+/// One step down the trie: the key bytes it covers and where it leads.
+pub struct Step<P, T> {
+    bytes: [u8; 8],
+    len: usize,
+    target: Target<P, T>
+}
+
+impl<P, T> Step<P, T> {
+    fn new(bytes: &[u8], target: Target<P, T>) -> Step<P, T> {
+        let mut step = Step {
+            bytes: [0; 8],
+            len: bytes.len(),
+            target
+        };
+        step.bytes[..bytes.len()].copy_from_slice(bytes);
+        step
+    }
+
+    /// A step over `len` key bytes packed big-endian into `suffix`.
+    fn packed(suffix: usize, len: usize, target: Target<P, T>) -> Step<P, T> {
+        let mut step = Step {
+            bytes: [0; 8],
+            len,
+            target
+        };
+        partial_write(&mut step.bytes[..len], suffix);
+        step
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// A shared or mutable pointer to a subtree that can be walked.
+pub trait Walk: Sized {
+    type Value;
+    type Frame: DoubleEndedIterator<Item=Step<Self, Self::Value>>;
+
+    /// The steps out of the node in key order, or `None` if it is empty.
+    fn open(self) -> Option<Self::Frame>;
+}
+
+macro_rules! frame_step {
+    ($frame:ident, $node:expr, $next:ident) => {
+        match *$node {
+            $frame::One(ref mut iter) => iter.$next(),
+            $frame::Immediate(ref mut iter) => {
+                iter.$next().map(|(bytes, value)| Step::new(bytes, Target::Value(value)))
+            },
+            $frame::LeafLinear(ref mut iter) => {
+                iter.$next().map(|(&byte, value)| Step::new(&[byte], Target::Value(value)))
+            },
+            $frame::LeafMulti(len, ref mut iter) => {
+                iter.$next().map(|(&suffix, value)| Step::packed(suffix, len, Target::Value(value)))
+            },
+            $frame::LeafBitmap(ref mut iter) => {
+                iter.$next().map(|(byte, value)| Step::new(&[byte], Target::Value(value)))
+            },
+            $frame::BranchLinear(ref mut iter) => {
+                iter.$next().map(|(&byte, child)| Step::new(&[byte], Target::Node(child)))
+            },
+            $frame::BranchBitmap(ref mut iter) => {
+                iter.$next().map(|(byte, child)| Step::new(&[byte], Target::Node(child)))
+            },
+            $frame::BranchUncompressed(ref mut iter) => {
+                iter.$next().map(|(byte, child)| Step::new(&[byte], Target::Node(child)))
+            }
+        }
+    }
+}
+
+macro_rules! make_frame {
+    ($frame:ident, $ptr:ty, $value:ty, $immediate:ty, $leaf_linear:ty, $leaf_multi:ty,
+     $leaf_bitmap:ty, $branch_linear:ty, $branch_bitmap:ty, $branch_uncompressed:ty) => {
+        /// The steps left out of one node. Narrows take a single step.
+        pub enum $frame<'a, K: Key + 'a, V: 'a> {
+            One(option::IntoIter<Step<$ptr, $value>>),
+            Immediate($immediate),
+            LeafLinear($leaf_linear),
+            LeafMulti(usize, $leaf_multi),
+            LeafBitmap($leaf_bitmap),
+            BranchLinear($branch_linear),
+            BranchBitmap($branch_bitmap),
+            BranchUncompressed($branch_uncompressed)
+        }
+
+        impl<'a, K: Key + 'a, V: 'a> Iterator for $frame<'a, K, V> {
+            type Item = Step<$ptr, $value>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                frame_step!($frame, self, next)
+            }
+        }
+
+        impl<'a, K: Key + 'a, V: 'a> DoubleEndedIterator for $frame<'a, K, V> {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                frame_step!($frame, self, next_back)
+            }
+        }
+    }
+}
+
+make_frame!(Frame, &'a InnerPtr<K, V>, &'a V,
+            immediate::Iter<'a, V>,
+            leaf_linear::Iter<'a, V>,
+            leaf_multi::Iter<'a, V>,
+            leaf_bitmap::Iter<'a, V>,
+            branch_linear::Iter<'a, K, V>,
+            branch_bitmap::Iter<'a, K, V>,
+            branch_uncompressed::Iter<'a, K, V>);
+
+make_frame!(FrameMut, &'a mut InnerPtr<K, V>, &'a mut V,
+            immediate::IterMut<'a, V>,
+            leaf_linear::IterMut<'a, V>,
+            leaf_multi::IterMut<'a, V>,
+            leaf_bitmap::IterMut<'a, V>,
+            branch_linear::IterMut<'a, K, V>,
+            branch_bitmap::IterMut<'a, K, V>,
+            branch_uncompressed::IterMut<'a, K, V>);
+
+impl<'a, K: Key + 'a, V: 'a> Walk for &'a InnerPtr<K, V> {
+    type Value = &'a V;
+    type Frame = Frame<'a, K, V>;
+
+    fn open(self) -> Option<Frame<'a, K, V>> {
+        let frame = match self.as_ref() {
+            Ref::Empty(_) => return None,
+            Ref::Immediate(immediate) => Frame::Immediate(immediate.iter()),
+            Ref::Narrow(narrow) => {
+                let step = Step::new(narrow.prefix(), Target::Node(narrow.child()));
+                Frame::One(Some(step).into_iter())
+            },
+            Ref::LeafLinear(leaf) => Frame::LeafLinear(leaf.iter()),
+            Ref::LeafMulti(leaf) => Frame::LeafMulti(leaf.suffix_len(), leaf.iter()),
+            Ref::LeafBitmap(leaf) => Frame::LeafBitmap(leaf.iter()),
+            Ref::BranchLinear(branch) => Frame::BranchLinear(branch.iter()),
+            Ref::BranchBitmap(branch) => Frame::BranchBitmap(branch.iter()),
+            Ref::BranchUncompressed(branch) => Frame::BranchUncompressed(branch.iter())
+        };
+        Some(frame)
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> Walk for &'a mut InnerPtr<K, V> {
+    type Value = &'a mut V;
+    type Frame = FrameMut<'a, K, V>;
+
+    fn open(self) -> Option<FrameMut<'a, K, V>> {
+        let frame = match self.as_mut() {
+            Mut::Empty(_) => return None,
+            Mut::Immediate(immediate) => FrameMut::Immediate(immediate.iter_mut()),
+            Mut::Narrow(narrow) => {
+                let (prefix, child) = narrow.split_mut();
+                FrameMut::One(Some(Step::new(prefix, Target::Node(child))).into_iter())
+            },
+            Mut::LeafLinear(leaf) => FrameMut::LeafLinear(leaf.iter_mut()),
+            Mut::LeafMulti(leaf) => FrameMut::LeafMulti(leaf.suffix_len(), leaf.iter_mut()),
+            Mut::LeafBitmap(leaf) => FrameMut::LeafBitmap(leaf.iter_mut()),
+            Mut::BranchLinear(branch) => FrameMut::BranchLinear(branch.iter_mut()),
+            Mut::BranchBitmap(branch) => FrameMut::BranchBitmap(branch.iter_mut()),
+            Mut::BranchUncompressed(branch) => FrameMut::BranchUncompressed(branch.iter_mut())
+        };
+        Some(frame)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum End {
+    Front,
+    Back
+}
+
+impl End {
+    fn pull<I: DoubleEndedIterator>(self, iter: &mut I) -> Option<I::Item> {
+        match self {
+            End::Front => iter.next(),
+            End::Back => iter.next_back()
+        }
+    }
+
+    /// Turn an ordering between keys into one in the direction this end
+    /// walks, so that `Less` means "not reached yet".
+    fn order(self, order: Ordering) -> Ordering {
+        match self {
+            End::Front => order,
+            End::Back => order.reverse()
+        }
+    }
+}
+
+/// One end of a walk.
+struct Side<K: Key, P: Walk> {
+    /// The nodes below the shared one that this end is partway through,
+    /// with the depth of the key bytes each one covers
+    stack: Vec<(usize, P::Frame)>,
+    key: K::Bytes,
+    /// While set, entries before this key are skipped
+    seek: Option<K::Bytes>
+}
+
+impl<K: Key, P: Walk> Side<K, P> {
+    fn new(seek: Option<K::Bytes>) -> Side<K, P> {
+        Side {
+            stack: Vec::new(),
+            key: K::default().into_bytes(),
+            seek
+        }
+    }
+
+    /// Take a step found at `depth`, returning the value if it led to one.
+    fn enter(&mut self, depth: usize, step: Step<P, P::Value>, end: End) -> Option<P::Value> {
+        let end_byte = depth + step.len;
+        if let Some(order) = self.seek.as_ref()
+            .map(|seek| end.order(step.bytes().cmp(&seek.as_ref()[depth..end_byte]))) {
+            match order {
+                Ordering::Less => return None,
+                Ordering::Equal => {},
+                // Everything from here on is past the key sought
+                Ordering::Greater => self.seek = None
+            }
+        }
+        self.key.as_mut()[depth..end_byte].copy_from_slice(step.bytes());
+        match step.target {
+            Target::Value(value) => Some(value),
+            Target::Node(node) => {
+                if let Some(frame) = node.open() {
+                    self.stack.push((end_byte, frame));
+                }
+                None
+            }
+        }
+    }
+
+    /// Carry on from the other end's topmost node, which covers the key
+    /// bytes from `depth`.
+    fn take_over(&mut self, other: &Side<K, P>, depth: usize, end: End) {
+        let prefix = &other.key.as_ref()[..depth];
+        self.key.as_mut()[..depth].copy_from_slice(prefix);
+        let passed = self.seek.as_ref()
+            .is_some_and(|seek| end.order(prefix.cmp(&seek.as_ref()[..depth])) == Ordering::Greater);
+        if passed {
+            self.seek = None;
+        }
+    }
+}
+
+/// A walk over a subtree in key order from both ends.
+pub struct Cursor<K: Key, P: Walk> {
+    /// The node where the paths of the two ends split
+    shared: Option<(usize, P::Frame)>,
+    front: Side<K, P>,
+    back: Side<K, P>,
+    remaining: usize
+}
+
+impl<K: Key, P: Walk> Cursor<K, P> {
+    /// Walk the `remaining` entries of `root` from the first at or after
+    /// `front` to the last at or before `back`, given as key bytes.
+    pub fn new(root: P, front: Option<K::Bytes>, back: Option<K::Bytes>, remaining: usize)
+               -> Cursor<K, P> {
+        Cursor {
+            shared: root.open().map(|frame| (0, frame)),
+            front: Side::new(front),
+            back: Side::new(back),
+            remaining
+        }
+    }
+
+    /// The number of entries left between the two ends.
+    pub fn len(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn next_front(&mut self) -> Option<(K, P::Value)> {
+        self.step(End::Front)
+    }
+
+    pub fn next_back(&mut self) -> Option<(K, P::Value)> {
+        self.step(End::Back)
+    }
+
+    fn step(&mut self, end: End) -> Option<(K, P::Value)> {
+        let (side, other) = match end {
+            End::Front => (&mut self.front, &mut self.back),
+            End::Back => (&mut self.back, &mut self.front)
+        };
+        while self.remaining != 0 {
+            let (depth, step) = if let Some(&mut (depth, ref mut frame)) = side.stack.last_mut() {
+                match end.pull(frame) {
+                    Some(step) => (depth, step),
+                    None => {
+                        side.stack.pop();
+                        continue;
+                    }
+                }
+            } else {
+                let pulled = match self.shared {
+                    Some((depth, ref mut frame)) => end.pull(frame).map(|step| (depth, step)),
+                    None => return None
+                };
+                match pulled {
+                    Some(pulled) => pulled,
+                    None => {
+                        // Whatever is left lies under the other end's nodes
+                        if other.stack.is_empty() {
+                            self.shared = None;
+                            return None;
+                        }
+                        let (depth, frame) = other.stack.remove(0);
+                        side.take_over(other, depth, end);
+                        self.shared = Some((depth, frame));
+                        continue;
+                    }
+                }
+            };
+            if let Some(value) = side.enter(depth, step, end) {
+                self.remaining -= 1;
+                return Some((K::from_bytes(side.key.clone()), value));
+            }
+        }
+        None
+    }
+}
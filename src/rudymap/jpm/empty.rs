@@ -1,12 +1,14 @@
-use super::traits::JpmNode;
+use super::traits::{JpmNode, Child};
 use super::innerptr::{InnerPtr, IntoPtr};
 use ::Key;
-//use super::leaf_linear::LeafLinear;
-use super::leaf_bitmap::LeafBitmap;
+use super::leaf_linear::LeafLinear;
+use super::leaf_multi::{LeafMulti, MAX_SUFFIX_LEN};
 use super::branch_uncompressed::BranchUncompressed;
-use super::branch_linear::BranchLinear;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use super::narrow::{Narrow, MAX_PREFIX_LEN};
+use super::immediate::Immediate;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 
+use std::cmp;
 use std::marker::PhantomData;
 
 #[derive(Copy, Clone)]
@@ -31,22 +33,48 @@ impl<K: Key, V> JpmNode<K, V> for Empty<K, V> {
         None
     }
 
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        None
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        None
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        0
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        0
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        None
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        true
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        true
+    }
+
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
         InsertResult::Resize(value)
     }
 
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        EntryResult::Resize(value)
+    }
+
     fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
-        //let mut leaf_linear = Box::new(LeafLinear::new());
-        //leaf_linear.insert(key, value).success();
-        //leaf_linear
-        if key.len() == 1 {
-            let mut leaf = Box::new(LeafBitmap::new());
-            leaf.insert(key, value).success();
-            IntoPtr::into_ptr(leaf, pop)
+        debug_assert_eq!(pop, 1);
+        if Immediate::<K, V>::fits(key.len()) {
+            Immediate::new_ptr(key, value)
         } else {
-            let mut branch = Box::new(BranchLinear::new());
-            branch.insert(key, value).success();
-            IntoPtr::into_ptr(branch, pop)
+            new_node(key, value)
         }
     }
 
@@ -58,7 +86,35 @@ impl<K: Key, V> JpmNode<K, V> for Empty<K, V> {
         unreachable!();
     }
 
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        Vec::new()
+    }
+
+    fn population(&self) -> usize {
+        0
+    }
+
     fn memory_usage(&self) -> usize {
         0
     }
 }
+
+/// Allocate a node for a single entry, picked by how many key bytes are left.
+/// An immediate that overflows starts its node from here too.
+pub fn new_node<K: Key, V>(key: &[u8], value: V) -> InnerPtr<K, V> {
+    if key.len() == 1 {
+        let mut leaf = LeafLinear::new();
+        leaf.insert(key, value).success();
+        leaf.into_ptr()
+    } else if key.len() <= MAX_SUFFIX_LEN {
+        let mut leaf = LeafMulti::new(key.len());
+        leaf.insert(key, value).success();
+        leaf.into_ptr()
+    } else {
+        // Skip straight down to where a multi-byte leaf can take over
+        let skip = cmp::min(MAX_PREFIX_LEN, key.len() - MAX_SUFFIX_LEN);
+        let mut child = InnerPtr::empty();
+        child.insert(&key[skip..], value);
+        Narrow::wrap(&key[..skip], child)
+    }
+}
@@ -0,0 +1,670 @@
+//! Immediates, after Judy's `JPIMMED` pointers: a subtree holding only a
+//! few entries is kept in the `InnerPtr` word itself rather than behind a
+//! box. The word holds a tag, the key bytes left for each entry and their
+//! values, so nothing is allocated.
+//!
+//! The tag byte has its low bit set, which no node pointer has, and packs
+//! the number of key bytes left and the number of entries above it. The
+//! entries are kept in key order. How many fit depends on the value and on
+//! how many key bytes are left: a `u32` value leaves room for one entry with
+//! three key bytes on a 64-bit target, while a set with one byte left fits
+//! seven. One more entry than fits promotes the immediate to a real node,
+//! and leaves shrink back once half that many are left.
+
+use super::traits::{JpmNode, Child};
+use super::innerptr::{InnerPtr, TAG_BYTE, TYPE_CODE_IMMEDIATE};
+use super::empty;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
+use ::util::{partial_read, partial_write};
+use ::Key;
+use std::cmp::{self, Ordering};
+use std::iter::Zip;
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::slice::{self, ChunksExact};
+
+const WORD: usize = mem::size_of::<usize>();
+
+/// The most entries the three bits of the tag for them can count.
+const MAX_COUNT: usize = 8;
+
+/// The key bytes and values of an immediate in key order, one chunk of key
+/// bytes per entry.
+pub type Iter<'a, V> = Zip<ChunksExact<'a, u8>, slice::Iter<'a, V>>;
+pub type IterMut<'a, V> = Zip<ChunksExact<'a, u8>, slice::IterMut<'a, V>>;
+
+/// An `InnerPtr` known to hold an immediate. The values sit at the end of
+/// the word away from the tag byte, so they are aligned, and the key bytes
+/// sit between them.
+#[repr(transparent)]
+pub struct Immediate<K: Key, V> {
+    word: MaybeUninit<usize>,
+    pd: PhantomData<(K, V)>
+}
+
+fn tag(len: usize, count: usize) -> u8 {
+    (TYPE_CODE_IMMEDIATE | len << 1 | (count - 1) << 4) as u8
+}
+
+impl<K: Key, V> Immediate<K, V> {
+    /// The most entries with `len` key bytes left that can be stored inline.
+    pub fn capacity(len: usize) -> usize {
+        if mem::align_of::<V>() > mem::align_of::<usize>() {
+            return 0;
+        }
+        cmp::min((WORD - 1) / (len + mem::size_of::<V>()), MAX_COUNT)
+    }
+
+    /// Whether an entry with `len` key bytes left can be stored inline.
+    pub fn fits(len: usize) -> bool {
+        Self::capacity(len) != 0
+    }
+
+    /// The most entries a leaf with `len` key bytes left is shrunk into an
+    /// immediate with, half of what one holds.
+    pub fn shrink_to(len: usize) -> usize {
+        Self::capacity(len).div_ceil(2)
+    }
+
+    /// Store an entry inline. The caller must have checked that it `fits`.
+    pub fn new_ptr(key: &[u8], value: V) -> InnerPtr<K, V> {
+        Self::single(key, value).into_ptr()
+    }
+
+    /// Store entries inline, given their key bytes packed big-endian into a
+    /// word and sorted. No entries at all give an empty pointer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more entries than `capacity` allows.
+    pub fn from_sorted<I>(len: usize, entries: I) -> InnerPtr<K, V>
+        where I: IntoIterator<Item=(usize, V)> {
+        let mut key = [0u8; WORD];
+        let key = &mut key[..len];
+        let mut immediate: Option<Immediate<K, V>> = None;
+        for (suffix, value) in entries {
+            partial_write(key, suffix);
+            match immediate {
+                Some(ref mut immediate) => {
+                    let count = immediate.count();
+                    immediate.insert_at(count, key, value);
+                },
+                None => immediate = Some(Self::single(key, value))
+            }
+        }
+        immediate.map_or_else(InnerPtr::empty, Immediate::into_ptr)
+    }
+
+    fn single(key: &[u8], value: V) -> Immediate<K, V> {
+        assert!(Self::fits(key.len()));
+        let mut immediate: Immediate<K, V> = Immediate {
+            word: MaybeUninit::uninit(),
+            pd: PhantomData
+        };
+        unsafe {
+            let bytes = immediate.word.as_mut_ptr() as *mut u8;
+            *bytes.add(TAG_BYTE) = tag(key.len(), 1);
+            ptr::copy_nonoverlapping(key.as_ptr(), bytes.add(Self::key_offset(1)), key.len());
+            ptr::write(bytes.add(Self::value_offset(1)) as *mut V, value);
+        }
+        immediate
+    }
+
+    fn into_ptr(self) -> InnerPtr<K, V> {
+        let word = self.word;
+        mem::forget(self);
+        unsafe { InnerPtr::from_word(word) }
+    }
+
+    /// Take over a word from an `InnerPtr`.
+    ///
+    /// # Safety
+    ///
+    /// The word must hold an immediate.
+    pub unsafe fn from_word(word: MaybeUninit<usize>) -> Immediate<K, V> {
+        Immediate {
+            word,
+            pd: PhantomData
+        }
+    }
+
+    #[cfg(target_endian = "little")]
+    fn key_offset(count: usize) -> usize {
+        1
+    }
+
+    #[cfg(target_endian = "little")]
+    fn value_offset(count: usize) -> usize {
+        WORD - count * mem::size_of::<V>()
+    }
+
+    #[cfg(target_endian = "big")]
+    fn key_offset(count: usize) -> usize {
+        count * mem::size_of::<V>()
+    }
+
+    #[cfg(target_endian = "big")]
+    fn value_offset(count: usize) -> usize {
+        0
+    }
+
+    fn tag(&self) -> u8 {
+        unsafe { *(self.word.as_ptr() as *const u8).add(TAG_BYTE) }
+    }
+
+    /// The number of key bytes left for each entry.
+    pub fn suffix_len(&self) -> usize {
+        (self.tag() >> 1 & 7) as usize
+    }
+
+    fn count(&self) -> usize {
+        (self.tag() >> 4) as usize + 1
+    }
+
+    /// The key bytes of every entry, one after another.
+    fn keys(&self) -> &[u8] {
+        let count = self.count();
+        unsafe {
+            let start = (self.word.as_ptr() as *const u8).add(Self::key_offset(count));
+            slice::from_raw_parts(start, count * self.suffix_len())
+        }
+    }
+
+    fn values(&self) -> &[V] {
+        let count = self.count();
+        unsafe {
+            let start = (self.word.as_ptr() as *const u8).add(Self::value_offset(count));
+            slice::from_raw_parts(start as *const V, count)
+        }
+    }
+
+    /// The key bytes alongside the values, which can be changed in place.
+    fn split_mut(&mut self) -> (&[u8], &mut [V]) {
+        let (len, count) = (self.suffix_len(), self.count());
+        unsafe {
+            let bytes = self.word.as_mut_ptr() as *mut u8;
+            let keys = slice::from_raw_parts(bytes.add(Self::key_offset(count)), count * len);
+            let values = bytes.add(Self::value_offset(count)) as *mut V;
+            (keys, slice::from_raw_parts_mut(values, count))
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.keys().chunks_exact(self.suffix_len()).zip(self.values())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        let len = self.suffix_len();
+        let (keys, values) = self.split_mut();
+        keys.chunks_exact(len).zip(values)
+    }
+
+    /// Find the position of `key` among the entries, or where it would go.
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let mut index = 0;
+        for bytes in self.keys().chunks_exact(self.suffix_len()) {
+            match bytes.cmp(key) {
+                Ordering::Less => index += 1,
+                Ordering::Equal => return Ok(index),
+                Ordering::Greater => break
+            }
+        }
+        Err(index)
+    }
+
+    /// Rebuild the word with a new entry at `index`, moving the others
+    /// across to where they belong with one more entry.
+    fn insert_at(&mut self, index: usize, key: &[u8], value: V) -> &mut V {
+        let (len, count) = (self.suffix_len(), self.count());
+        assert!(count < Self::capacity(len));
+        let old = self.word;
+        unsafe {
+            let from = old.as_ptr() as *const u8;
+            let to = self.word.as_mut_ptr() as *mut u8;
+            *to.add(TAG_BYTE) = tag(len, count + 1);
+
+            let old_keys = from.add(Self::key_offset(count));
+            let new_keys = to.add(Self::key_offset(count + 1));
+            ptr::copy_nonoverlapping(old_keys, new_keys, index * len);
+            ptr::copy_nonoverlapping(key.as_ptr(), new_keys.add(index * len), len);
+            ptr::copy_nonoverlapping(old_keys.add(index * len), new_keys.add((index + 1) * len),
+                                     (count - index) * len);
+
+            let old_values = from.add(Self::value_offset(count)) as *const V;
+            let new_values = to.add(Self::value_offset(count + 1)) as *mut V;
+            ptr::copy_nonoverlapping(old_values, new_values, index);
+            ptr::copy_nonoverlapping(old_values.add(index), new_values.add(index + 1),
+                                     count - index);
+            ptr::write(new_values.add(index), value);
+            &mut *new_values.add(index)
+        }
+    }
+
+    /// Rebuild the word without the entry at `index`, which must not be the
+    /// only one, returning its value.
+    fn remove_at(&mut self, index: usize) -> V {
+        let (len, count) = (self.suffix_len(), self.count());
+        debug_assert!(count > 1);
+        let old = self.word;
+        unsafe {
+            let from = old.as_ptr() as *const u8;
+            let to = self.word.as_mut_ptr() as *mut u8;
+            *to.add(TAG_BYTE) = tag(len, count - 1);
+
+            let old_keys = from.add(Self::key_offset(count));
+            let new_keys = to.add(Self::key_offset(count - 1));
+            ptr::copy_nonoverlapping(old_keys, new_keys, index * len);
+            ptr::copy_nonoverlapping(old_keys.add((index + 1) * len), new_keys.add(index * len),
+                                     (count - index - 1) * len);
+
+            let old_values = from.add(Self::value_offset(count)) as *const V;
+            let new_values = to.add(Self::value_offset(count - 1)) as *mut V;
+            ptr::copy_nonoverlapping(old_values, new_values, index);
+            ptr::copy_nonoverlapping(old_values.add(index + 1), new_values.add(index),
+                                     count - index - 1);
+            ptr::read(old_values.add(index))
+        }
+    }
+
+    /// Copy the entries into a new word.
+    pub fn clone_ptr(&self) -> InnerPtr<K, V> where V: Clone {
+        let entries = self.iter().map(|(bytes, value)| (partial_read(bytes), value.clone()));
+        Immediate::from_sorted(self.suffix_len(), entries)
+    }
+
+    /// Move the entries out, with their key bytes packed into words.
+    fn into_entries(self) -> Vec<(usize, V)> {
+        let entries = self.iter()
+            .map(|(bytes, value)| (partial_read(bytes), unsafe { ptr::read(value) }))
+            .collect();
+        mem::forget(self);
+        entries
+    }
+
+    /// The largest suffix that fits in the key bytes left.
+    fn max_suffix(&self) -> usize {
+        (1 << (8 * self.suffix_len())) - 1
+    }
+}
+
+impl<K: Key, V> JpmNode<K, V> for Immediate<K, V> {
+    fn get(&self, key: &[u8]) -> Option<&V> {
+        self.search(key).ok().map(|index| &self.values()[index])
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let index = self.search(key).ok()?;
+        Some(&mut self.split_mut().1[index])
+    }
+
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let (bytes, value) = self.iter().find(|&(bytes, _)| bytes >= &*key)?;
+        key.copy_from_slice(bytes);
+        Some(value)
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let (bytes, value) = self.iter().rev().find(|&(bytes, _)| bytes <= &*key)?;
+        key.copy_from_slice(bytes);
+        Some(value)
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        self.keys()
+            .chunks_exact(self.suffix_len())
+            .take_while(|&bytes| bytes < key)
+            .count()
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        self.keys()
+            .chunks_exact(self.suffix_len())
+            .filter(|bytes| bytes.starts_with(prefix))
+            .count()
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        let (bytes, value) = self.iter().nth(index)?;
+        key.copy_from_slice(bytes);
+        Some(value)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        // As in `LeafLinear`, a run of entries starting at the search key is
+        // met in order
+        let start = partial_read(key);
+        let mut suffix = start;
+        for bytes in self.keys().chunks_exact(self.suffix_len()) {
+            let entry = partial_read(bytes);
+            if entry < start {
+                continue;
+            }
+            if entry != suffix {
+                break;
+            }
+            if suffix == self.max_suffix() {
+                return false;
+            }
+            suffix += 1;
+        }
+        partial_write(key, suffix);
+        true
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        let start = partial_read(key);
+        let mut suffix = start;
+        for bytes in self.keys().chunks_exact(self.suffix_len()).rev() {
+            let entry = partial_read(bytes);
+            if entry > start {
+                continue;
+            }
+            if entry != suffix {
+                break;
+            }
+            if suffix == 0 {
+                return false;
+            }
+            suffix -= 1;
+        }
+        partial_write(key, suffix);
+        true
+    }
+
+    fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
+        match self.search(key) {
+            Ok(found) => InsertResult::replace(&mut self.split_mut().1[found], value),
+            Err(_) if self.count() == Self::capacity(key.len()) => InsertResult::Resize(value),
+            Err(insert) => {
+                self.insert_at(insert, key, value);
+                InsertResult::Success(None)
+            }
+        }
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        match self.search(key) {
+            Ok(_) => unreachable!(),
+            Err(_) if self.count() == Self::capacity(key.len()) => EntryResult::Resize(value),
+            Err(insert) => EntryResult::Success(self.insert_at(insert, key, value))
+        }
+    }
+
+    /// Promote to a real node holding every entry and the new one.
+    fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
+        let mut buffer = [0u8; WORD];
+        let bytes = &mut buffer[..key.len()];
+        let mut entries = self.into_entries().into_iter();
+        let (suffix, first) = entries.next().unwrap();
+        partial_write(bytes, suffix);
+        let mut node = empty::new_node(bytes, first);
+        for (suffix, rest) in entries {
+            partial_write(bytes, suffix);
+            node.insert(bytes, rest);
+        }
+        node.insert(key, value);
+        debug_assert_eq!(node.population(), pop);
+        node
+    }
+
+    fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
+        match self.search(key) {
+            Ok(_) if self.count() == 1 => RemoveResult::Downsize,
+            Ok(found) => RemoveResult::Success(Some(self.remove_at(found))),
+            Err(_) => RemoveResult::Success(None)
+        }
+    }
+
+    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        debug_assert_eq!(pop, 0);
+        let (_, value) = self.into_entries().pop().unwrap();
+        (InnerPtr::empty(), value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        let len = self.suffix_len();
+        let shift = 8 * (len - 1);
+        self.into_entries()
+            .into_iter()
+            .map(|(suffix, value)| {
+                let byte = (suffix >> shift) as u8;
+                if len == 1 {
+                    (byte, Child::Value(value))
+                } else {
+                    (byte, Child::Suffix(suffix & ((1 << shift) - 1), value))
+                }
+            })
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.count()
+    }
+
+    fn memory_usage(&self) -> usize {
+        0
+    }
+}
+
+impl<K: Key, V> Drop for Immediate<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.split_mut().1 as *mut [V]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use super::super::innerptr::Ref;
+    use std::cell::Cell;
+
+    thread_local!(static DROPS: Cell<usize> = const { Cell::new(0) });
+
+    /// A value small enough to be inline that counts its drops.
+    struct Counted(u16);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            DROPS.with(|drops| drops.set(drops.get() + 1));
+        }
+    }
+
+    fn drops() -> usize {
+        DROPS.with(|drops| drops.get())
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_inline() {
+        let mut ptr: InnerPtr<u32, u32> = InnerPtr::empty();
+        assert_eq!(ptr.insert(&[1, 2, 3], 123), None);
+        match ptr.as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("a single entry should be stored inline")
+        }
+        assert_eq!(ptr.target_memory_usage(), 0);
+        assert_eq!(ptr.population(), 1);
+        assert_eq!(ptr.get(&[1, 2, 3]), Some(&123));
+        assert_eq!(ptr.get(&[1, 2, 4]), None);
+        assert_eq!(ptr.insert(&[1, 2, 3], 321), Some(123));
+
+        let mut key = [0, 0, 0];
+        assert_eq!(ptr.first(&mut key), Some(&321));
+        assert_eq!(key, [1, 2, 3]);
+        assert_eq!(ptr.last(&mut [1, 2, 2]), None);
+        assert_eq!(ptr.count_below(&[1, 2, 4]), 1);
+        let mut key = [1, 2, 3];
+        assert!(ptr.first_empty(&mut key));
+        assert_eq!(key, [1, 2, 4]);
+        let mut key = [1, 2, 3];
+        assert!(ptr.last_empty(&mut key));
+        assert_eq!(key, [1, 2, 2]);
+
+        assert_eq!(ptr.remove(&[1, 2, 4]), None);
+        assert_eq!(ptr.remove(&[1, 2, 3]), Some(321));
+        assert_eq!(ptr.population(), 0);
+    }
+
+    #[test]
+    fn test_promote_and_demote() {
+        let mut ptr: InnerPtr<u32, u32> = InnerPtr::empty();
+        ptr.insert(&[7], 7);
+        ptr.insert(&[9], 9);
+        match ptr.as_ref() {
+            Ref::LeafLinear(_) => {},
+            _ => panic!("a second entry should promote the immediate")
+        }
+        assert_eq!(ptr.get(&[7]), Some(&7));
+        assert_eq!(ptr.get(&[9]), Some(&9));
+
+        assert_eq!(ptr.remove(&[7]), Some(7));
+        match ptr.as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("a leaf down to one entry should go back inline")
+        }
+        assert_eq!(ptr.get(&[9]), Some(&9));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_pack() {
+        // With no values, seven one-byte keys fill the word after the tag
+        let mut ptr: InnerPtr<u32, ()> = InnerPtr::empty();
+        for &byte in [9, 3, 7, 1, 5, 2, 8].iter() {
+            assert_eq!(ptr.insert(&[byte], ()), None);
+        }
+        match ptr.as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("seven entries should still be inline")
+        }
+        assert_eq!(ptr.population(), 7);
+        assert_eq!(ptr.get(&[5]), Some(&()));
+        assert_eq!(ptr.get(&[4]), None);
+        assert_eq!(ptr.count_below(&[6]), 4);
+        let mut key = [4];
+        assert_eq!(ptr.first(&mut key), Some(&()));
+        assert_eq!(key, [5]);
+        let mut key = [6];
+        assert_eq!(ptr.last(&mut key), Some(&()));
+        assert_eq!(key, [5]);
+        let mut key = [0];
+        assert_eq!(ptr.select(3, &mut key), Some(&()));
+        assert_eq!(key, [5]);
+        let mut key = [1];
+        assert!(ptr.first_empty(&mut key));
+        assert_eq!(key, [4]);
+        let mut key = [3];
+        assert!(ptr.last_empty(&mut key));
+        assert_eq!(key, [0]);
+
+        ptr.insert(&[4], ());
+        if let Ref::Immediate(_) = ptr.as_ref() {
+            panic!("an eighth entry should promote the immediate");
+        }
+        for &byte in [9, 3, 7, 1].iter() {
+            assert_eq!(ptr.remove(&[byte]), Some(()));
+        }
+        if let Ref::Immediate(_) = ptr.as_ref() {
+            panic!("a leaf should only shrink once half an immediate is left");
+        }
+        assert_eq!(ptr.remove(&[5]), Some(()));
+        match ptr.as_ref() {
+            Ref::Immediate(_) => {},
+            _ => panic!("three entries should go back inline")
+        }
+        for &byte in [2, 4, 8].iter() {
+            assert_eq!(ptr.get(&[byte]), Some(&()));
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_pack_suffixes() {
+        // Three entries of two key bytes each
+        let mut ptr: InnerPtr<u32, ()> = InnerPtr::empty();
+        for key in [[2, 0], [1, 3], [1, 2]].iter() {
+            ptr.insert(key, ());
+        }
+        match ptr.as_ref() {
+            Ref::Immediate(immediate) => {
+                let keys: Vec<&[u8]> = immediate.iter().map(|(key, _)| key).collect();
+                assert_eq!(keys, [&[1, 2], &[1, 3], &[2, 0]]);
+            },
+            _ => panic!("three entries should be inline")
+        }
+        assert_eq!(ptr.count_prefix(&[1]), 2);
+        let mut key = [1, 2];
+        assert!(ptr.first_empty(&mut key));
+        assert_eq!(key, [1, 4]);
+
+        ptr.insert(&[0, 0], ());
+        match ptr.as_ref() {
+            Ref::LeafMulti(_) => {},
+            _ => panic!("a fourth entry should promote the immediate")
+        }
+    }
+
+    #[test]
+    fn test_too_big() {
+        let mut ptr: InnerPtr<u32, u64> = InnerPtr::empty();
+        ptr.insert(&[1], 1);
+        if let Ref::Immediate(_) = ptr.as_ref() {
+            panic!("a whole word of value leaves no room for the key");
+        }
+
+        // a byte for the tag and one for the value leave room for six
+        let mut ptr: InnerPtr<u64, u8> = InnerPtr::empty();
+        ptr.insert(&[0; 8][..WORD - 1], 1);
+        if let Ref::Immediate(_) = ptr.as_ref() {
+            panic!("too many key bytes to be inline");
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_drop() {
+        let before = drops();
+        {
+            let mut ptr: InnerPtr<u32, Counted> = InnerPtr::empty();
+            ptr.insert(&[1, 2], Counted(1));
+            ptr.insert(&[1, 2], Counted(2));
+            assert_eq!(drops() - before, 1);
+            // promoting moves the value rather than dropping it
+            ptr.insert(&[3, 4], Counted(3));
+            assert_eq!(drops() - before, 1);
+            ptr.remove(&[1, 2]);
+            assert_eq!(drops() - before, 2);
+        }
+
+        assert_eq!(drops() - before, 3);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_drop_packed() {
+        let before = drops();
+        {
+            // Two one-byte keys with their values fit
+            let mut ptr: InnerPtr<u32, Counted> = InnerPtr::empty();
+            ptr.insert(&[5], Counted(5));
+            ptr.insert(&[1], Counted(1));
+            match ptr.as_ref() {
+                Ref::Immediate(_) => {},
+                _ => panic!("both entries should be inline")
+            }
+            assert_eq!(ptr.remove(&[5]).map(|value| value.0), Some(5));
+            assert_eq!(drops() - before, 1);
+            ptr.insert(&[0], Counted(0));
+            ptr.get_mut(&[1]).unwrap().0 = 10;
+            assert_eq!(ptr.get(&[0]).map(|value| value.0), Some(0));
+            assert_eq!(ptr.get(&[1]).map(|value| value.0), Some(10));
+            assert_eq!(drops() - before, 1);
+        }
+
+        assert_eq!(drops() - before, 3);
+    }
+}
@@ -1,89 +1,256 @@
+//! A pointer to a JPM node, packed into a single word like Judy's JPs. The
+//! low bits of the word hold a type code: nodes are allocated with enough
+//! alignment to leave them free. An empty subtree is the zero word, and an
+//! immediate keeps its entries in the word itself instead of pointing
+//! anywhere. Node type codes are even, so the lowest bit alone marks an
+//! immediate, and the rest of its tag byte is free to describe the entries.
+//!
+//! Populations are not stored here. Every node can report its own, and the
+//! branches keep theirs up to date as entries come and go.
+
 use super::empty::Empty;
+use super::immediate::Immediate;
 use super::branch_linear::BranchLinear;
 use super::branch_bitmap::BranchBitmap;
 use super::branch_uncompressed::BranchUncompressed;
 use super::leaf_linear::LeafLinear;
+use super::leaf_multi::LeafMulti;
+use super::narrow::Narrow;
 use super::leaf_bitmap::LeafBitmap;
-use super::traits::JpmNode;
-use ::rudymap::results::{InsertResult, RemoveResult};
-use ::util::{partial_write, partial_read};
+use super::traits::{JpmNode, Child};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use ::Key;
-use std::mem;
-
-#[cfg(target_pointer_width = "32")]
-pub struct Population {
-    inner: [u8; 3]
-}
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
 
-#[cfg(target_pointer_width = "64")]
-pub struct Population {
-    inner: [u8; 7]
-}
+/// Nodes are boxed with this alignment, so the low four bits of a pointer to
+/// one are always clear.
+#[repr(C, align(16))]
+struct Aligned<T>(T);
 
-impl Population {
-    fn new(value: usize) -> Population {
-        let mut pop = Population {
-            inner: Default::default()
-        };
-        partial_write(&mut pop.inner, value);
-        pop
-    }
+pub const TYPE_CODE_MASK: usize = 0xf;
+const TYPE_CODE_EMPTY: usize = 0;
+pub const TYPE_CODE_IMMEDIATE: usize = 1;
 
-    fn as_usize(&self) -> usize {
-        partial_read(&self.inner)
-    }
-}
+/// The byte of the word that holds the type code. Immediates use it for
+/// their key length and entry count as well.
+#[cfg(target_endian = "little")]
+pub const TAG_BYTE: usize = 0;
+#[cfg(target_endian = "big")]
+pub const TAG_BYTE: usize = mem::size_of::<usize>() - 1;
 
 macro_rules! make_inner_ptr {
-    ($($type:ident),+) => {
+    ($($type_code:expr => $type:ident),+) => {
+        #[repr(transparent)]
+        pub struct InnerPtr<K: Key, V> {
+            // Only an immediate can leave bytes of the word uninitialized,
+            // through padding in its value
+            word: MaybeUninit<usize>,
+            phantomdata: PhantomData<(K, V)>
+        }
+
         pub enum Ref<'a, K: Key + 'a, V: 'a> {
+            Empty(&'a Empty<K, V>),
+            Immediate(&'a Immediate<K, V>),
             $(
                 $type(&'a $type<K, V>),
             )*
         }
 
         pub enum Mut<'a, K: Key + 'a, V: 'a> {
+            Empty(&'a mut Empty<K, V>),
+            Immediate(&'a mut Immediate<K, V>),
             $(
                 $type(&'a mut $type<K, V>),
             )*
         }
 
-        pub enum InnerPtr<K: Key, V> {
+        // Nodes are only moved out of their boxes to be consumed, which
+        // moves them onto the stack anyway
+        #[allow(clippy::large_enum_variant)]
+        pub enum Owned<K: Key, V> {
+            Empty(Empty<K, V>),
+            Immediate(Immediate<K, V>),
             $(
-                $type(Box<$type<K, V>>, Population),
+                $type($type<K, V>),
             )*
         }
 
         impl<K: Key, V> InnerPtr<K, V> {
-            pub fn new<B: IntoPtr<K, V>>(boxed: Box<B>, pop: usize) -> InnerPtr<K, V> {
-                IntoPtr::into_ptr(boxed, pop)
+            pub fn new<N: IntoPtr<K, V>>(node: N) -> InnerPtr<K, V> {
+                node.into_ptr()
             }
 
-            pub fn as_ref(&self) -> Ref<K, V> {
-                match *self {
+            /// Wrap a word built elsewhere, such as an immediate.
+            ///
+            /// # Safety
+            ///
+            /// The type code in the word must describe the rest of it.
+            pub unsafe fn from_word(word: MaybeUninit<usize>) -> InnerPtr<K, V> {
+                InnerPtr {
+                    word,
+                    phantomdata: PhantomData
+                }
+            }
+
+            unsafe fn from_node<T>(node: T, type_code: usize) -> InnerPtr<K, V> {
+                let ptr = Box::into_raw(Box::new(Aligned(node))) as usize;
+                debug_assert_eq!(ptr & TYPE_CODE_MASK, 0,
+                                 "Low bits of inner ptr {:?} are set", ptr);
+                Self::from_word(MaybeUninit::new(ptr | type_code))
+            }
+
+            fn type_code(&self) -> usize {
+                // The tag byte is always initialized, even in an immediate
+                let tag = unsafe {
+                    *(self as *const Self as *const u8).add(TAG_BYTE)
+                };
+                if tag as usize & TYPE_CODE_IMMEDIATE != 0 {
+                    TYPE_CODE_IMMEDIATE
+                } else {
+                    tag as usize & TYPE_CODE_MASK
+                }
+            }
+
+            fn ptr(&self) -> *mut () {
+                // Only called for nodes, whose words are plain pointers
+                (unsafe { self.word.assume_init() } & !TYPE_CODE_MASK) as *mut ()
+            }
+
+            pub fn as_ref(&self) -> Ref<'_, K, V> {
+                unsafe {
+                    match self.type_code() {
+                        TYPE_CODE_EMPTY => {
+                            Ref::Empty(&*(self as *const Self as *const Empty<K, V>))
+                        },
+                        TYPE_CODE_IMMEDIATE => {
+                            Ref::Immediate(&*(self as *const Self as *const Immediate<K, V>))
+                        },
+                        $(
+                            $type_code => Ref::$type(&*(self.ptr() as *const $type<K, V>)),
+                        )*
+                        x => panic!("Unknown type code in inner pointer: {}", x)
+                    }
+                }
+            }
+
+            pub fn as_mut(&mut self) -> Mut<'_, K, V> {
+                unsafe {
+                    match self.type_code() {
+                        TYPE_CODE_EMPTY => {
+                            Mut::Empty(&mut *(self as *mut Self as *mut Empty<K, V>))
+                        },
+                        TYPE_CODE_IMMEDIATE => {
+                            Mut::Immediate(&mut *(self as *mut Self as *mut Immediate<K, V>))
+                        },
+                        $(
+                            $type_code => Mut::$type(&mut *(self.ptr() as *mut $type<K, V>)),
+                        )*
+                        x => panic!("Unknown type code in inner pointer: {}", x)
+                    }
+                }
+            }
+
+            pub fn into_owned(self) -> Owned<K, V> {
+                let type_code = self.type_code();
+                let word = self.word;
+                mem::forget(self);
+                unsafe {
+                    match type_code {
+                        TYPE_CODE_EMPTY => Owned::Empty(Empty::new()),
+                        TYPE_CODE_IMMEDIATE => Owned::Immediate(Immediate::from_word(word)),
+                        $(
+                            $type_code => {
+                                let ptr = word.assume_init() & !TYPE_CODE_MASK;
+                                let Aligned(node) = *Box::from_raw(ptr as *mut Aligned<$type<K, V>>);
+                                Owned::$type(node)
+                            },
+                        )*
+                        x => panic!("Unknown type code in inner pointer: {}", x)
+                    }
+                }
+            }
+
+            // In general, an `InnerPtr` or array of `InnerPtr`s will be included in some other data
+            // structure which already counts its own size in a `memory_usage()` function. Thus, it
+            // is not useful for `InnerPtr` to report its own memory consumption.
+            //
+            // However, it _is_ useful to ask an `InnerPtr` about the memory consumption of its
+            // target without including the `InnerPtr` itself. This is what `target_memory_usage()`
+            // does.
+            pub fn target_memory_usage(&self) -> usize {
+                match self.as_ref() {
+                    Ref::Empty(_) | Ref::Immediate(_) => 0,
                     $(
-                        InnerPtr::$type(ref target, ..) => {
-                            Ref::$type(target)
+                        Ref::$type(target) => {
+                            // The node's own size plus the padding added by aligning it
+                            target.memory_usage()
+                                + mem::size_of::<Aligned<$type<K, V>>>()
+                                - mem::size_of::<$type<K, V>>()
                         },
                     )*
                 }
             }
+        }
 
-            pub fn as_mut(&mut self) -> Mut<K, V> {
-                match *self {
+        impl<K: Key, V> Drop for InnerPtr<K, V> {
+            fn drop(&mut self) {
+                unsafe {
+                    match self.type_code() {
+                        TYPE_CODE_EMPTY => {},
+                        TYPE_CODE_IMMEDIATE => {
+                            drop(Immediate::<K, V>::from_word(self.word));
+                        },
+                        $(
+                            // Dropped in place rather than moved out of the box
+                            $type_code => {
+                                drop(Box::from_raw(self.ptr() as *mut Aligned<$type<K, V>>));
+                            },
+                        )*
+                        x => panic!("Unknown type code in inner pointer: {}", x)
+                    }
+                }
+            }
+        }
+
+        impl<K: Key, V: Clone> Clone for InnerPtr<K, V> {
+            fn clone(&self) -> InnerPtr<K, V> {
+                match self.as_ref() {
+                    Ref::Empty(_) => InnerPtr::empty(),
+                    Ref::Immediate(immediate) => immediate.clone_ptr(),
                     $(
-                        InnerPtr::$type(ref mut target, ..) => {
-                            Mut::$type(target)},
+                        Ref::$type(node) => node.clone().into_ptr(),
                     )*
                 }
             }
+        }
+
+        pub trait IntoPtr<K: Key, V> {
+            fn into_ptr(self) -> InnerPtr<K, V>;
+        }
+
+        $(
+            impl<K: Key, V> IntoPtr<K, V> for $type<K, V> {
+                fn into_ptr(self) -> InnerPtr<K, V> {
+                    unsafe {
+                        InnerPtr::from_node(self, $type_code)
+                    }
+                }
+            }
+        )*
 
+        impl_inner_ptr_dispatch!(Empty, Immediate, $($type),*);
+    }
+}
+
+macro_rules! impl_inner_ptr_dispatch {
+    ($($type:ident),+) => {
+        impl<K: Key, V> InnerPtr<K, V> {
             pub fn get(&self, bytes: &[u8]) -> Option<&V> {
                 match self.as_ref() {
                     $(
-                        Ref::$type(target) => {
-                            target.get(bytes)
-                        },
+                        Ref::$type(target) => target.get(bytes),
                     )*
                 }
             }
@@ -91,9 +258,94 @@ macro_rules! make_inner_ptr {
             pub fn get_mut(&mut self, bytes: &[u8]) -> Option<&mut V> {
                 match self.as_mut() {
                     $(
-                        Mut::$type(target) => {
-                            target.get_mut(bytes)
-                        },
+                        Mut::$type(target) => target.get_mut(bytes),
+                    )*
+                }
+            }
+
+            pub fn first(&self, key: &mut [u8]) -> Option<&V> {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.first(key),
+                    )*
+                }
+            }
+
+            pub fn last(&self, key: &mut [u8]) -> Option<&V> {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.last(key),
+                    )*
+                }
+            }
+
+            pub fn count_below(&self, key: &[u8]) -> usize {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.count_below(key),
+                    )*
+                }
+            }
+
+            /// Count the entries whose keys start with `prefix`, which may
+            /// be empty but must be shorter than the keys.
+            pub fn count_prefix(&self, prefix: &[u8]) -> usize {
+                if prefix.is_empty() {
+                    return self.population();
+                }
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.count_prefix(prefix),
+                    )*
+                }
+            }
+
+            pub fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.select(index, key),
+                    )*
+                }
+            }
+
+            /// The number of entries in the subtree behind this pointer.
+            pub fn population(&self) -> usize {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.population(),
+                    )*
+                }
+            }
+
+            /// In debug builds, check the population a node keeps for itself
+            /// against its children. Every mutation checks each node on its
+            /// path on the way back up, and nothing else changes, so the
+            /// whole trie stays consistent.
+            fn check_population(&self) {
+                if cfg!(debug_assertions) {
+                    let (kept, counted) = match self.as_ref() {
+                        $(
+                            Ref::$type(target) => {
+                                (target.population(), target.count_population())
+                            },
+                        )*
+                    };
+                    assert_eq!(kept, counted, "node population disagrees with its children");
+                }
+            }
+
+            pub fn first_empty(&self, key: &mut [u8]) -> bool {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.first_empty(key),
+                    )*
+                }
+            }
+
+            pub fn last_empty(&self, key: &mut [u8]) -> bool {
+                match self.as_ref() {
+                    $(
+                        Ref::$type(target) => target.last_empty(key),
                     )*
                 }
             }
@@ -101,27 +353,48 @@ macro_rules! make_inner_ptr {
             pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
                 let insert_result = match self.as_mut() {
                     $(
-                        Mut::$type(target) => {
-                            target.insert(key, value)
-                        },
+                        Mut::$type(target) => target.insert(key, value),
                     )*
                 };
-                match insert_result {
+                let evicted = match insert_result {
                     InsertResult::Success(evicted) => evicted,
                     InsertResult::Resize(value) => {
                         *self = self.take().expand(key, value);
                         None
                     }
+                };
+                self.check_population();
+                evicted
+            }
+
+            /// Insert an entry for `key`, which must not be present yet,
+            /// returning the slot the value went into. Only a node that has
+            /// to be rebuilt is searched again, since rebuilding can move
+            /// values that live inline.
+            pub fn insert_entry(&mut self, key: &[u8], value: V) -> &mut V {
+                // Returning the slot from one arm and replacing the node in
+                // the other is more than the borrow checker can follow
+                let this: *mut InnerPtr<K, V> = self;
+                let result = match unsafe { &mut *this }.as_mut() {
+                    $(
+                        Mut::$type(target) => target.insert_entry(key, value),
+                    )*
+                };
+                match result {
+                    EntryResult::Success(slot) => slot,
+                    EntryResult::Resize(value) => {
+                        *self = self.take().expand(key, value);
+                        self.check_population();
+                        self.get_mut(key).unwrap()
+                    }
                 }
             }
 
             fn expand(self, key: &[u8], value: V) -> InnerPtr<K, V> {
-                match self {
+                let new_pop = self.population() + 1;
+                match self.into_owned() {
                     $(
-                        InnerPtr::$type(target, pop) => {
-                            let new_pop = pop.as_usize() + 1;
-                            target.expand(new_pop, key, value)
-                        },
+                        Owned::$type(target) => target.expand(new_pop, key, value),
                     )*
                 }
             }
@@ -129,77 +402,64 @@ macro_rules! make_inner_ptr {
             pub fn remove(&mut self, key: &[u8]) -> Option<V> {
                 let remove_result = match self.as_mut() {
                     $(
-                        Mut::$type(target) => {
-                            target.remove(key)
-                        },
+                        Mut::$type(target) => target.remove(key),
                     )*
                 };
-                match remove_result {
+                let evicted = match remove_result {
                     RemoveResult::Success(evicted) => evicted,
                     RemoveResult::Downsize => {
                         let (ptr, value) = self.take().shrink_remove(key);
                         *self = ptr;
                         Some(value)
                     }
-                }
+                };
+                self.check_population();
+                evicted
             }
 
             fn shrink_remove(self, key: &[u8]) -> (InnerPtr<K, V>, V) {
-                match self {
+                let new_pop = self.population() - 1;
+                match self.into_owned() {
                     $(
-                        InnerPtr::$type(target, pop) => {
-                            let new_pop = pop.as_usize() - 1;
-                            target.shrink_remove(new_pop, key)
-                        },
+                        Owned::$type(target) => target.shrink_remove(new_pop, key),
                     )*
                 }
             }
 
-            pub fn take(&mut self) -> InnerPtr<K, V> {
-                ::std::mem::replace(self, InnerPtr::default())
-            }
-
-            // In general, an `InnerPtr` or array of `InnerPtr`s will be included in some other data
-            // structure which already counts its own size in a `memory_usage()` function. Thus, it
-            // is not useful for `InnerPtr` to report its own memory consumption.
-            //
-            // However, it _is_ useful to ask an `InnerPtr` about the memory consumption of its
-            // target without including the `InnerPtr` itself. This is what `target_memory_usage()`
-            // does.
-            pub fn target_memory_usage(&self) -> usize {
-                match *self {
+            pub fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+                match self.into_owned() {
                     $(
-                        InnerPtr::$type(ref target, ref _pop) => {
-                            target.memory_usage()
-                        },
+                        Owned::$type(target) => target.into_children(),
                     )*
                 }
             }
         }
-
-
-        pub trait IntoPtr<K: Key, V> {
-            fn into_ptr(from: Box<Self>, pop: usize) -> InnerPtr<K, V>;
-        }
-
-        $(
-            impl<K: Key, V> IntoPtr<K, V> for $type<K, V> {
-                fn into_ptr(from: Box<Self>, pop: usize) -> InnerPtr<K, V> {
-                    InnerPtr::$type(from, Population::new(pop))
-                }
-            }
-        )*
     }
 }
 
-make_inner_ptr!(Empty,
-                BranchLinear, BranchBitmap, BranchUncompressed,
-                LeafLinear, LeafBitmap);
+make_inner_ptr!(2 => BranchLinear,
+                4 => BranchBitmap,
+                6 => BranchUncompressed,
+                8 => Narrow,
+                10 => LeafLinear,
+                12 => LeafMulti,
+                14 => LeafBitmap);
 
 impl<K: Key, V> InnerPtr<K, V> {
     pub fn empty() -> InnerPtr<K, V> {
-        // Empty is a ZST, so this does not actually allocate
-        InnerPtr::new(Box::new(Empty::new()), 0)
+        unsafe {
+            Self::from_word(MaybeUninit::new(TYPE_CODE_EMPTY))
+        }
+    }
+
+    pub fn take(&mut self) -> InnerPtr<K, V> {
+        mem::take(self)
+    }
+
+    /// Whether removing `key` would leave this subtree empty. Only a subtree
+    /// holding a single entry needs the lookup.
+    pub fn is_last(&self, key: &[u8]) -> bool {
+        self.population() == 1 && self.get(key).is_some()
     }
 }
 
@@ -211,21 +471,46 @@ impl<K: Key, V> Default for InnerPtr<K, V> {
 
 #[cfg(test)]
 mod test {
-    use super::InnerPtr;
-    use super::BranchLinear;
-    use super::BranchBitmap;
-    use super::BranchUncompressed;
-    use super::LeafLinear;
-    use super::LeafBitmap;
+    use super::*;
 
     #[test]
     fn test_new() {
         type WordInnerPtr = InnerPtr<usize, usize>;
         WordInnerPtr::empty();
-        WordInnerPtr::new(Box::new(BranchLinear::new()), 0);
-        WordInnerPtr::new(Box::new(BranchBitmap::new()), 0);
-        WordInnerPtr::new(Box::new(BranchUncompressed::new()), 0);
-        //WordInnerPtr::new(Box::new(LeafLinear::new()), 0);
-        //WordInnerPtr::new(Box::new(LeafBitmap::new()), 0);
+        WordInnerPtr::new(BranchLinear::new());
+        WordInnerPtr::new(BranchBitmap::new());
+        WordInnerPtr::new(BranchUncompressed::new());
+        WordInnerPtr::new(LeafLinear::new());
+        WordInnerPtr::new(LeafBitmap::new());
+    }
+
+    #[test]
+    fn test_one_word() {
+        assert_eq!(mem::size_of::<InnerPtr<u64, u64>>(), mem::size_of::<usize>());
+        assert_eq!(mem::size_of::<InnerPtr<u64, [u8; 100]>>(), mem::size_of::<usize>());
+
+        let ptr: InnerPtr<u32, u32> = InnerPtr::new(BranchUncompressed::new());
+        match ptr.as_ref() {
+            Ref::BranchUncompressed(_) => {},
+            _ => panic!("the type code should survive the round trip")
+        }
+        assert_eq!(ptr.population(), 0);
+    }
+
+    #[test]
+    fn test_clone_uncompressed() {
+        // Build the branch directly rather than inserting enough to grow one
+        let mut branch: BranchUncompressed<u16, String> = BranchUncompressed::new();
+        for key in 0..1000u16 {
+            branch.insert(&key.to_be_bytes(), key.to_string()).success();
+        }
+        let ptr = InnerPtr::new(branch);
+        let copy = ptr.clone();
+        drop(ptr);
+        assert_eq!(copy.population(), 1000);
+        copy.check_population();
+        for key in 0..1000u16 {
+            assert_eq!(copy.get(&key.to_be_bytes()), Some(&key.to_string()));
+        }
     }
 }
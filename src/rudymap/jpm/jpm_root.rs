@@ -1,12 +1,24 @@
 use std::iter::FromIterator;
 use std::mem;
-use rudymap::root_leaf::RootLeaf;
+use std::ops::Bound;
+use std::vec;
+use rudymap::root_leaf::{RootLeaf, VecLeaf};
 use super::innerptr::InnerPtr;
-use super::traits::JpmNode;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use super::build::Sorted;
+use super::merge;
+use super::traits::{JpmNode, Child};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use ::rudymap::rootptr::RootPtr;
 use ::Key;
+use ::key::{lower_bytes, upper_bytes};
+use ::util::partial_write;
 
+/// Once a removal leaves this few entries, the map collapses back into a
+/// `VecLeaf`.
+pub const SHRINK_TO_LEAF: usize = 15;
+
+#[derive(Clone)]
+#[repr(align(8))]
 pub struct Jpm<K: Key, V> {
     head: InnerPtr<K, V>,
     len: usize
@@ -19,6 +31,47 @@ impl<K: Key, V> Jpm<K, V> {
             len: 0
         }
     }
+
+    /// Build a trie from every entry left in `sorted`.
+    pub fn from_sorted<I: Iterator<Item=(K, V)>>(sorted: &mut Sorted<K, V, I>) -> Jpm<K, V> {
+        let head = sorted.build();
+        Jpm {
+            len: head.population(),
+            head
+        }
+    }
+
+    pub fn head(&self) -> &InnerPtr<K, V> {
+        &self.head
+    }
+
+    pub fn head_mut(&mut self) -> &mut InnerPtr<K, V> {
+        &mut self.head
+    }
+
+    /// The number of key bytes the trie branches on.
+    fn key_len() -> usize {
+        K::default().into_bytes().as_ref().len()
+    }
+
+    /// Move every entry of `other` in, keeping this trie's values for keys
+    /// both have.
+    pub fn union_with(&mut self, other: Jpm<K, V>) {
+        self.head = merge::union(self.head.take(), other.head, Self::key_len());
+        self.len = self.head.population();
+    }
+
+    /// Keep only the entries whose keys `other` also has.
+    pub fn intersect_with<W>(&mut self, other: &Jpm<K, W>) {
+        self.head = merge::intersection(self.head.take(), &other.head, Self::key_len());
+        self.len = self.head.population();
+    }
+
+    /// Remove the entries whose keys `other` has.
+    pub fn difference_with<W>(&mut self, other: &Jpm<K, W>) {
+        self.head = merge::difference(self.head.take(), &other.head, Self::key_len());
+        self.len = self.head.population();
+    }
 }
 
 impl<K: Key, V> RootLeaf<K, V> for Jpm<K, V> {
@@ -32,9 +85,92 @@ impl<K: Key, V> RootLeaf<K, V> for Jpm<K, V> {
         self.head.get_mut(bytes.as_ref())
     }
 
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+        let mut bytes = lower_bytes(from)?;
+        let value = self.head.first(bytes.as_mut())?;
+        Some((K::from_bytes(bytes), value))
+    }
+
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+        let mut bytes = upper_bytes(to)?;
+        let value = self.head.last(bytes.as_mut())?;
+        Some((K::from_bytes(bytes), value))
+    }
+
+    fn first_empty(&self, from: Bound<K>) -> Option<K> {
+        let mut bytes = lower_bytes(from)?;
+        // Rounding past bytes that are not a key can land on a present key,
+        // so search again from there
+        loop {
+            if !self.head.first_empty(bytes.as_mut()) {
+                return None;
+            }
+            let found = bytes.clone();
+            if !K::round_up(bytes.as_mut()) {
+                return None;
+            }
+            if bytes.as_ref() == found.as_ref() {
+                return Some(K::from_bytes(bytes));
+            }
+        }
+    }
+
+    fn last_empty(&self, to: Bound<K>) -> Option<K> {
+        let mut bytes = upper_bytes(to)?;
+        loop {
+            if !self.head.last_empty(bytes.as_mut()) {
+                return None;
+            }
+            let found = bytes.clone();
+            if !K::round_down(bytes.as_mut()) {
+                return None;
+            }
+            if bytes.as_ref() == found.as_ref() {
+                return Some(K::from_bytes(bytes));
+            }
+        }
+    }
+
+    fn count(&self, from: Bound<K>, to: Bound<K>) -> usize {
+        let upper = match upper_bytes(to) {
+            Some(upper) => upper,
+            None => return 0
+        };
+        let mut through_upper = self.head.count_below(upper.as_ref());
+        if self.head.get(upper.as_ref()).is_some() {
+            through_upper += 1;
+        }
+        let below_lower = match lower_bytes(from) {
+            Some(lower) => self.head.count_below(lower.as_ref()),
+            None => return 0
+        };
+        through_upper.saturating_sub(below_lower)
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        self.head.count_prefix(prefix)
+    }
+
+    fn nth(&self, index: usize) -> Option<(K, &V)> {
+        let mut bytes = K::default().into_bytes();
+        let value = self.head.select(index, bytes.as_mut())?;
+        Some((K::from_bytes(bytes), value))
+    }
+
     fn insert(&mut self, key: K, value: V) -> InsertResult<V> {
         let bytes = key.into_bytes();
-        InsertResult::Success(self.head.insert(bytes.as_ref(), value))
+        let evicted = self.head.insert(bytes.as_ref(), value);
+        if evicted.is_none() {
+            self.len += 1;
+        }
+        debug_assert_eq!(self.len, self.head.population());
+        InsertResult::Success(evicted)
+    }
+
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V> {
+        let bytes = key.into_bytes();
+        self.len += 1;
+        EntryResult::Success(self.head.insert_entry(bytes.as_ref(), value))
     }
 
     fn expand(mut self, key: K, value: V) -> RootPtr<K, V> {
@@ -44,11 +180,22 @@ impl<K: Key, V> RootLeaf<K, V> for Jpm<K, V> {
 
     fn remove(&mut self, key: K) -> RemoveResult<V> {
         let bytes = key.into_bytes();
-        RemoveResult::Success(self.head.remove(bytes.as_ref()))
+        if self.len <= SHRINK_TO_LEAF + 1 && self.head.get(bytes.as_ref()).is_some() {
+            return RemoveResult::Downsize;
+        }
+        let evicted = self.head.remove(bytes.as_ref());
+        if evicted.is_some() {
+            self.len -= 1;
+        }
+        debug_assert_eq!(self.len, self.head.population());
+        RemoveResult::Success(evicted)
     }
 
-    fn shrink_remove(self, key: K) -> (RootPtr<K, V>, V) {
-        unimplemented!();
+    fn shrink_remove(mut self, key: K) -> (RootPtr<K, V>, V) {
+        let bytes = key.into_bytes();
+        let value = self.head.remove(bytes.as_ref()).unwrap();
+        let leaf: VecLeaf<K, V> = self.into_iter().collect();
+        (Box::new(leaf).into(), value)
     }
 
     fn len(&self) -> usize {
@@ -59,6 +206,58 @@ impl<K: Key, V> RootLeaf<K, V> for Jpm<K, V> {
     }
 }
 
+impl<K: Key, V> IntoIterator for Jpm<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut key = K::default().into_bytes();
+        key.as_mut().fill(0);
+        IntoIter {
+            key,
+            stack: vec![self.head.into_children().into_iter()]
+        }
+    }
+}
+
+/// An owning iterator over a `Jpm`. Nodes are taken apart as the iterator
+/// reaches them, so memory is given back while iterating.
+pub struct IntoIter<K: Key, V> {
+    key: K::Bytes,
+    // One iterator over the remaining children for every level of the key
+    stack: Vec<vec::IntoIter<(u8, Child<K, V>)>>
+}
+
+impl<K: Key, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let child = self.stack.last_mut()?.next();
+            let depth = self.stack.len() - 1;
+            match child {
+                Some((byte, Child::Value(value))) => {
+                    self.key.as_mut()[depth] = byte;
+                    return Some((K::from_bytes(self.key.clone()), value));
+                },
+                Some((byte, Child::Suffix(rest, value))) => {
+                    let key = self.key.as_mut();
+                    key[depth] = byte;
+                    partial_write(&mut key[depth + 1..], rest);
+                    return Some((K::from_bytes(self.key.clone()), value));
+                },
+                Some((byte, Child::Node(node))) => {
+                    self.key.as_mut()[depth] = byte;
+                    self.stack.push(node.into_children().into_iter());
+                },
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
 impl<K: Key, V> FromIterator<(K, V)> for Jpm<K, V> {
     fn from_iter<I>(iter: I) -> Self where I: IntoIterator<Item=(K, V)> {
         let mut jpm = Jpm::new();
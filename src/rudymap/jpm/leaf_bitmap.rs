@@ -1,21 +1,27 @@
-/// # Plans
-/// Current this uses raw bit operations to provide a packed representation
-/// with an interface similar to [Option<T>; N]. Once integer generics land,
-/// it can instead use an abstraction layer to provide operations. Hopefully
-/// that can be maintained as a separate crate.
+//! # Plans
+//! Current this uses raw bit operations to provide a packed representation
+//! with an interface similar to [Option<T>; N]. Once integer generics land,
+//! it can instead use an abstraction layer to provide operations. Hopefully
+//! that can be maintained as a separate crate.
 
 use std::marker::PhantomData;
-use std::mem;
+use std::mem::{self, MaybeUninit};
+use std::iter::Map;
 use std::ptr;
-use super::traits::JpmNode;
-use super::innerptr::InnerPtr;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use std::slice;
+use super::traits::{JpmNode, Child};
+use super::innerptr::{InnerPtr, IntoPtr};
+use super::leaf_linear::LeafLinear;
+use super::immediate::Immediate;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use ::Key;
-use nodrop::NoDrop;
+use ::util::Masked;
+use std::iter::FromIterator;
 
 pub struct LeafBitmap<K: Key, V> {
     keys: [u8; 256 / 8],
-    values: NoDrop<[V; 256]>,
+    /// Only the slots whose bits are set in `keys` are initialized
+    values: MaybeUninit<[V; 256]>,
     pd: PhantomData<K>
 }
 
@@ -23,23 +29,45 @@ impl<K: Key, V> LeafBitmap<K, V> {
     pub fn new() -> LeafBitmap<K, V> {
         LeafBitmap {
             keys: [0; 256 / 8],
-            values: unsafe { mem::zeroed() },
+            values: MaybeUninit::uninit(),
             pd: PhantomData
         }
     }
 }
 
+/// The key bytes and values of a `LeafBitmap` in key order.
+pub type Iter<'a, V> = Map<Masked<slice::Iter<'a, MaybeUninit<V>>>,
+                           fn((u8, &'a MaybeUninit<V>)) -> (u8, &'a V)>;
+pub type IterMut<'a, V> = Map<Masked<slice::IterMut<'a, MaybeUninit<V>>>,
+                              fn((u8, &'a mut MaybeUninit<V>)) -> (u8, &'a mut V)>;
+
 impl<K: Key, V> Drop for LeafBitmap<K, V> {
     fn drop(&mut self) {
         for index in 0..256 {
             let occupied = self.keys[index / 8] & (1 << (index % 8));
             if occupied != 0 {
-                let mut value = &mut self.values[index];
                 unsafe {
-                    ptr::drop_in_place(value as *mut V);
+                    ptr::drop_in_place(self.slot(index));
+                }
+            }
+        }
+    }
+}
+
+impl<K: Key, V: Clone> Clone for LeafBitmap<K, V> {
+    fn clone(&self) -> LeafBitmap<K, V> {
+        let mut leaf = LeafBitmap::new();
+        for index in 0..256 {
+            let bit = 1 << (index % 8);
+            if self.keys[index / 8] & bit != 0 {
+                unsafe {
+                    ptr::write(leaf.slot(index), self.value(index).clone());
                 }
+                // Only marked once written, in case cloning panics
+                leaf.keys[index / 8] |= bit;
             }
         }
+        leaf
     }
 }
 
@@ -60,38 +88,294 @@ fn singleton_index(key: &[u8], keys: &[u8; 256 / 8]) -> Place {
     }
 }
 
+/// Find the lowest set bit at or above `from`.
+fn next_set(bits: &[u8; 256 / 8], from: usize) -> Option<usize> {
+    let mut index = from / 8;
+    let mut byte = bits[index] & (0xff << (from % 8));
+    loop {
+        if byte != 0 {
+            return Some(index * 8 + byte.trailing_zeros() as usize);
+        }
+        index += 1;
+        if index == bits.len() {
+            return None;
+        }
+        byte = bits[index];
+    }
+}
+
+/// Find the highest set bit at or below `from`.
+fn prev_set(bits: &[u8; 256 / 8], from: usize) -> Option<usize> {
+    let mut index = from / 8;
+    let mut byte = bits[index] & (0xff >> (7 - from % 8));
+    loop {
+        if byte != 0 {
+            return Some(index * 8 + 7 - byte.leading_zeros() as usize);
+        }
+        if index == 0 {
+            return None;
+        }
+        index -= 1;
+        byte = bits[index];
+    }
+}
+
+/// Find the lowest clear bit at or above `from`.
+fn next_clear(bits: &[u8; 256 / 8], from: usize) -> Option<usize> {
+    let mut index = from / 8;
+    let mut byte = !bits[index] & (0xff << (from % 8));
+    loop {
+        if byte != 0 {
+            return Some(index * 8 + byte.trailing_zeros() as usize);
+        }
+        index += 1;
+        if index == bits.len() {
+            return None;
+        }
+        byte = !bits[index];
+    }
+}
+
+/// Find the highest clear bit at or below `from`.
+fn prev_clear(bits: &[u8; 256 / 8], from: usize) -> Option<usize> {
+    let mut index = from / 8;
+    let mut byte = !bits[index] & (0xff >> (7 - from % 8));
+    loop {
+        if byte != 0 {
+            return Some(index * 8 + 7 - byte.leading_zeros() as usize);
+        }
+        if index == 0 {
+            return None;
+        }
+        index -= 1;
+        byte = !bits[index];
+    }
+}
+
+impl<K: Key, V> LeafBitmap<K, V> {
+    /// The slot for the value under key byte `index`, which may not hold one
+    /// yet.
+    fn slot(&mut self, index: usize) -> *mut V {
+        debug_assert!(index < 256);
+        unsafe { (self.values.as_mut_ptr() as *mut V).add(index) }
+    }
+
+    /// The value under key byte `index`, which must be occupied.
+    fn value(&self, index: usize) -> &V {
+        debug_assert!(self.is_occupied(index));
+        unsafe { &*(self.values.as_ptr() as *const V).add(index) }
+    }
+
+    /// Once a removal leaves this few entries, the leaf shrinks back to a
+    /// `LeafLinear`: half of what one holds, so that churn at the boundary
+    /// does not convert the leaf back and forth.
+    fn shrink_to_linear() -> usize {
+        LeafLinear::<K, V>::capacity() / 2
+    }
+
+    fn is_occupied(&self, index: usize) -> bool {
+        self.keys[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn len(&self) -> usize {
+        self.keys.iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    fn take(&mut self, index: usize) -> V {
+        let value = unsafe { ptr::read(self.slot(index)) };
+        self.keys[index / 8] &= !(1 << (index % 8));
+        value
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        let slots = unsafe {
+            slice::from_raw_parts(self.values.as_ptr() as *const MaybeUninit<V>, 256)
+        };
+        // Only the slots whose bits are set are visited
+        fn init<V>((key, value): (u8, &MaybeUninit<V>)) -> (u8, &V) {
+            (key, unsafe { value.assume_init_ref() })
+        }
+        Masked::new(self.keys, 0, slots.iter()).map(init as _)
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        let slots = unsafe {
+            slice::from_raw_parts_mut(self.values.as_mut_ptr() as *mut MaybeUninit<V>, 256)
+        };
+        fn init<V>((key, value): (u8, &mut MaybeUninit<V>)) -> (u8, &mut V) {
+            (key, unsafe { value.assume_init_mut() })
+        }
+        Masked::new(self.keys, 0, slots.iter_mut()).map(init as _)
+    }
+
+    /// The occupied key bytes, a bit each.
+    pub fn bitmap(&self) -> &[u8; 256 / 8] {
+        &self.keys
+    }
+
+    /// Move in the entries of `other` whose keys are missing here, a byte
+    /// of the bitmaps at a time. Entries under keys both leaves have are
+    /// left in `other`.
+    pub fn absorb(&mut self, other: &mut LeafBitmap<K, V>) {
+        for index in 0..self.keys.len() {
+            let mut moved = other.keys[index] & !self.keys[index];
+            self.keys[index] |= moved;
+            other.keys[index] &= !moved;
+            while moved != 0 {
+                let key = index * 8 + moved.trailing_zeros() as usize;
+                unsafe {
+                    ptr::write(self.slot(key), ptr::read(other.slot(key)));
+                }
+                moved &= moved - 1;
+            }
+        }
+    }
+
+    /// Drop every entry whose key's bit is clear in `mask`.
+    pub fn retain(&mut self, mask: &[u8; 256 / 8]) {
+        for (index, &mask) in mask.iter().enumerate() {
+            let mut dropped = self.keys[index] & !mask;
+            self.keys[index] &= mask;
+            while dropped != 0 {
+                let key = index * 8 + dropped.trailing_zeros() as usize;
+                unsafe {
+                    ptr::drop_in_place(self.slot(key));
+                }
+                dropped &= dropped - 1;
+            }
+        }
+    }
+
+    /// Move the entries out in key order.
+    fn into_entries(mut self) -> Vec<(u8, V)> {
+        let mut entries = Vec::new();
+        for index in 0..256 {
+            if self.is_occupied(index) {
+                entries.push((index as u8, self.take(index)));
+            }
+        }
+        entries
+    }
+}
+
 impl<K: Key, V> JpmNode<K, V> for LeafBitmap<K, V> {
     fn get(&self, key: &[u8]) -> Option<&V> {
         match singleton_index(key, &self.keys) {
-            Place::Occupied(index) => Some(&self.values[index]),
+            Place::Occupied(index) => Some(self.value(index)),
             Place::Empty(index) => None
         }
     }
 
     fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
         match singleton_index(key, &self.keys) {
-            Place::Occupied(index) => Some(&mut self.values[index]),
+            Place::Occupied(index) => Some(unsafe { &mut *self.slot(index) }),
             Place::Empty(index) => None
         }
     }
 
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        next_set(&self.keys, key[0] as usize).map(|index| {
+            key[0] = index as u8;
+            self.value(index)
+        })
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        prev_set(&self.keys, key[0] as usize).map(|index| {
+            key[0] = index as u8;
+            self.value(index)
+        })
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        debug_assert_eq!(key.len(), 1);
+        let index = key[0] as usize;
+        let whole: u32 = self.keys[..index / 8]
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum();
+        let partial = self.keys[index / 8] & !(0xff << (index % 8));
+        (whole + partial.count_ones()) as usize
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        unreachable!("one byte keys have no shorter prefix")
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        let mut remaining = index;
+        for (position, &byte) in self.keys.iter().enumerate() {
+            let ones = byte.count_ones() as usize;
+            if remaining < ones {
+                let mut bits = byte;
+                for _ in 0..remaining {
+                    bits &= bits - 1;
+                }
+                let found = position * 8 + bits.trailing_zeros() as usize;
+                key[0] = found as u8;
+                return Some(self.value(found));
+            }
+            remaining -= ones;
+        }
+        None
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        debug_assert_eq!(key.len(), 1);
+        match next_clear(&self.keys, key[0] as usize) {
+            Some(index) => {
+                key[0] = index as u8;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        debug_assert_eq!(key.len(), 1);
+        match prev_clear(&self.keys, key[0] as usize) {
+            Some(index) => {
+                key[0] = index as u8;
+                true
+            },
+            None => false
+        }
+    }
+
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
         match singleton_index(key, &self.keys) {
             Place::Occupied(index) => {
-                let place = &mut self.values[index];
+                let place = unsafe { &mut *self.slot(index) };
                 InsertResult::replace(place, value)
             },
             Place::Empty(index) => {
-                let place = &mut self.values[index];
                 self.keys[index / 8] |= 1 << (index % 8);
                 unsafe {
-                    ptr::write(place, value);
+                    ptr::write(self.slot(index), value);
                 }
                 InsertResult::Success(None)
             }
         }
     }
 
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        match singleton_index(key, &self.keys) {
+            Place::Occupied(_) => unreachable!(),
+            Place::Empty(index) => {
+                self.keys[index / 8] |= 1 << (index % 8);
+                unsafe {
+                    ptr::write(self.slot(index), value);
+                    EntryResult::Success(&mut *self.slot(index))
+                }
+            }
+        }
+    }
+
     fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
         unreachable!();
     }
@@ -99,10 +383,10 @@ impl<K: Key, V> JpmNode<K, V> for LeafBitmap<K, V> {
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
         match singleton_index(key, &self.keys) {
             Place::Occupied(index) => {
-                let place = &mut self.values[index];
-                let value = unsafe { ptr::read(place) };
-                self.keys[index / 8] &= !(1 << (index % 8));
-                RemoveResult::Success(Some(value))
+                if self.len() <= Self::shrink_to_linear() + 1 {
+                    return RemoveResult::Downsize;
+                }
+                RemoveResult::Success(Some(self.take(index)))
             },
             Place::Empty(_) => {
                 RemoveResult::Success(None)
@@ -110,8 +394,29 @@ impl<K: Key, V> JpmNode<K, V> for LeafBitmap<K, V> {
         }
     }
 
-    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
-        unreachable!()
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let value = self.take(key[0] as usize);
+        if pop == 0 {
+            return (InnerPtr::empty(), value);
+        }
+        let entries = self.into_entries();
+        if pop <= Immediate::<K, V>::shrink_to(1) {
+            let entries = entries.into_iter().map(|(byte, value)| (byte as usize, value));
+            return (Immediate::from_sorted(1, entries), value);
+        }
+        let leaf: LeafLinear<K, V> = entries.into_iter().collect();
+        (leaf.into_ptr(), value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        self.into_entries()
+            .into_iter()
+            .map(|(byte, value)| (byte, Child::Value(value)))
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.len()
     }
 
     fn memory_usage(&self) -> usize {
@@ -119,6 +424,17 @@ impl<K: Key, V> JpmNode<K, V> for LeafBitmap<K, V> {
     }
 }
 
+impl<K: Key, V> FromIterator<(u8, V)> for LeafBitmap<K, V> {
+    fn from_iter<I>(iter: I) -> LeafBitmap<K, V>
+        where I: IntoIterator<Item=(u8, V)> {
+        let mut leaf = LeafBitmap::new();
+        for (byte, value) in iter {
+            leaf.insert(&[byte], value).success();
+        }
+        leaf
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -173,35 +489,33 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn test_memory_usage() {
-        // size should be 32 + nodrop flag + padding + 256*sizeof(V)
-        // nodrop flag + padding basically works out to an extra V, so 32 + 257*N
+        // 32 bytes of bitmap followed by room for 256 values
         {
             let lb: LeafBitmap<u32, u8> = LeafBitmap::new();
-            assert_eq!(lb.memory_usage(), 32 + 257*1);
+            assert_eq!(lb.memory_usage(), 32 + 256*1);
         }
 
         {
             let lb: LeafBitmap<u32, u16> = LeafBitmap::new();
-            assert_eq!(lb.memory_usage(), 32 + 257*2);
+            assert_eq!(lb.memory_usage(), 32 + 256*2);
         }
 
         {
             let lb: LeafBitmap<u32, u32> = LeafBitmap::new();
-            assert_eq!(lb.memory_usage(), 32 + 257*4);
+            assert_eq!(lb.memory_usage(), 32 + 256*4);
         }
 
         {
             let lb: LeafBitmap<u32, u64> = LeafBitmap::new();
-            assert_eq!(lb.memory_usage(), 32 + 257*8);
+            assert_eq!(lb.memory_usage(), 32 + 256*8);
         }
 
-        /*
-        // FIXME: why is this 34 instead of 32?
+        // with () values the leaf is nothing but the bitmap
         {
             let lb: LeafBitmap<u32, ()> = LeafBitmap::new();
             assert_eq!(lb.memory_usage(), 32);
         }
-        */
     }
-}
\ No newline at end of file
+}
@@ -1,54 +1,475 @@
-use super::traits::JpmNode;
-use super::innerptr::InnerPtr;
-use ::rudymap::results::{InsertResult, RemoveResult};
+//! A leaf for the last key byte that keeps its entries as a sorted array of
+//! key bytes alongside their values. Sparse subtrees end in one of these
+//! instead of a `LeafBitmap` with room for all 256 values.
+//!
+//! The first few entries are kept in the node itself. A leaf that outgrows
+//! them moves its entries into a boxed array of up to 31, so the many leaves
+//! holding only a few entries stay small. Values small enough that the boxed
+//! array would be no smaller than a `LeafBitmap` skip it and go straight to
+//! the bitmap.
+
+use ::util::locksteparray::{self, LockstepArray};
+use ::util::SliceExt;
+use super::traits::{JpmNode, Child};
+use super::innerptr::{InnerPtr, IntoPtr};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use ::Key;
 use super::leaf_bitmap::LeafBitmap;
+use super::immediate::Immediate;
+use std::iter::{FromIterator, Zip};
 use std::marker::PhantomData;
+use std::mem;
+use std::slice;
+
+/// The most entries a `LeafLinear` holds, once they are boxed.
+const CAPACITY: usize = 31;
+
+/// The most entries a `LeafLinear` keeps in the node itself. With six there
+/// is room for the variant tag in the space seven would take on their own.
+const INLINE_CAPACITY: usize = 6;
+
+/// Once a removal leaves this few entries in a boxed array, they move back
+/// into the node.
+const SHRINK_TO_INLINE: usize = 3;
+
+type Inline<V> = LockstepArray<[u8; INLINE_CAPACITY], [V; INLINE_CAPACITY]>;
+type Boxed<V> = LockstepArray<[u8; CAPACITY], [V; CAPACITY]>;
+
+/// The key bytes and values of a leaf, in the node or out of it.
+#[derive(Clone)]
+enum Entries<V> {
+    Inline(Inline<V>),
+    Boxed(Box<Boxed<V>>)
+}
+
+macro_rules! each {
+    ($entries:expr, $array:ident => $body:expr) => {
+        match $entries {
+            Entries::Inline($array) => $body,
+            Entries::Boxed($array) => $body
+        }
+    }
+}
+
+impl<V> Entries<V> {
+    fn len(&self) -> usize {
+        each!(self, array => array.len())
+    }
+
+    fn array1(&self) -> &[u8] {
+        each!(self, array => array.array1())
+    }
+
+    fn array2(&self) -> &[V] {
+        each!(self, array => array.array2())
+    }
+
+    fn array2_mut(&mut self) -> &mut [V] {
+        each!(self, array => array.array2_mut())
+    }
+
+    fn split_mut(&mut self) -> (&[u8], &mut [V]) {
+        each!(self, array => array.split_mut())
+    }
+
+    fn get(&self, index: usize) -> Option<(&u8, &V)> {
+        each!(self, array => array.get(index))
+    }
+
+    fn insert(&mut self, index: usize, byte: u8, value: V)
+              -> Result<(), locksteparray::InsertError<u8, V>> {
+        each!(self, array => array.insert(index, byte, value))
+    }
+
+    /// Move the entries out of the node into a boxed array.
+    fn spill(&mut self) {
+        if let Entries::Inline(ref mut array) = *self {
+            let mut boxed: Box<Boxed<V>> = Box::default();
+            for (byte, value) in mem::take(array) {
+                if boxed.push(byte, value).is_err() {
+                    unreachable!("the boxed array is the larger");
+                }
+            }
+            *self = Entries::Boxed(boxed);
+        }
+    }
 
+    fn remove(&mut self, index: usize) -> Option<(u8, V)> {
+        let removed = each!(self, array => array.remove(index));
+        if let Entries::Boxed(ref mut array) = *self {
+            if array.len() <= SHRINK_TO_INLINE {
+                let mut inline = Inline::new();
+                for (byte, value) in mem::take(&mut **array) {
+                    if inline.push(byte, value).is_err() {
+                        unreachable!("few enough entries are left to fit");
+                    }
+                }
+                *self = Entries::Inline(inline);
+            }
+        }
+        removed
+    }
+
+    fn into_vec(self) -> Vec<(u8, V)> {
+        match self {
+            Entries::Inline(array) => array.into_iter().collect(),
+            Entries::Boxed(array) => array.into_iter().collect()
+        }
+    }
+
+    /// The bytes used outside the node.
+    fn heap_usage(&self) -> usize {
+        match self {
+            Entries::Inline(_) => 0,
+            Entries::Boxed(_) => mem::size_of::<Boxed<V>>()
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct LeafLinear<K: Key, V> {
-    /*
-    keys: [u8; <K as Key>::SIZE * 4],
-    values: [V; 4],
-    */
-    pd: PhantomData<(K, V)>
+    array: Entries<V>,
+    pd: PhantomData<K>
 }
 
+/// The key bytes and values of a `LeafLinear` in key order.
+pub type Iter<'a, V> = Zip<slice::Iter<'a, u8>, slice::Iter<'a, V>>;
+pub type IterMut<'a, V> = Zip<slice::Iter<'a, u8>, slice::IterMut<'a, V>>;
+
 impl<K: Key, V> LeafLinear<K, V> {
     pub fn new() -> LeafLinear<K, V> {
-        unimplemented!();
-        /*
         LeafLinear {
-            keys: [0; K::SIZE * 4],
-            values:
+            array: Entries::Inline(LockstepArray::new()),
+            pd: PhantomData
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.array.array1().iter().zip(self.array.array2())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        let (keys, values) = self.array.split_mut();
+        keys.iter().zip(values)
+    }
+
+    /// The most entries a leaf of these values holds. They are only boxed
+    /// while the boxed array is smaller than a `LeafBitmap`.
+    pub fn capacity() -> usize {
+        if mem::size_of::<Boxed<V>>() < mem::size_of::<LeafBitmap<K, V>>() {
+            CAPACITY
+        } else {
+            INLINE_CAPACITY
+        }
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        debug_assert_eq!(key.len(), 1);
+        self.array.array1()
+            .iter()
+            .position(|&b| b == key[0])
+    }
+
+    /// Insert an entry at `index`, first moving the entries out of the node
+    /// if they fill it and the leaf can grow.
+    fn insert_at(&mut self, index: usize, byte: u8, value: V)
+                 -> Result<(), locksteparray::InsertError<u8, V>> {
+        if self.array.len() == INLINE_CAPACITY && Self::capacity() > INLINE_CAPACITY {
+            self.array.spill();
         }
-        */
+        self.array.insert(index, byte, value)
     }
 }
 
 impl<K: Key, V> JpmNode<K, V> for LeafLinear<K, V> {
     fn get(&self, key: &[u8]) -> Option<&V> {
-        unimplemented!();
+        self.position(key)
+            .map(|index| &self.array.array2()[index])
     }
 
     fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
-        unimplemented!()
+        self.position(key)
+            .map(move |index| &mut self.array.array2_mut()[index])
+    }
+
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        let index = self.array.array1()
+            .iter()
+            .position(|&b| b >= key[0])?;
+        key[0] = self.array.array1()[index];
+        Some(&self.array.array2()[index])
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        let index = self.array.array1()
+            .iter()
+            .rposition(|&b| b <= key[0])?;
+        key[0] = self.array.array1()[index];
+        Some(&self.array.array2()[index])
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        debug_assert_eq!(key.len(), 1);
+        self.array.array1()
+            .iter()
+            .take_while(|&&b| b < key[0])
+            .count()
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        unreachable!("one byte keys have no shorter prefix")
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        debug_assert_eq!(key.len(), 1);
+        let (&byte, value) = self.array.get(index)?;
+        key[0] = byte;
+        Some(value)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        debug_assert_eq!(key.len(), 1);
+        // The bytes are sorted, so a run of occupied bytes starting at the
+        // search key is met in order
+        let start = key[0];
+        let mut byte = start;
+        for &b in self.array.array1().iter().skip_while(|&&b| b < start) {
+            if b != byte {
+                break;
+            }
+            if byte == 0xff {
+                return false;
+            }
+            byte += 1;
+        }
+        key[0] = byte;
+        true
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        debug_assert_eq!(key.len(), 1);
+        let start = key[0];
+        let mut byte = start;
+        for &b in self.array.array1().iter().rev().skip_while(|&&b| b > start) {
+            if b != byte {
+                break;
+            }
+            if byte == 0 {
+                return false;
+            }
+            byte -= 1;
+        }
+        key[0] = byte;
+        true
     }
 
     fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
-        unimplemented!();
+        debug_assert_eq!(key.len(), 1);
+        match self.array.array1().linear_search(&key[0]) {
+            Ok(found) => {
+                InsertResult::replace(&mut self.array.array2_mut()[found], value)
+            },
+            Err(insert) => match self.insert_at(insert, key[0], value) {
+                Ok(()) => InsertResult::Success(None),
+                Err(locksteparray::InsertError::Overflow(_, value)) => {
+                    InsertResult::Resize(value)
+                },
+                Err(locksteparray::InsertError::OutOfBounds(..)) => {
+                    unreachable!()
+                }
+            }
+        }
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        debug_assert_eq!(key.len(), 1);
+        let insert = match self.array.array1().linear_search(&key[0]) {
+            Ok(_) => unreachable!(),
+            Err(insert) => insert
+        };
+        match self.insert_at(insert, key[0], value) {
+            Ok(()) => EntryResult::Success(&mut self.array.array2_mut()[insert]),
+            Err(locksteparray::InsertError::Overflow(_, value)) => EntryResult::Resize(value),
+            Err(locksteparray::InsertError::OutOfBounds(..)) => unreachable!()
+        }
     }
 
     fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
-        unimplemented!();
+        let mut leaf: LeafBitmap<K, V> = self.array.into_vec().into_iter().collect();
+        leaf.insert(key, value).success();
+        leaf.into_ptr()
     }
 
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
-        unimplemented!();
+        match self.position(key) {
+            Some(_) if self.array.len() - 1 <= Immediate::<K, V>::shrink_to(1) => {
+                RemoveResult::Downsize
+            },
+            Some(index) => {
+                let evicted = self.array.remove(index).map(|(_, value)| value);
+                RemoveResult::Success(evicted)
+            },
+            None => RemoveResult::Success(None)
+        }
+    }
+
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let index = self.position(key).unwrap();
+        let (_, value) = self.array.remove(index).unwrap();
+        // The entries left over, if any, go back inline
+        let rest = self.array.into_vec().into_iter().map(|(byte, value)| (byte as usize, value));
+        let ptr = Immediate::from_sorted(1, rest);
+        debug_assert_eq!(ptr.population(), pop);
+        (ptr, value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        self.array.into_vec().into_iter()
+            .map(|(byte, value)| (byte, Child::Value(value)))
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.array.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.array.heap_usage()
+    }
+}
+
+impl<K: Key, V> FromIterator<(u8, V)> for LeafLinear<K, V> {
+    fn from_iter<I>(iter: I) -> LeafLinear<K, V>
+        where I: IntoIterator<Item=(u8, V)> {
+        let mut leaf = LeafLinear::new();
+        for (byte, value) in iter {
+            let end = leaf.array.len();
+            if leaf.insert_at(end, byte, value).is_err() {
+                panic!("Too many entries for a linear leaf");
+            }
+        }
+        leaf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::innerptr::Ref;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use util::test::Droppable;
+
+    #[test]
+    fn test_drop() {
+        let drop_count = AtomicUsize::new(0);
+
+        {
+            let mut leaf: LeafLinear<u32, Droppable> = LeafLinear::new();
+            for i in 0..7 {
+                leaf.insert(&[i * 3], Droppable(&drop_count)).success();
+            }
+            assert_eq!(drop_count.load(Ordering::Acquire), 0);
+
+            // overwriting a key should drop the old value
+            leaf.insert(&[3], Droppable(&drop_count)).success();
+            assert_eq!(drop_count.load(Ordering::Acquire), 1);
+
+            // removing a key should drop its value
+            leaf.remove(&[6]).success();
+            assert_eq!(drop_count.load(Ordering::Acquire), 2);
+        }
+
+        // dropping the leaf should drop the remaining six values
+        assert_eq!(drop_count.load(Ordering::Acquire), 8);
+    }
+
+    #[test]
+    fn test_expand_and_shrink() {
+        let capacity = LeafLinear::<u32, u32>::capacity();
+        assert_eq!(capacity, CAPACITY);
+        let mut ptr: InnerPtr<u32, u32> = InnerPtr::empty();
+        for i in 0..capacity {
+            ptr.insert(&[i as u8 * 8], i as u32);
+        }
+        match ptr.as_ref() {
+            Ref::LeafLinear(leaf) => {
+                assert!(leaf.memory_usage() > mem::size_of::<LeafLinear<u32, u32>>());
+            },
+            _ => panic!("a full boxed array should still be a linear leaf")
+        }
+
+        ptr.insert(&[255], 255);
+        match ptr.as_ref() {
+            Ref::LeafBitmap(_) => {},
+            _ => panic!("one more entry should promote to a bitmap leaf")
+        }
+
+        for i in 0..capacity {
+            assert_eq!(ptr.get(&[i as u8 * 8]), Some(&(i as u32)));
+        }
+        let removed = capacity + 1 - capacity / 2;
+        for i in 0..removed {
+            assert_eq!(ptr.remove(&[i as u8 * 8]), Some(i as u32));
+        }
+        match ptr.as_ref() {
+            Ref::LeafLinear(_) => {},
+            _ => panic!("a sparse bitmap leaf should shrink to a linear leaf")
+        }
+        assert_eq!(ptr.population(), capacity / 2);
+        for i in removed..capacity {
+            assert_eq!(ptr.get(&[i as u8 * 8]), Some(&(i as u32)));
+        }
+        assert_eq!(ptr.get(&[255]), Some(&255));
+
+        // a few entries move back into the node
+        for i in removed..capacity - 2 {
+            assert_eq!(ptr.remove(&[i as u8 * 8]), Some(i as u32));
+        }
+        match ptr.as_ref() {
+            Ref::LeafLinear(leaf) => {
+                assert_eq!(leaf.memory_usage(), mem::size_of::<LeafLinear<u32, u32>>());
+            },
+            _ => panic!("three entries should stay in a linear leaf")
+        }
+        let entries: Vec<_> = match ptr.as_ref() {
+            Ref::LeafLinear(leaf) => leaf.iter().map(|(&byte, &value)| (byte, value)).collect(),
+            _ => unreachable!()
+        };
+        assert_eq!(entries, vec![(232, 29), (240, 30), (255, 255)]);
     }
 
-    fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
-        unreachable!()
+    #[test]
+    fn test_small_values_skip_the_box() {
+        // a boxed array of unit values is no smaller than a bitmap
+        assert_eq!(LeafLinear::<u32, ()>::capacity(), INLINE_CAPACITY);
+        let mut ptr: InnerPtr<u32, ()> = InnerPtr::empty();
+        for byte in 0..INLINE_CAPACITY as u8 + 2 {
+            ptr.insert(&[byte], ());
+        }
+        match ptr.as_ref() {
+            Ref::LeafBitmap(_) => {},
+            _ => panic!("a set should go from an immediate straight to a bitmap leaf")
+        }
     }
 
-    fn memory_usage(&self) -> usize {  unimplemented!()  }
+    #[test]
+    fn test_empty_search() {
+        let mut leaf: LeafLinear<u32, ()> = LeafLinear::new();
+        for &byte in [0u8, 1, 2, 5, 254, 255].iter() {
+            leaf.insert(&[byte], ()).success();
+        }
+        let mut key = [0];
+        assert!(leaf.first_empty(&mut key));
+        assert_eq!(key, [3]);
+        let mut key = [255];
+        assert!(leaf.last_empty(&mut key));
+        assert_eq!(key, [253]);
+        let mut key = [254];
+        assert!(!leaf.first_empty(&mut key));
+        let mut key = [2];
+        assert!(!leaf.last_empty(&mut key));
+    }
 }
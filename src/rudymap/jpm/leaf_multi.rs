@@ -0,0 +1,380 @@
+//! A leaf holding several remaining key bytes per entry, like Judy's
+//! `JU_LEAF2`..`JU_LEAF7`. Each entry keeps the rest of its key packed
+//! big-endian into a word, so the entries sort by key. Sparse subtrees end
+//! in one of these rather than a chain of single-child branches.
+//!
+//! When the leaf overflows it splits into a branch on the first byte its
+//! entries disagree on, behind a `Narrow` for any bytes they all share.
+
+use ::util::locksteparray::{self, LockstepArray};
+use ::util::{SliceExt, partial_read, partial_write};
+use super::traits::{JpmNode, Child};
+use super::innerptr::{InnerPtr, IntoPtr};
+use super::branch_bitmap::branch_for;
+use super::narrow::Narrow;
+use super::leaf_bitmap::LeafBitmap;
+use super::immediate::Immediate;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
+use ::Key;
+use std::iter::Zip;
+use std::marker::PhantomData;
+use std::mem;
+use std::slice;
+
+/// The most remaining key bytes a `LeafMulti` can hold.
+pub const MAX_SUFFIX_LEN: usize = mem::size_of::<usize>();
+
+/// The most entries a `LeafMulti` holds.
+pub const CAPACITY: usize = 7;
+
+#[derive(Clone)]
+pub struct LeafMulti<K: Key, V> {
+    array: LockstepArray<[usize; CAPACITY], [V; CAPACITY]>,
+    /// The number of key bytes left at this level, from 2 up to
+    /// `MAX_SUFFIX_LEN`
+    len: u8,
+    pd: PhantomData<K>
+}
+
+/// The packed suffixes and values of a `LeafMulti` in key order.
+pub type Iter<'a, V> = Zip<slice::Iter<'a, usize>, slice::Iter<'a, V>>;
+pub type IterMut<'a, V> = Zip<slice::Iter<'a, usize>, slice::IterMut<'a, V>>;
+
+/// The largest suffix that fits in `len` bytes.
+fn max_suffix(len: usize) -> usize {
+    if len == MAX_SUFFIX_LEN {
+        !0
+    } else {
+        (1 << (8 * len)) - 1
+    }
+}
+
+impl<K: Key, V> LeafMulti<K, V> {
+    pub fn new(len: usize) -> LeafMulti<K, V> {
+        debug_assert!((2..=MAX_SUFFIX_LEN).contains(&len));
+        LeafMulti {
+            array: LockstepArray::new(),
+            len: len as u8,
+            pd: PhantomData
+        }
+    }
+
+    /// Build a leaf for keys with `len` bytes left from suffixes that are
+    /// already sorted.
+    pub fn from_sorted<I>(len: usize, entries: I) -> LeafMulti<K, V>
+        where I: IntoIterator<Item=(usize, V)> {
+        let mut leaf = LeafMulti::new(len);
+        for (suffix, value) in entries {
+            if leaf.array.push(suffix, value).is_err() {
+                panic!("Too many entries for a multi-byte leaf");
+            }
+        }
+        leaf
+    }
+
+    /// The number of key bytes each suffix holds.
+    pub fn suffix_len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.array.array1().iter().zip(self.array.array2())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        let (suffixes, values) = self.array.split_mut();
+        suffixes.iter().zip(values)
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        debug_assert_eq!(key.len(), self.len as usize);
+        let suffix = partial_read(key);
+        self.array.array1()
+            .iter()
+            .position(|&s| s == suffix)
+    }
+}
+
+impl<K: Key, V> JpmNode<K, V> for LeafMulti<K, V> {
+    fn get(&self, key: &[u8]) -> Option<&V> {
+        self.position(key)
+            .map(|index| &self.array.array2()[index])
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        self.position(key)
+            .map(move |index| &mut self.array.array2_mut()[index])
+    }
+
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let suffix = partial_read(key);
+        let index = self.array.array1()
+            .iter()
+            .position(|&s| s >= suffix)?;
+        partial_write(key, self.array.array1()[index]);
+        Some(&self.array.array2()[index])
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let suffix = partial_read(key);
+        let index = self.array.array1()
+            .iter()
+            .rposition(|&s| s <= suffix)?;
+        partial_write(key, self.array.array1()[index]);
+        Some(&self.array.array2()[index])
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        let suffix = partial_read(key);
+        self.array.array1()
+            .iter()
+            .take_while(|&&s| s < suffix)
+            .count()
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let shift = 8 * (self.len as usize - prefix.len());
+        let prefix = partial_read(prefix);
+        self.array.array1()
+            .iter()
+            .filter(|&&s| s >> shift == prefix)
+            .count()
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        let (&suffix, value) = self.array.get(index)?;
+        partial_write(key, suffix);
+        Some(value)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        let start = partial_read(key);
+        let max = max_suffix(key.len());
+        let mut suffix = start;
+        for &s in self.array.array1().iter().skip_while(|&&s| s < start) {
+            if s != suffix {
+                break;
+            }
+            if suffix == max {
+                return false;
+            }
+            suffix += 1;
+        }
+        partial_write(key, suffix);
+        true
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        let start = partial_read(key);
+        let mut suffix = start;
+        for &s in self.array.array1().iter().rev().skip_while(|&&s| s > start) {
+            if s != suffix {
+                break;
+            }
+            if suffix == 0 {
+                return false;
+            }
+            suffix -= 1;
+        }
+        partial_write(key, suffix);
+        true
+    }
+
+    fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
+        let suffix = partial_read(key);
+        match self.array.array1().linear_search(&suffix) {
+            Ok(found) => {
+                InsertResult::replace(&mut self.array.array2_mut()[found], value)
+            },
+            Err(insert) => match self.array.insert(insert, suffix, value) {
+                Ok(()) => InsertResult::Success(None),
+                Err(locksteparray::InsertError::Overflow(_, value)) => {
+                    InsertResult::Resize(value)
+                },
+                Err(locksteparray::InsertError::OutOfBounds(..)) => {
+                    unreachable!()
+                }
+            }
+        }
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        let suffix = partial_read(key);
+        let insert = match self.array.array1().linear_search(&suffix) {
+            Ok(_) => unreachable!(),
+            Err(insert) => insert
+        };
+        match self.array.insert(insert, suffix, value) {
+            Ok(()) => EntryResult::Success(&mut self.array.array2_mut()[insert]),
+            Err(locksteparray::InsertError::Overflow(_, value)) => EntryResult::Resize(value),
+            Err(locksteparray::InsertError::OutOfBounds(..)) => unreachable!()
+        }
+    }
+
+    fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
+        let len = key.len();
+        let entries: Vec<(usize, V)> = self.array
+            .into_iter()
+            .chain(Some((partial_read(key), value)))
+            .collect();
+        let differing = entries.iter()
+            .fold(0, |acc, &(suffix, _)| acc | (suffix ^ entries[0].0));
+        let shared = differing.leading_zeros() as usize / 8 - (MAX_SUFFIX_LEN - len);
+
+        let mut buffer = [0u8; MAX_SUFFIX_LEN];
+        let bytes = &mut buffer[..len];
+        if shared == len - 1 {
+            // Only the last byte differs, which is a job for a leaf
+            let mut leaf: LeafBitmap<K, V> = LeafBitmap::new();
+            for (suffix, value) in entries {
+                partial_write(bytes, suffix);
+                leaf.insert(&bytes[shared..], value).success();
+            }
+            let leaf = leaf.into_ptr();
+            return Narrow::wrap(&bytes[..shared], leaf);
+        }
+
+        // Split on the first byte the entries disagree on. The children are
+        // built by inserting the rest of each key, so they pick their own
+        // node types.
+        let mut children: Vec<(u8, InnerPtr<K, V>)> = Vec::new();
+        for (suffix, value) in entries {
+            partial_write(bytes, suffix);
+            let (&byte, rest) = bytes[shared..].split_first().unwrap();
+            match children.iter().position(|&(b, _)| b == byte) {
+                Some(index) => {
+                    children[index].1.insert(rest, value);
+                },
+                None => {
+                    let mut child = InnerPtr::empty();
+                    child.insert(rest, value);
+                    children.push((byte, child));
+                }
+            }
+        }
+        children.sort_by_key(|&(byte, _)| byte);
+
+        // `bytes` still holds the last entry, which shares the prefix
+        Narrow::wrap(&bytes[..shared], branch_for(children))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
+        match self.position(key) {
+            Some(_) if self.array.len() - 1 <= Immediate::<K, V>::shrink_to(key.len()) => {
+                RemoveResult::Downsize
+            },
+            Some(index) => {
+                let evicted = self.array.remove(index).map(|(_, value)| value);
+                RemoveResult::Success(evicted)
+            },
+            None => RemoveResult::Success(None)
+        }
+    }
+
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        let index = self.position(key).unwrap();
+        let (_, value) = self.array.remove(index).unwrap();
+        // The entries left over, if any, go back inline
+        let ptr = Immediate::from_sorted(key.len(), self.array);
+        debug_assert_eq!(ptr.population(), pop);
+        (ptr, value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        let shift = 8 * (self.len as usize - 1);
+        let rest_mask = max_suffix(self.len as usize - 1);
+        self.array.into_iter()
+            .map(|(suffix, value)| {
+                ((suffix >> shift) as u8, Child::Suffix(suffix & rest_mask, value))
+            })
+            .collect()
+    }
+
+    fn population(&self) -> usize {
+        self.array.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::innerptr::Ref;
+
+    fn key(value: u64) -> [u8; 8] {
+        value.to_be_bytes()
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_split_and_collapse() {
+        let mut ptr: InnerPtr<u64, u64> = InnerPtr::empty();
+        let values: Vec<u64> = (0..8).map(|i| (i / 2) << 40 | i).collect();
+        for &value in values[..7].iter() {
+            ptr.insert(&key(value), value);
+        }
+        match ptr.as_ref() {
+            Ref::LeafMulti(_) => {},
+            _ => panic!("seven entries should fit a multi-byte leaf")
+        }
+
+        // the entries share their top two bytes, which a narrow node skips
+        ptr.insert(&key(values[7]), values[7]);
+        match ptr.as_ref() {
+            Ref::Narrow(_) => {},
+            _ => panic!("an eighth entry should split the leaf")
+        }
+        assert_eq!(ptr.population(), 8);
+        for &value in values.iter() {
+            assert_eq!(ptr.get(&key(value)), Some(&value));
+        }
+
+        for &value in values[..3].iter() {
+            assert_eq!(ptr.remove(&key(value)), Some(value));
+        }
+        assert_eq!(ptr.remove(&key(values[3])), Some(values[3]));
+        // the branch below the narrow node folds back into a leaf
+        assert_eq!(ptr.target_memory_usage(),
+                   mem::size_of::<Narrow<u64, u64>>() + mem::size_of::<LeafMulti<u64, u64>>());
+        assert_eq!(ptr.population(), 4);
+        for &value in values[4..].iter() {
+            assert_eq!(ptr.get(&key(value)), Some(&value));
+        }
+    }
+
+    #[test]
+    fn test_into_children() {
+        let mut leaf: LeafMulti<u32, char> = LeafMulti::new(3);
+        leaf.insert(&[1, 2, 3], 'a').success();
+        leaf.insert(&[0, 0, 255], 'b').success();
+        let children: Vec<_> = leaf.into_children()
+            .into_iter()
+            .map(|(byte, child)| match child {
+                Child::Suffix(rest, value) => (byte, rest, value),
+                _ => panic!("leaves give up suffixes")
+            })
+            .collect();
+        assert_eq!(children, vec![(0, 0x00ff, 'b'), (1, 0x0203, 'a')]);
+    }
+
+    #[test]
+    fn test_empty_search() {
+        let mut leaf: LeafMulti<u64, ()> = LeafMulti::new(MAX_SUFFIX_LEN);
+        for &value in [0, 1, !0 - 1, !0].iter() {
+            leaf.insert(&key(value)[8 - MAX_SUFFIX_LEN..], ()).success();
+        }
+        let mut search = key(0);
+        assert!(leaf.first_empty(&mut search[8 - MAX_SUFFIX_LEN..]));
+        assert_eq!(search, key(2));
+        let mut search = key(!0 - 1);
+        assert!(!leaf.first_empty(&mut search[8 - MAX_SUFFIX_LEN..]));
+        let mut search = key(!0);
+        assert!(leaf.last_empty(&mut search[8 - MAX_SUFFIX_LEN..]));
+        assert_eq!(search, key(!0 - 2));
+        let mut search = key(1);
+        assert!(!leaf.last_empty(&mut search[8 - MAX_SUFFIX_LEN..]));
+    }
+}
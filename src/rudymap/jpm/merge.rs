@@ -0,0 +1,594 @@
+//! Set operations on two tries at once. Both are walked together a key byte
+//! at a time, so a subtree that only one side has is moved across or dropped
+//! whole without visiting it. Branches are changed in place: two
+//! `BranchBitmap`s OR or AND their bitmaps and move child pointers across,
+//! and two `LeafBitmap`s meet a byte of their bitmaps at a time. Where two
+//! leaves meet, their entries are merged as sorted arrays.
+//!
+//! The results end up as insertions would have grown them: a lone child
+//! goes behind a `Narrow`, and a subtree left with few entries folds back
+//! into a leaf. Narrows and `BranchLinear`s hold only a handful of pointers,
+//! so they are simply rebuilt, as is a branch whose children no longer suit
+//! its type.
+
+use super::innerptr::{InnerPtr, IntoPtr, Ref, Mut, Owned};
+use super::immediate::Immediate;
+use super::branch_linear::{self, COLLAPSE_TO_LEAF};
+use super::branch_bitmap::{GROW_TO_UNCOMPRESSED, branch_for};
+use super::leaf_linear::LeafLinear;
+use super::leaf_multi::{self, LeafMulti, MAX_SUFFIX_LEN};
+use super::leaf_bitmap::LeafBitmap;
+use super::narrow::Narrow;
+use super::traits::{Child, collect_suffixes};
+use ::util::{partial_read, partial_write};
+use ::Key;
+
+/// Whether the node holds entries rather than subtrees. Leaves only appear
+/// where the rest of the key fits in a word.
+fn is_leaf<K: Key, V>(ptr: &InnerPtr<K, V>) -> bool {
+    matches!(ptr.as_ref(),
+             Ref::Immediate(_) | Ref::LeafLinear(_) | Ref::LeafMulti(_) | Ref::LeafBitmap(_))
+}
+
+/// Move every entry out of a subtree whose keys fit in a word, in key order.
+fn into_entries<K: Key, V>(ptr: InnerPtr<K, V>, len: usize) -> Vec<(usize, V)> {
+    let mut entries = Vec::with_capacity(ptr.population());
+    collect_suffixes(ptr.into_children(), len, 0, &mut entries);
+    entries
+}
+
+/// Build a subtree from entries whose keys fit in a word.
+fn from_entries<K: Key, V>(entries: Vec<(usize, V)>, len: usize) -> InnerPtr<K, V> {
+    let mut key = [0; MAX_SUFFIX_LEN];
+    let key = &mut key[..len];
+    let mut ptr = InnerPtr::empty();
+    for (suffix, value) in entries {
+        partial_write(key, suffix);
+        ptr.insert(key, value);
+    }
+    ptr
+}
+
+/// Build a subtree from entries in key order, straight into the leaf that
+/// inserting them would have grown. Too many entries for a multi-byte leaf
+/// are inserted one by one, to split the way insertions do.
+fn from_sorted<K: Key, V>(entries: Vec<(usize, V)>, len: usize) -> InnerPtr<K, V> {
+    let count = entries.len();
+    if count <= Immediate::<K, V>::capacity(len) {
+        Immediate::from_sorted(len, entries)
+    } else if len == 1 {
+        let entries = entries.into_iter().map(|(byte, value)| (byte as u8, value));
+        if count <= LeafLinear::<K, V>::capacity() {
+            entries.collect::<LeafLinear<K, V>>().into_ptr()
+        } else {
+            entries.collect::<LeafBitmap<K, V>>().into_ptr()
+        }
+    } else if count <= leaf_multi::CAPACITY {
+        LeafMulti::from_sorted(len, entries).into_ptr()
+    } else {
+        from_entries(entries, len)
+    }
+}
+
+/// Read the keys of a leaf, in order.
+fn keys<K: Key, V>(ptr: &InnerPtr<K, V>) -> Vec<usize> {
+    match ptr.as_ref() {
+        Ref::Immediate(leaf) => leaf.iter().map(|(bytes, _)| partial_read(bytes)).collect(),
+        Ref::LeafLinear(leaf) => leaf.iter().map(|(&byte, _)| byte as usize).collect(),
+        Ref::LeafMulti(leaf) => leaf.iter().map(|(&suffix, _)| suffix).collect(),
+        Ref::LeafBitmap(leaf) => leaf.iter().map(|(byte, _)| byte as usize).collect(),
+        _ => unreachable!("only leaves are read key by key")
+    }
+}
+
+/// Take apart a branch or `Narrow`, returning its subtrees in key order.
+fn into_subtrees<K: Key, V>(ptr: InnerPtr<K, V>) -> Vec<(u8, InnerPtr<K, V>)> {
+    ptr.into_children()
+        .into_iter()
+        .map(|(byte, child)| match child {
+            Child::Node(node) => (byte, node),
+            _ => unreachable!("only leaves give up entries")
+        })
+        .collect()
+}
+
+/// Replace each child of a branch in place with `merge` of it, dropping the
+/// ones left empty. Returns `false`, changing nothing, for any other node.
+fn merge_children<K: Key, V, F>(ptr: &mut InnerPtr<K, V>, mut merge: F) -> bool
+    where F: FnMut(u8, InnerPtr<K, V>) -> InnerPtr<K, V> {
+    match ptr.as_mut() {
+        Mut::BranchLinear(branch) => {
+            for (&byte, child) in branch.iter_mut() {
+                *child = merge(byte, child.take());
+            }
+            branch.prune();
+        },
+        Mut::BranchBitmap(branch) => {
+            for (byte, child) in branch.iter_mut() {
+                *child = merge(byte, child.take());
+            }
+            branch.prune();
+        },
+        Mut::BranchUncompressed(branch) => {
+            for (byte, child) in branch.iter_mut() {
+                *child = merge(byte, child.take());
+            }
+            branch.prune();
+        },
+        _ => return false
+    }
+    true
+}
+
+/// Fold a subtree that has lost entries in bulk into a leaf once it is small
+/// enough, as removing them one at a time would have.
+fn shrink<K: Key, V>(ptr: InnerPtr<K, V>, len: usize) -> InnerPtr<K, V> {
+    let pop = ptr.population();
+    if pop == 0 {
+        return InnerPtr::empty();
+    }
+    let inline = matches!(ptr.as_ref(), Ref::Immediate(_));
+    if len <= MAX_SUFFIX_LEN && pop <= COLLAPSE_TO_LEAF && !inline {
+        from_sorted(into_entries(ptr, len), len)
+    } else {
+        ptr
+    }
+}
+
+/// Put merged subtrees back under a branch, dropping any left empty.
+fn branch<K: Key, V>(mut children: Vec<(u8, InnerPtr<K, V>)>, len: usize) -> InnerPtr<K, V> {
+    children.retain(|(_, child)| child.population() != 0);
+    shrink(branch_for(children), len)
+}
+
+/// Finish a branch changed in place. It is only rebuilt if `branch_for`
+/// would pick another node for the children it has left.
+fn settle<K: Key, V>(ptr: InnerPtr<K, V>, len: usize) -> InnerPtr<K, V> {
+    let suits = match ptr.as_ref() {
+        Ref::BranchLinear(branch) => (2..=branch_linear::CAPACITY).contains(&branch.len()),
+        Ref::BranchBitmap(branch) => {
+            (branch_linear::CAPACITY + 1..=GROW_TO_UNCOMPRESSED).contains(&branch.len())
+        },
+        Ref::BranchUncompressed(branch) => branch.len() > GROW_TO_UNCOMPRESSED,
+        _ => unreachable!("only branches are changed in place")
+    };
+    if suits {
+        shrink(ptr, len)
+    } else {
+        branch(into_subtrees(ptr), len)
+    }
+}
+
+/// A subtree of the other trie, read in place. A `Narrow` is stepped through
+/// a byte at a time by keeping the part of its prefix left to match.
+struct View<'a, K: Key + 'a, W: 'a> {
+    skip: &'a [u8],
+    node: &'a InnerPtr<K, W>
+}
+
+impl<'a, K: Key + 'a, W: 'a> Clone for View<'a, K, W> {
+    fn clone(&self) -> View<'a, K, W> {
+        *self
+    }
+}
+
+impl<'a, K: Key + 'a, W: 'a> Copy for View<'a, K, W> {}
+
+impl<'a, K: Key + 'a, W: 'a> View<'a, K, W> {
+    fn new(node: &'a InnerPtr<K, W>) -> View<'a, K, W> {
+        View {
+            skip: &[],
+            node
+        }
+    }
+
+    fn population(self) -> usize {
+        self.node.population()
+    }
+
+    fn is_leaf(self) -> bool {
+        self.skip.is_empty() && is_leaf(self.node)
+    }
+
+    fn contains(self, key: &[u8]) -> bool {
+        key.starts_with(self.skip) && self.node.get(&key[self.skip.len()..]).is_some()
+    }
+
+    /// The subtree under the key byte `byte`. Never called on a leaf.
+    fn child(self, byte: u8) -> Option<View<'a, K, W>> {
+        if let Some((&first, rest)) = self.skip.split_first() {
+            return if first == byte {
+                Some(View { skip: rest, node: self.node })
+            } else {
+                None
+            };
+        }
+        match self.node.as_ref() {
+            Ref::Narrow(narrow) => {
+                let (&first, rest) = narrow.prefix().split_first().unwrap();
+                if first == byte {
+                    Some(View { skip: rest, node: narrow.child() })
+                } else {
+                    None
+                }
+            },
+            Ref::BranchLinear(branch) => branch.child(byte).map(View::new),
+            Ref::BranchBitmap(branch) => branch.child(byte).map(View::new),
+            Ref::BranchUncompressed(branch) => branch.child(byte).map(View::new),
+            _ => None
+        }
+    }
+
+    /// The keys present in a one-byte leaf, a bit each.
+    fn bitmap(self) -> [u8; 256 / 8] {
+        if let Ref::LeafBitmap(leaf) = self.node.as_ref() {
+            return *leaf.bitmap();
+        }
+        let mut bitmap = [0; 256 / 8];
+        for byte in keys(self.node) {
+            bitmap[byte / 8] |= 1 << (byte % 8);
+        }
+        bitmap
+    }
+}
+
+/// Merge two sorted runs of entries, keeping `a`'s values for keys both have.
+fn merge_sorted<V>(a: Vec<(usize, V)>, b: Vec<(usize, V)>) -> Vec<(usize, V)> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut others = b.into_iter().peekable();
+    for (suffix, value) in a {
+        while let Some(&(other, _)) = others.peek() {
+            if other > suffix {
+                break;
+            }
+            let entry = others.next().unwrap();
+            if other < suffix {
+                merged.push(entry);
+            }
+        }
+        merged.push((suffix, value));
+    }
+    merged.extend(others);
+    merged
+}
+
+/// Keep the entries whose keys are (or are not, if `keep` is false) among
+/// the sorted `keys`.
+fn filter_sorted<V>(entries: Vec<(usize, V)>, keys: &[usize], keep: bool) -> Vec<(usize, V)> {
+    let mut keys = keys.iter().peekable();
+    entries.into_iter()
+        .filter(|&(suffix, _)| {
+            while keys.next_if(|&&key| key < suffix).is_some() {}
+            (keys.peek() == Some(&&suffix)) == keep
+        })
+        .collect()
+}
+
+/// Merge `b` into `a`, keeping `a`'s values for keys both have. `len` is the
+/// number of key bytes left at this level.
+pub fn union<K: Key, V>(mut a: InnerPtr<K, V>, mut b: InnerPtr<K, V>, len: usize)
+                        -> InnerPtr<K, V> {
+    if b.population() == 0 {
+        return a;
+    }
+    if a.population() == 0 {
+        return b;
+    }
+    if let (Mut::LeafBitmap(leaf), Mut::LeafBitmap(other)) = (a.as_mut(), b.as_mut()) {
+        leaf.absorb(other);
+        return a;
+    }
+
+    if is_leaf(&a) && is_leaf(&b) {
+        return from_sorted(merge_sorted(into_entries(a, len), into_entries(b, len)), len);
+    }
+    // A leaf is merged entry by entry into a branch on the other side
+    let mut key = [0; MAX_SUFFIX_LEN];
+    if is_leaf(&b) {
+        let key = &mut key[..len];
+        for (suffix, value) in into_entries(b, len) {
+            partial_write(key, suffix);
+            if a.get(key).is_none() {
+                a.insert(key, value);
+            }
+        }
+        return a;
+    }
+    if is_leaf(&a) {
+        let key = &mut key[..len];
+        for (suffix, value) in into_entries(a, len) {
+            partial_write(key, suffix);
+            b.insert(key, value);
+        }
+        return b;
+    }
+
+    let bitmaps = matches!((a.as_ref(), b.as_ref()),
+                           (Ref::BranchBitmap(_), Ref::BranchBitmap(_)));
+    if bitmaps {
+        match (a.as_mut(), b.into_owned()) {
+            (Mut::BranchBitmap(branch), Owned::BranchBitmap(other)) => {
+                branch.union_with(other, |child, other| union(child, other, len - 1));
+            },
+            _ => unreachable!()
+        }
+        return settle(a, len);
+    }
+    // The larger branches have a slot for every subtree `b` brings
+    let merged = match a.as_mut() {
+        Mut::BranchBitmap(branch) => {
+            for (byte, other) in into_subtrees(b.take()) {
+                let slot = branch.slot(byte);
+                *slot = union(slot.take(), other, len - 1);
+            }
+            branch.prune();
+            true
+        },
+        Mut::BranchUncompressed(branch) => {
+            for (byte, other) in into_subtrees(b.take()) {
+                let slot = branch.slot(byte);
+                *slot = union(slot.take(), other, len - 1);
+            }
+            branch.prune();
+            true
+        },
+        _ => false
+    };
+    if merged {
+        return settle(a, len);
+    }
+
+    let mut others = into_subtrees(b).into_iter().peekable();
+    let mut merged = Vec::new();
+    for (byte, child) in into_subtrees(a) {
+        while let Some(&(other, _)) = others.peek() {
+            if other >= byte {
+                break;
+            }
+            merged.push(others.next().unwrap());
+        }
+        let child = match others.peek() {
+            Some(&(other, _)) if other == byte => {
+                union(child, others.next().unwrap().1, len - 1)
+            },
+            _ => child
+        };
+        merged.push((byte, child));
+    }
+    merged.extend(others);
+    branch(merged, len)
+}
+
+/// Keep the entries of `a` whose keys `b` also has.
+pub fn intersection<K: Key, V, W>(a: InnerPtr<K, V>, b: &InnerPtr<K, W>, len: usize)
+                                  -> InnerPtr<K, V> {
+    intersect(a, View::new(b), len)
+}
+
+fn intersect<K: Key, V, W>(mut a: InnerPtr<K, V>, b: View<'_, K, W>, len: usize)
+                           -> InnerPtr<K, V> {
+    if a.population() == 0 || b.population() == 0 {
+        return InnerPtr::empty();
+    }
+    if let Mut::LeafBitmap(leaf) = a.as_mut() {
+        leaf.retain(&b.bitmap());
+        return shrink(a, len);
+    }
+
+    let mut key = [0; MAX_SUFFIX_LEN];
+    if is_leaf(&a) {
+        let entries = into_entries(a, len);
+        let kept = if b.is_leaf() {
+            filter_sorted(entries, &keys(b.node), true)
+        } else {
+            let key = &mut key[..len];
+            entries.into_iter()
+                .filter(|&(suffix, _)| {
+                    partial_write(key, suffix);
+                    b.contains(key)
+                })
+                .collect()
+        };
+        return from_sorted(kept, len);
+    }
+    if b.is_leaf() {
+        // Few enough keys to pull each one out of `a`
+        let key = &mut key[..len];
+        let mut kept = Vec::new();
+        for suffix in keys(b.node) {
+            partial_write(key, suffix);
+            if let Some(value) = a.remove(key) {
+                kept.push((suffix, value));
+            }
+        }
+        return from_sorted(kept, len);
+    }
+
+    if let (Mut::BranchBitmap(branch), true) = (a.as_mut(), b.skip.is_empty()) {
+        if let Ref::BranchBitmap(other) = b.node.as_ref() {
+            branch.retain_shared(other);
+        }
+    }
+    let merged = merge_children(&mut a, |byte, child| match b.child(byte) {
+        Some(other) => intersect(child, other, len - 1),
+        None => InnerPtr::empty()
+    });
+    if merged {
+        return settle(a, len);
+    }
+
+    let mut kept = Vec::new();
+    for (byte, child) in into_subtrees(a) {
+        if let Some(other) = b.child(byte) {
+            kept.push((byte, intersect(child, other, len - 1)));
+        }
+    }
+    branch(kept, len)
+}
+
+/// Remove the entries of `a` whose keys `b` has.
+pub fn difference<K: Key, V, W>(a: InnerPtr<K, V>, b: &InnerPtr<K, W>, len: usize)
+                                -> InnerPtr<K, V> {
+    subtract(a, View::new(b), len)
+}
+
+fn subtract<K: Key, V, W>(mut a: InnerPtr<K, V>, b: View<'_, K, W>, len: usize)
+                          -> InnerPtr<K, V> {
+    if a.population() == 0 || b.population() == 0 {
+        return a;
+    }
+    if let Mut::LeafBitmap(leaf) = a.as_mut() {
+        let mut mask = b.bitmap();
+        for byte in mask.iter_mut() {
+            *byte = !*byte;
+        }
+        leaf.retain(&mask);
+        return shrink(a, len);
+    }
+
+    let mut key = [0; MAX_SUFFIX_LEN];
+    if is_leaf(&a) {
+        let entries = into_entries(a, len);
+        let kept = if b.is_leaf() {
+            filter_sorted(entries, &keys(b.node), false)
+        } else {
+            let key = &mut key[..len];
+            entries.into_iter()
+                .filter(|&(suffix, _)| {
+                    partial_write(key, suffix);
+                    !b.contains(key)
+                })
+                .collect()
+        };
+        return from_sorted(kept, len);
+    }
+    if b.is_leaf() {
+        let key = &mut key[..len];
+        for suffix in keys(b.node) {
+            partial_write(key, suffix);
+            a.remove(key);
+        }
+        return a;
+    }
+
+    let merged = merge_children(&mut a, |byte, child| match b.child(byte) {
+        Some(other) => subtract(child, other, len - 1),
+        None => child
+    });
+    if merged {
+        return settle(a, len);
+    }
+
+    let mut kept = Vec::new();
+    for (byte, child) in into_subtrees(a) {
+        let child = match b.child(byte) {
+            Some(other) => subtract(child, other, len - 1),
+            None => child
+        };
+        kept.push((byte, child));
+    }
+    branch(kept, len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::util::increment;
+
+    fn trie(keys: &[[u8; 4]]) -> InnerPtr<u32, u32> {
+        let mut ptr = InnerPtr::empty();
+        for key in keys {
+            ptr.insert(key, u32::from_be_bytes(*key));
+        }
+        ptr
+    }
+
+    fn contents(ptr: &InnerPtr<u32, u32>) -> Vec<[u8; 4]> {
+        let mut found = Vec::new();
+        let mut key = [0; 4];
+        while ptr.first(&mut key).is_some() {
+            found.push(key);
+            if !increment(&mut key) {
+                break;
+            }
+        }
+        assert_eq!(found.len(), ptr.population());
+        found
+    }
+
+    #[test]
+    fn test_leaf_bitmaps() {
+        // Dense enough in the last byte for bitmap leaves
+        let evens: Vec<[u8; 4]> = (0..=255).step_by(2).map(|byte| [0, 0, 1, byte]).collect();
+        let thirds: Vec<[u8; 4]> = (0..=255).step_by(3).map(|byte| [0, 0, 1, byte]).collect();
+        let either = |pick: &dyn Fn(u8) -> bool| -> Vec<[u8; 4]> {
+            (0..=255).filter(|&byte| pick(byte)).map(|byte| [0, 0, 1, byte]).collect()
+        };
+
+        let merged = union(trie(&evens), trie(&thirds), 4);
+        assert_eq!(contents(&merged), either(&|byte| byte % 2 == 0 || byte % 3 == 0));
+        let common = intersection(trie(&evens), &trie(&thirds), 4);
+        assert_eq!(contents(&common), either(&|byte| byte % 6 == 0));
+        let rest = difference(trie(&evens), &trie(&thirds), 4);
+        assert_eq!(contents(&rest), either(&|byte| byte % 2 == 0 && byte % 3 != 0));
+    }
+
+    #[test]
+    fn test_narrows() {
+        let a = trie(&[[1, 2, 3, 4], [1, 2, 3, 5]]);
+        let b = trie(&[[1, 2, 9, 9], [1, 2, 3, 5]]);
+        let merged = union(a, b, 4);
+        assert_eq!(contents(&merged), vec![[1, 2, 3, 4], [1, 2, 3, 5], [1, 2, 9, 9]]);
+        assert_eq!(merged.get(&[1, 2, 3, 5]), Some(&0x01020305));
+
+        let far = trie(&[[7, 7, 7, 7]]);
+        assert_eq!(intersection(trie(&[[1, 2, 3, 4]]), &far, 4).population(), 0);
+        let common = intersection(merged, &trie(&[[1, 2, 9, 9], [7, 7, 7, 7]]), 4);
+        assert_eq!(contents(&common), vec![[1, 2, 9, 9]]);
+    }
+
+    #[test]
+    fn test_bitmap_branches() {
+        // Spread over enough first bytes for bitmap branches on both sides
+        let spread = |bytes: &mut dyn Iterator<Item=usize>| -> Vec<[u8; 4]> {
+            bytes.map(|byte| [byte as u8, 0, 0, 7]).collect()
+        };
+        let evens = spread(&mut (0..200).step_by(2));
+        let thirds = spread(&mut (0..180).step_by(3));
+        let node = |ptr: &InnerPtr<u32, u32>| match ptr.as_ref() {
+            Ref::BranchBitmap(_) => "bitmap",
+            Ref::BranchUncompressed(_) => "uncompressed",
+            _ => "other"
+        };
+        assert_eq!(node(&trie(&evens)), "bitmap");
+        assert_eq!(node(&trie(&thirds)), "bitmap");
+
+        let merged = union(trie(&evens), trie(&thirds), 4);
+        let either = spread(&mut (0..200).filter(|byte| byte % 2 == 0 || (byte % 3 == 0 && *byte < 180)));
+        assert_eq!(contents(&merged), either);
+        assert_eq!(node(&merged), "uncompressed");
+
+        let common = intersection(trie(&evens), &trie(&thirds), 4);
+        assert_eq!(contents(&common), spread(&mut (0..180).step_by(6)));
+        assert_eq!(node(&common), "bitmap");
+
+        let rest = difference(trie(&evens), &trie(&thirds), 4);
+        let only = spread(&mut (0..200).filter(|byte| byte % 2 == 0 && !(byte % 3 == 0 && *byte < 180)));
+        assert_eq!(contents(&rest), only);
+        let rest = difference(trie(&evens), &trie(&evens[..90]), 4);
+        assert_eq!(contents(&rest), &evens[90..]);
+        assert_eq!(node(&rest), "bitmap");
+    }
+
+    #[test]
+    fn test_prepend() {
+        let mut child = InnerPtr::<u32, u32>::empty();
+        child.insert(&[3, 4], 34);
+        let ptr = Narrow::prepend(1, Narrow::prepend(2, child));
+        match ptr.as_ref() {
+            Ref::Narrow(narrow) => assert_eq!(narrow.prefix(), &[1, 2]),
+            _ => panic!("the skipped bytes should share one narrow")
+        }
+        assert_eq!(ptr.get(&[1, 2, 3, 4]), Some(&34));
+    }
+}
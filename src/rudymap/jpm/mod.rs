@@ -1,11 +1,26 @@
+//! The trie behind a `RudyMap` once it outgrows the root leaves, named after
+//! the JPM at the top of a Judy array.
+//!
+//! Each node type grows into a larger one when an insert does not fit and
+//! shrinks into a smaller one when removals leave it sparse. The point at
+//! which a node shrinks always sits well below the point at which the
+//! smaller node would grow again, so a key inserted and removed over and
+//! over at the boundary does not rebuild the node every time.
+
 pub mod innerptr;
 pub mod empty;
+pub mod immediate;
 pub mod branch_bitmap;
 pub mod branch_linear;
 pub mod branch_uncompressed;
 pub mod leaf_bitmap;
 pub mod leaf_linear;
+pub mod leaf_multi;
+pub mod narrow;
 pub mod jpm_root;
+pub mod merge;
+pub mod build;
+pub mod cursor;
 pub mod traits;
 
 pub use self::jpm_root::Jpm;
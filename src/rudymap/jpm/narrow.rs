@@ -0,0 +1,313 @@
+//! A node that skips key bytes shared by its whole subtree, like Judy's
+//! narrow pointers. Rather than a chain of single-child branches, the shared
+//! bytes are stored once and checked on the way down.
+//!
+//! A key that disagrees with the stored bytes splits the node: a branch goes
+//! in at the first differing byte, with what is left of the prefix on either
+//! side of it.
+
+use ::util::{increment, decrement};
+use super::traits::{JpmNode, Child};
+use super::innerptr::{InnerPtr, IntoPtr, Ref, Owned};
+use super::branch_linear::BranchLinear;
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
+use ::Key;
+use std::cmp::Ordering;
+use std::mem;
+
+/// The most key bytes a single `Narrow` can skip.
+pub const MAX_PREFIX_LEN: usize = 7;
+
+#[derive(Clone)]
+pub struct Narrow<K: Key, V> {
+    prefix: [u8; MAX_PREFIX_LEN],
+    len: u8,
+    child: InnerPtr<K, V>
+}
+
+impl<K: Key, V> Narrow<K, V> {
+    /// Skip `prefix` on the way down to `child`.
+    pub fn new(prefix: &[u8], child: InnerPtr<K, V>) -> Narrow<K, V> {
+        debug_assert!(!prefix.is_empty() && prefix.len() <= MAX_PREFIX_LEN);
+        let mut node = Narrow {
+            prefix: [0; MAX_PREFIX_LEN],
+            len: prefix.len() as u8,
+            child
+        };
+        node.prefix[..prefix.len()].copy_from_slice(prefix);
+        node
+    }
+
+    /// Put `child` behind a `Narrow` skipping `prefix`, or leave it alone if
+    /// there is nothing to skip.
+    pub fn wrap(prefix: &[u8], child: InnerPtr<K, V>) -> InnerPtr<K, V> {
+        if prefix.is_empty() {
+            child
+        } else {
+            Narrow::new(prefix, child).into_ptr()
+        }
+    }
+
+    /// Put `child` behind one more skipped byte, folding that byte into
+    /// `child`'s own prefix if it is a `Narrow` with room for it.
+    pub fn prepend(byte: u8, child: InnerPtr<K, V>) -> InnerPtr<K, V> {
+        let room = match child.as_ref() {
+            Ref::Narrow(narrow) => (narrow.len as usize) < MAX_PREFIX_LEN,
+            _ => false
+        };
+        if !room {
+            return Narrow::new(&[byte], child).into_ptr();
+        }
+        match child.into_owned() {
+            Owned::Narrow(Narrow { prefix, len, child }) => {
+                let mut joined = [byte; MAX_PREFIX_LEN];
+                joined[1..=len as usize].copy_from_slice(&prefix[..len as usize]);
+                Narrow::new(&joined[..=len as usize], child).into_ptr()
+            },
+            _ => unreachable!()
+        }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix[..self.len as usize]
+    }
+
+    pub fn child(&self) -> &InnerPtr<K, V> {
+        &self.child
+    }
+
+    /// The prefix along with the child under it, which can be changed.
+    pub fn split_mut(&mut self) -> (&[u8], &mut InnerPtr<K, V>) {
+        (&self.prefix[..self.len as usize], &mut self.child)
+    }
+
+    /// The rest of `key` below the prefix, if `key` starts with it.
+    fn strip<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let len = self.len as usize;
+        if key[..len] == *self.prefix() {
+            Some(&key[len..])
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: Key, V> JpmNode<K, V> for Narrow<K, V> {
+    fn get(&self, key: &[u8]) -> Option<&V> {
+        self.strip(key).and_then(|subkey| self.child.get(subkey))
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let len = self.len as usize;
+        if key[..len] == *self.prefix() {
+            self.child.get_mut(&key[len..])
+        } else {
+            None
+        }
+    }
+
+    fn first(&self, key: &mut [u8]) -> Option<&V> {
+        let (head, subkey) = key.split_at_mut(self.len as usize);
+        match (*head).cmp(self.prefix()) {
+            Ordering::Less => {
+                head.copy_from_slice(self.prefix());
+                subkey.fill(0);
+            },
+            Ordering::Equal => {},
+            Ordering::Greater => return None
+        }
+        self.child.first(subkey)
+    }
+
+    fn last(&self, key: &mut [u8]) -> Option<&V> {
+        let (head, subkey) = key.split_at_mut(self.len as usize);
+        match (*head).cmp(self.prefix()) {
+            Ordering::Less => return None,
+            Ordering::Equal => {},
+            Ordering::Greater => {
+                head.copy_from_slice(self.prefix());
+                subkey.fill(0xff);
+            }
+        }
+        self.child.last(subkey)
+    }
+
+    fn count_below(&self, key: &[u8]) -> usize {
+        let (head, subkey) = key.split_at(self.len as usize);
+        match head.cmp(self.prefix()) {
+            Ordering::Less => 0,
+            Ordering::Equal => self.child.count_below(subkey),
+            Ordering::Greater => self.child.population()
+        }
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let len = prefix.len().min(self.len as usize);
+        if prefix[..len] != self.prefix()[..len] {
+            return 0;
+        }
+        self.child.count_prefix(&prefix[len..])
+    }
+
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V> {
+        let (head, subkey) = key.split_at_mut(self.len as usize);
+        head.copy_from_slice(self.prefix());
+        self.child.select(index, subkey)
+    }
+
+    fn first_empty(&self, key: &mut [u8]) -> bool {
+        let (head, subkey) = key.split_at_mut(self.len as usize);
+        if *head != *self.prefix() || self.child.first_empty(subkey) {
+            return true;
+        }
+        // Everything from the key to the end of the prefix is taken, but
+        // the first key past the prefix is free
+        head.copy_from_slice(self.prefix());
+        subkey.fill(0);
+        increment(head)
+    }
+
+    fn last_empty(&self, key: &mut [u8]) -> bool {
+        let (head, subkey) = key.split_at_mut(self.len as usize);
+        if *head != *self.prefix() || self.child.last_empty(subkey) {
+            return true;
+        }
+        head.copy_from_slice(self.prefix());
+        subkey.fill(0xff);
+        decrement(head)
+    }
+
+    fn insert(&mut self, key: &[u8], value: V) -> InsertResult<V> {
+        let len = self.len as usize;
+        if key[..len] == *self.prefix() {
+            InsertResult::Success(self.child.insert(&key[len..], value))
+        } else {
+            InsertResult::Resize(value)
+        }
+    }
+
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V> {
+        let len = self.len as usize;
+        if key[..len] == *self.prefix() {
+            EntryResult::Success(self.child.insert_entry(&key[len..], value))
+        } else {
+            EntryResult::Resize(value)
+        }
+    }
+
+    fn expand(self, pop: usize, key: &[u8], value: V) -> InnerPtr<K, V> {
+        let Narrow { prefix, len, child } = self;
+        let prefix = &prefix[..len as usize];
+        let split = prefix.iter()
+            .zip(key.iter())
+            .position(|(a, b)| a != b)
+            .unwrap();
+
+        let old = Narrow::wrap(&prefix[split + 1..], child);
+        let mut new = InnerPtr::empty();
+        new.insert(&key[split + 1..], value);
+        let mut children = vec![(prefix[split], old), (key[split], new)];
+        children.sort_by_key(|&(byte, _)| byte);
+        let branch: BranchLinear<K, V> = children.into_iter().collect();
+        let branch = branch.into_ptr();
+        Narrow::wrap(&prefix[..split], branch)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> RemoveResult<V> {
+        let len = self.len as usize;
+        if key[..len] != *self.prefix() {
+            return RemoveResult::Success(None);
+        }
+        if self.child.is_last(&key[len..]) {
+            return RemoveResult::Downsize;
+        }
+        RemoveResult::Success(self.child.remove(&key[len..]))
+    }
+
+    fn shrink_remove(mut self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V) {
+        // Only the last entry is removed this way
+        debug_assert_eq!(pop, 0);
+        let value = self.child.remove(&key[self.len as usize..]).unwrap();
+        (InnerPtr::empty(), value)
+    }
+
+    fn into_children(self) -> Vec<(u8, Child<K, V>)> {
+        let Narrow { prefix, len, child } = self;
+        let rest = Narrow::wrap(&prefix[1..len as usize], child);
+        vec![(prefix[0], Child::Node(rest))]
+    }
+
+    fn population(&self) -> usize {
+        self.child.population()
+    }
+
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.child.target_memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::innerptr::Ref;
+
+    fn narrow() -> InnerPtr<u32, u32> {
+        let mut child = InnerPtr::empty();
+        child.insert(&[5, 6], 56);
+        Narrow::wrap(&[1, 2], child)
+    }
+
+    #[test]
+    fn test_search() {
+        let ptr = narrow();
+        assert_eq!(ptr.get(&[1, 2, 5, 6]), Some(&56));
+        assert_eq!(ptr.get(&[1, 3, 5, 6]), None);
+
+        let mut key = [0, 0, 0, 0];
+        assert_eq!(ptr.first(&mut key), Some(&56));
+        assert_eq!(key, [1, 2, 5, 6]);
+        let mut key = [9, 9, 9, 9];
+        assert_eq!(ptr.last(&mut key), Some(&56));
+        assert_eq!(key, [1, 2, 5, 6]);
+        assert_eq!(ptr.first(&mut [1, 2, 5, 7]), None);
+        assert_eq!(ptr.last(&mut [1, 2, 5, 5]), None);
+
+        assert_eq!(ptr.count_below(&[1, 1, 255, 255]), 0);
+        assert_eq!(ptr.count_below(&[1, 2, 5, 7]), 1);
+        assert_eq!(ptr.count_below(&[1, 3, 0, 0]), 1);
+
+        let mut key = [1, 2, 5, 6];
+        assert!(ptr.first_empty(&mut key));
+        assert_eq!(key, [1, 2, 5, 7]);
+        let mut key = [1, 2, 5, 6];
+        assert!(ptr.last_empty(&mut key));
+        assert_eq!(key, [1, 2, 5, 5]);
+    }
+
+    #[test]
+    fn test_split() {
+        let mut ptr = narrow();
+        // disagreeing on the second byte keeps the first one skipped
+        assert_eq!(ptr.insert(&[1, 3, 0, 0], 130), None);
+        match ptr.as_ref() {
+            Ref::Narrow(_) => {},
+            _ => panic!("the shared first byte should stay narrow")
+        }
+        // disagreeing on the first byte needs a branch at the top
+        assert_eq!(ptr.insert(&[0, 2, 5, 6], 256), None);
+        match ptr.as_ref() {
+            Ref::BranchLinear(_) => {},
+            _ => panic!("nothing is shared any more")
+        }
+        assert_eq!(ptr.population(), 3);
+        assert_eq!(ptr.get(&[1, 2, 5, 6]), Some(&56));
+        assert_eq!(ptr.get(&[1, 3, 0, 0]), Some(&130));
+        assert_eq!(ptr.get(&[0, 2, 5, 6]), Some(&256));
+
+        let mut key = [0, 0, 0, 0];
+        let order: Vec<u32> = (0..3)
+            .map(|index| *ptr.select(index, &mut key).unwrap())
+            .collect();
+        assert_eq!(order, vec![256, 56, 130]);
+    }
+}
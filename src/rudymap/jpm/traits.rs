@@ -1,14 +1,147 @@
 use super::innerptr::{InnerPtr, IntoPtr};
 use ::Key;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
+
+/// A child given up by a node that is being consumed.
+pub enum Child<K: Key, V> {
+    Value(V),
+    /// A value from a multi-byte leaf, with the key bytes after this one
+    /// packed big-endian into the word
+    Suffix(usize, V),
+    Node(InnerPtr<K, V>)
+}
 
 pub trait JpmNode<K: Key, V> {
     fn get(&self, key: &[u8]) -> Option<&V>;
     fn get_mut(&mut self, key: &[u8]) -> Option<&mut V>;
+    /// Find the entry with the smallest key greater than or equal to `key`,
+    /// like `JudyLFirst`. On success `key` is overwritten with the key that
+    /// was found; on failure its contents are unspecified.
+    fn first(&self, key: &mut [u8]) -> Option<&V>;
+    /// Find the entry with the largest key less than or equal to `key`, like
+    /// `JudyLLast`. On success `key` is overwritten with the key that was
+    /// found; on failure its contents are unspecified.
+    fn last(&self, key: &mut [u8]) -> Option<&V>;
+    /// Count the entries with keys strictly less than `key`.
+    fn count_below(&self, key: &[u8]) -> usize;
+    /// Count the entries whose keys start with `prefix`, which is neither
+    /// empty nor as long as the keys. Branches step down by one byte and
+    /// read the population of the child they land on.
+    fn count_prefix(&self, prefix: &[u8]) -> usize;
+    /// Find the entry at position `index` in key order, like `JudyLByCount`
+    /// but counting from zero. On success `key` is overwritten with the key
+    /// of that entry.
+    fn select(&self, index: usize, key: &mut [u8]) -> Option<&V>;
+    /// Find the smallest absent key greater than or equal to `key`, like
+    /// `Judy1FirstEmpty`. On success `key` is overwritten with that key.
+    fn first_empty(&self, key: &mut [u8]) -> bool;
+    /// Find the largest absent key less than or equal to `key`, like
+    /// `Judy1LastEmpty`. On success `key` is overwritten with that key.
+    fn last_empty(&self, key: &mut [u8]) -> bool;
     fn insert(&mut self, key: &[u8], value: V)
               -> InsertResult<V>;
+    /// Insert an entry for `key`, which must not be present yet, returning
+    /// the slot the value went into.
+    fn insert_entry(&mut self, key: &[u8], value: V) -> EntryResult<'_, V>;
     fn expand(self, population: usize, key: &[u8], value: V) -> InnerPtr<K, V>;
     fn remove(&mut self, key: &[u8]) -> RemoveResult<V>;
     fn shrink_remove(self, pop: usize, key: &[u8]) -> (InnerPtr<K, V>, V);
+    /// Consume the node, returning its children in key order along with the
+    /// key byte leading to each. Branches give up subtrees and leaves give up
+    /// values.
+    fn into_children(self) -> Vec<(u8, Child<K, V>)>;
+    /// The number of entries under this node. Branches keep a running count
+    /// so that this does not have to visit their children.
+    fn population(&self) -> usize;
+    /// Count the entries under this node from its children, to check the
+    /// population a branch keeps for itself.
+    fn count_population(&self) -> usize {
+        self.population()
+    }
     fn memory_usage(&self) -> usize;
 }
+
+/// `first_empty` for any branch, given a way to look up the child under a
+/// key byte.
+pub fn branch_first_empty<'a, K, V, F>(key: &mut [u8], child: F) -> bool
+    where K: Key + 'a, V: 'a, F: Fn(u8) -> Option<&'a InnerPtr<K, V>> {
+    let (byte, subkey) = key.split_first_mut().unwrap();
+    let start = *byte;
+    for index in start..=255 {
+        if index != start {
+            *byte = index;
+            subkey.fill(0);
+        }
+        match child(index) {
+            Some(child) => {
+                if child.first_empty(subkey) {
+                    return true;
+                }
+            },
+            None => {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// `last_empty` for any branch, given a way to look up the child under a key
+/// byte.
+pub fn branch_last_empty<'a, K, V, F>(key: &mut [u8], child: F) -> bool
+    where K: Key + 'a, V: 'a, F: Fn(u8) -> Option<&'a InnerPtr<K, V>> {
+    let (byte, subkey) = key.split_first_mut().unwrap();
+    let start = *byte;
+    for index in (0..=start).rev() {
+        if index != start {
+            *byte = index;
+            subkey.fill(0xff);
+        }
+        match child(index) {
+            Some(child) => {
+                if child.last_empty(subkey) {
+                    return true;
+                }
+            },
+            None => {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// `select` for any branch, given its children in key order.
+pub fn branch_select<'a, K, V, I>(mut index: usize, key: &mut [u8], children: I)
+                                  -> Option<&'a V>
+    where K: Key + 'a, V: 'a, I: Iterator<Item=(u8, &'a InnerPtr<K, V>)> {
+    let (byte, subkey) = key.split_first_mut().unwrap();
+    for (child_byte, child) in children {
+        let pop = child.population();
+        if index < pop {
+            *byte = child_byte;
+            return child.select(index, subkey);
+        }
+        index -= pop;
+    }
+    None
+}
+
+/// Move every entry out of a consumed subtree, given its children and the
+/// number of key bytes left at its level. Each entry comes out with those
+/// bytes packed big-endian into a word, appended to `prefix`.
+pub fn collect_suffixes<K: Key, V>(children: Vec<(u8, Child<K, V>)>, len: usize,
+                                   prefix: usize, entries: &mut Vec<(usize, V)>) {
+    for (byte, child) in children {
+        let prefix = prefix << 8 | byte as usize;
+        match child {
+            Child::Value(value) => entries.push((prefix, value)),
+            Child::Suffix(rest, value) => {
+                entries.push((prefix << (8 * (len - 1)) | rest, value));
+            },
+            Child::Node(node) => {
+                collect_suffixes(node.into_children(), len - 1, prefix, entries);
+            }
+        }
+    }
+}
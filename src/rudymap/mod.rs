@@ -2,10 +2,23 @@ mod root_leaf;
 mod rootptr;
 mod jpm;
 mod results;
+mod iter;
+mod entry;
 
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Bound, Index, RangeBounds};
 use ::Key;
 use self::rootptr::RootPtr;
+use self::jpm::Jpm;
+use self::jpm::build::Sorted;
 
+pub use self::iter::{Iter, IterMut, IntoIter, Drain};
+pub use self::entry::{Entry, OccupiedEntry, VacantEntry};
+
+#[derive(Clone)]
 pub struct RudyMap<K: Key, V> {
     root: RootPtr<K, V>
 }
@@ -45,6 +58,26 @@ impl<K: Key, V> RudyMap<K, V> {
         self.root.get_mut(key)
     }
 
+    /// Get the entry for `key` for in-place updates. Finding an occupied
+    /// entry walks the trie once, and reading or modifying its value after
+    /// that is free.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut counts = RudyMap::<u8, usize>::new();
+    /// for &byte in b"abracadabra" {
+    ///     *counts.entry(byte).or_insert(0) += 1;
+    /// }
+    /// assert_eq!(counts.get(b'a'), Some(&5));
+    /// assert_eq!(counts.get(b'c'), Some(&1));
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        Entry::new(&mut self.root, key)
+    }
+
     pub fn len(&self) -> usize {
         self.root.len()
     }
@@ -53,27 +86,504 @@ impl<K: Key, V> RudyMap<K, V> {
         self.len() == 0
     }
 
-    /*
-    pub fn iter(&self) -> impl Iterator<Item=(K, &V)> {
+    /// Return the entry with the smallest key, like `JudyLFirst` starting
+    /// from zero.
+    pub fn first_key_value(&self) -> Option<(K, &V)> {
+        self.root.first(Bound::Unbounded)
+    }
+
+    /// Return the entry with the largest key, like `JudyLLast` starting from
+    /// the maximum key.
+    pub fn last_key_value(&self) -> Option<(K, &V)> {
+        self.root.last(Bound::Unbounded)
+    }
+
+    /// Return the entry with the smallest key strictly greater than `key`,
+    /// like `JudyLNext`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<u32, &str>::new();
+    /// map.insert(10, "a");
+    /// map.insert(20, "b");
+    /// assert_eq!(map.next_after(10), Some((20, &"b")));
+    /// assert_eq!(map.next_after(20), None);
+    /// ```
+    pub fn next_after(&self, key: K) -> Option<(K, &V)> {
+        self.root.first(Bound::Excluded(key))
+    }
+
+    /// Return the entry with the smallest key greater than or equal to
+    /// `key`, like `JudyLFirst`.
+    pub fn next_at_or_after(&self, key: K) -> Option<(K, &V)> {
+        self.root.first(Bound::Included(key))
+    }
+
+    /// Return the entry with the largest key strictly less than `key`, like
+    /// `JudyLPrev`.
+    pub fn prev_before(&self, key: K) -> Option<(K, &V)> {
+        self.root.last(Bound::Excluded(key))
+    }
+
+    /// Return the entry with the largest key less than or equal to `key`,
+    /// like `JudyLLast`.
+    pub fn prev_at_or_before(&self, key: K) -> Option<(K, &V)> {
+        self.root.last(Bound::Included(key))
+    }
+
+    /// Return the smallest key strictly greater than `key` that is not in
+    /// the map, like `JudyLNextEmpty`.
+    pub fn next_empty_after(&self, key: K) -> Option<K> {
+        self.root.first_empty(Bound::Excluded(key))
+    }
+
+    /// Return the smallest key greater than or equal to `key` that is not in
+    /// the map, like `JudyLFirstEmpty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<u8, ()>::new();
+    /// map.insert(4, ());
+    /// map.insert(5, ());
+    /// assert_eq!(map.next_empty_at_or_after(3), Some(3));
+    /// assert_eq!(map.next_empty_at_or_after(4), Some(6));
+    /// ```
+    pub fn next_empty_at_or_after(&self, key: K) -> Option<K> {
+        self.root.first_empty(Bound::Included(key))
+    }
+
+    /// Return the largest key strictly less than `key` that is not in the
+    /// map, like `JudyLPrevEmpty`.
+    pub fn prev_empty_before(&self, key: K) -> Option<K> {
+        self.root.last_empty(Bound::Excluded(key))
+    }
+
+    /// Return the largest key less than or equal to `key` that is not in the
+    /// map, like `JudyLLastEmpty`.
+    pub fn prev_empty_at_or_before(&self, key: K) -> Option<K> {
+        self.root.last_empty(Bound::Included(key))
+    }
+
+    /// Iterate over the entries of the map in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
         Iter::new(&self.root)
     }
 
-    pub fn iter_mut(&self) -> impl Iterator<Item=(K, &mut V)> {
+    /// Iterate over the entries of the map in key order, with mutable
+    /// references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
         IterMut::new(&mut self.root)
     }
-    */
+
+    /// Iterate in key order over the entries whose keys fall inside `range`.
+    /// Subtrees outside of the range are never visited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<u64, &str>::new();
+    /// map.insert(10, "a");
+    /// map.insert(20, "b");
+    /// map.insert(30, "c");
+    /// let found: Vec<_> = map.range(15..=30).collect();
+    /// assert_eq!(found, vec![(20, &"b"), (30, &"c")]);
+    /// ```
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V> {
+        Iter::range(&self.root,
+                    range.start_bound().cloned(),
+                    range.end_bound().cloned())
+    }
+
+    /// Iterate in key order over the entries whose keys fall inside `range`,
+    /// with mutable references to the values.
+    pub fn range_mut<R: RangeBounds<K>>(&mut self, range: R) -> IterMut<'_, K, V> {
+        IterMut::range(&mut self.root,
+                       range.start_bound().cloned(),
+                       range.end_bound().cloned())
+    }
+
+    /// Count the entries whose keys fall inside `range`, like `JudyLCount`.
+    /// Large maps answer from the populations cached in each subtree rather
+    /// than visiting every entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<u32, ()>::new();
+    /// for key in 0..100 {
+    ///     map.insert(key * 2, ());
+    /// }
+    /// assert_eq!(map.count_range(..), 100);
+    /// assert_eq!(map.count_range(10..20), 5);
+    /// assert_eq!(map.count_range(11..=11), 0);
+    /// ```
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R) -> usize {
+        self.root.count(range.start_bound().cloned(),
+                        range.end_bound().cloned())
+    }
+
+    /// Get the entry at position `index` in key order, counting from zero,
+    /// like `JudyLByCount`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<u32, &str>::new();
+    /// map.insert(30, "c");
+    /// map.insert(10, "a");
+    /// map.insert(20, "b");
+    /// assert_eq!(map.nth(1), Some((20, &"b")));
+    /// assert_eq!(map.nth(3), None);
+    /// ```
+    pub fn nth(&self, index: usize) -> Option<(K, &V)> {
+        self.root.nth(index)
+    }
+
+    /// Iterate over the entries whose key bytes start with `prefix`, in key
+    /// order. This is most useful for byte array and tuple keys, where a
+    /// prefix picks out leading components. The walk descends straight to
+    /// the first and last match and then steps between them, so the cost is
+    /// a descent per end plus the output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::<(u16, u32), &str>::new();
+    /// map.insert((1, 10), "a");
+    /// map.insert((2, 20), "b");
+    /// map.insert((2, 30), "c");
+    /// let tenant: Vec<_> = map.prefix_iter(&2u16.to_be_bytes()).collect();
+    /// assert_eq!(tenant, vec![((2, 20), &"b"), ((2, 30), &"c")]);
+    /// assert_eq!(map.prefix_len(&2u16.to_be_bytes()), 2);
+    /// ```
+    pub fn prefix_iter(&self, prefix: &[u8]) -> Iter<'_, K, V> {
+        match prefix_bounds::<K>(prefix) {
+            Some((low, high)) => {
+                Iter::range(&self.root, Bound::Included(low), Bound::Included(high))
+            },
+            None => Iter::empty()
+        }
+    }
+
+    /// Count the entries whose key bytes start with `prefix`. Large maps
+    /// follow the prefix down the trie and read the population kept for the
+    /// subtree they reach, without visiting its entries.
+    pub fn prefix_len(&self, prefix: &[u8]) -> usize {
+        let len = K::default().into_bytes().as_ref().len();
+        if prefix.len() < len {
+            self.root.count_prefix(prefix)
+        } else if prefix.len() == len {
+            prefix_bounds::<K>(prefix)
+                .map_or(0, |(key, _)| self.contains_key(key) as usize)
+        } else {
+            0
+        }
+    }
+
+    /// Build a map from entries in ascending key order. Each trie node is
+    /// built once, after all of its entries have been read, rather than
+    /// grown one insertion at a time. A repeated key keeps the last value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is smaller than the one before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let map = RudyMap::from_sorted_iter((0u32..1000).map(|n| (n, n * 2)));
+    /// assert_eq!(map.len(), 1000);
+    /// assert_eq!(map.get(500), Some(&1000));
+    /// ```
+    pub fn from_sorted_iter<I>(iter: I) -> RudyMap<K, V>
+        where I: IntoIterator<Item=(K, V)> {
+        let mut sorted = Sorted::new(iter.into_iter());
+        let mut map = RudyMap::new();
+        // As many as a `VecLeaf` holds
+        if sorted.peek(31).is_none() {
+            while let Some((bytes, value)) = sorted.pop() {
+                map.insert(K::from_bytes(bytes), value);
+            }
+        } else {
+            map.root = Box::new(Jpm::from_sorted(&mut sorted)).into();
+        }
+        map
+    }
+
+    /// Insert entries in ascending key order, replacing the values of keys
+    /// already in the map. The entries are built into a trie of their own
+    /// as in `from_sorted_iter`, then merged in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a key is smaller than the one before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut map = RudyMap::new();
+    /// map.insert(1u32, "old");
+    /// map.extend_sorted(vec![(1, "new"), (2, "new")]);
+    /// assert_eq!(map.get(1), Some(&"new"));
+    /// assert_eq!(map.get(2), Some(&"new"));
+    /// ```
+    pub fn extend_sorted<I>(&mut self, iter: I)
+        where I: IntoIterator<Item=(K, V)> {
+        let mut map = RudyMap::from_sorted_iter(iter);
+        map.union_with(mem::take(self));
+        *self = map;
+    }
+
+    /// Move every entry of `other` into this map, keeping this map's values
+    /// for keys both have. Two large maps are merged trie node by trie node,
+    /// moving over whole subtrees that only `other` has.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut a = RudyMap::new();
+    /// a.insert(1u32, "a");
+    /// let mut b = RudyMap::new();
+    /// b.insert(1u32, "b");
+    /// b.insert(2u32, "b");
+    /// a.union_with(b);
+    /// assert_eq!(a.get(1), Some(&"a"));
+    /// assert_eq!(a.get(2), Some(&"b"));
+    /// ```
+    pub fn union_with(&mut self, mut other: RudyMap<K, V>) {
+        if self.root.jpm().is_some() && other.root.jpm().is_some() {
+            let other = other.root.take_jpm().unwrap();
+            self.root.jpm_mut().unwrap().union_with(*other);
+        } else if self.len() >= other.len() {
+            for (key, value) in other {
+                self.entry(key).or_insert(value);
+            }
+        } else {
+            mem::swap(self, &mut other);
+            for (key, value) in other {
+                self.insert(key, value);
+            }
+        }
+    }
+
+    /// Keep only the entries whose keys `other` also has. Two large maps are
+    /// walked together, dropping whole subtrees that `other` lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut a = RudyMap::new();
+    /// a.insert(1u32, "a");
+    /// a.insert(2u32, "a");
+    /// let mut b = RudyMap::new();
+    /// b.insert(2u32, ());
+    /// a.intersect_with(&b);
+    /// assert_eq!(a.len(), 1);
+    /// assert_eq!(a.get(2), Some(&"a"));
+    /// ```
+    pub fn intersect_with<W>(&mut self, other: &RudyMap<K, W>) {
+        match (self.root.jpm_mut(), other.root.jpm()) {
+            (Some(jpm), Some(other)) => jpm.intersect_with(other),
+            (Some(_), None) => {
+                // Few enough keys in `other` to pull each one out
+                let mut all = mem::take(self);
+                for (key, _) in other.iter() {
+                    if let Some(value) = all.remove(key) {
+                        self.insert(key, value);
+                    }
+                }
+            },
+            (None, _) => {
+                for (key, value) in mem::take(self) {
+                    if other.contains_key(key) {
+                        self.insert(key, value);
+                    }
+                }
+            }
+        }
+        self.root.shrink();
+    }
+
+    /// Remove the entries whose keys `other` has. Two large maps are walked
+    /// together, keeping whole subtrees that `other` lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudymap::RudyMap;
+    ///
+    /// let mut a = RudyMap::new();
+    /// a.insert(1u32, "a");
+    /// a.insert(2u32, "a");
+    /// let mut b = RudyMap::new();
+    /// b.insert(2u32, ());
+    /// a.difference_with(&b);
+    /// assert_eq!(a.len(), 1);
+    /// assert_eq!(a.get(1), Some(&"a"));
+    /// ```
+    pub fn difference_with<W>(&mut self, other: &RudyMap<K, W>) {
+        match (self.root.jpm_mut(), other.root.jpm()) {
+            (Some(jpm), Some(other)) => jpm.difference_with(other),
+            (Some(_), None) => {
+                for (key, _) in other.iter() {
+                    self.remove(key);
+                }
+            },
+            (None, _) => {
+                for (key, value) in mem::take(self) {
+                    if !other.contains_key(key) {
+                        self.insert(key, value);
+                    }
+                }
+            }
+        }
+        self.root.shrink();
+    }
+
+    /// Remove every entry from the map, returning them in key order.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        Drain::new(&mut self.root)
+    }
 
     pub fn memory_usage(&self) -> usize {
         self.root.memory_usage()
     }
 }
 
+/// The smallest and largest keys whose bytes start with `prefix`, or `None`
+/// if there are no such keys.
+fn prefix_bounds<K: Key>(prefix: &[u8]) -> Option<(K, K)> {
+    let mut low = K::default().into_bytes();
+    if prefix.len() > low.as_ref().len() {
+        return None;
+    }
+    let mut high = low.clone();
+    low.as_mut().fill(0);
+    high.as_mut().fill(0xff);
+    low.as_mut()[..prefix.len()].copy_from_slice(prefix);
+    high.as_mut()[..prefix.len()].copy_from_slice(prefix);
+    if !K::round_up(low.as_mut()) || !K::round_down(high.as_mut()) {
+        return None;
+    }
+    if !low.as_ref().starts_with(prefix) || low.as_ref() > high.as_ref() {
+        return None;
+    }
+    Some((K::from_bytes(low), K::from_bytes(high)))
+}
+
 impl<K: Key, V> Default for RudyMap<K, V> {
     fn default() -> RudyMap<K, V> {
         RudyMap::new()
     }
 }
 
+impl<K: Key, V> FromIterator<(K, V)> for RudyMap<K, V> {
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> RudyMap<K, V> {
+        let mut map = RudyMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Key, V> Extend<(K, V)> for RudyMap<K, V> {
+    fn extend<I: IntoIterator<Item=(K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Key, V: Copy + 'a> Extend<(K, &'a V)> for RudyMap<K, V> {
+    fn extend<I: IntoIterator<Item=(K, &'a V)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(|(key, &value)| (key, value)));
+    }
+}
+
+impl<K: Key, V: PartialEq> PartialEq for RudyMap<K, V> {
+    fn eq(&self, other: &RudyMap<K, V>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Key, V: Eq> Eq for RudyMap<K, V> {}
+
+impl<K: Key + Hash, V: Hash> Hash for RudyMap<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for entry in self.iter() {
+            entry.hash(state);
+        }
+    }
+}
+
+impl<K: Key, V: fmt::Debug> fmt::Debug for RudyMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Key, V> Index<K> for RudyMap<K, V> {
+    type Output = V;
+
+    /// Returns a reference to the value under `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is not in the map.
+    fn index(&self, key: K) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K: Key, V> IntoIterator for RudyMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter::new(self.root)
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> IntoIterator for &'a RudyMap<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<'a, K: Key + 'a, V: 'a> IntoIterator for &'a mut RudyMap<K, V> {
+    type Item = (K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -95,6 +605,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_contains_key() {
         let mut map = RudyMap::<u32, u32>::new();
         assert_eq!(map.contains_key(0), false);
@@ -110,6 +621,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_len() {
         let mut map = RudyMap::<u32, u32>::new();
         assert_eq!(map.len(), 0);
@@ -136,6 +648,28 @@ mod test {
         assert_eq!(map.is_empty(), true);
     }
 
+    #[test]
+    fn test_len_jpm() {
+        let mut map = RudyMap::<u64, u64>::new();
+        let keys: Vec<u64> = scattered_keys(1000).into_iter().map(|k| k as u64 * 3).collect();
+        for (i, &key) in keys.iter().enumerate() {
+            assert_eq!(map.insert(key, key), None);
+            assert_eq!(map.len(), i + 1);
+        }
+        // replacing must not count twice
+        for &key in keys.iter() {
+            assert_eq!(map.insert(key, 0), Some(key));
+        }
+        assert_eq!(map.len(), keys.len());
+        assert_eq!(map.remove(1), None);
+        assert_eq!(map.len(), keys.len());
+        for (i, &key) in keys.iter().enumerate() {
+            assert_eq!(map.remove(key), Some(0));
+            assert_eq!(map.len(), keys.len() - i - 1);
+        }
+        assert!(map.is_empty());
+    }
+
     #[test]
     fn test_get_mut() {
         let mut map = RudyMap::<u32, u32>::new();
@@ -154,4 +688,1096 @@ mod test {
 
         map.remove(0);
     }
+
+    // Spread keys over every byte so the JPM grows linear and bitmap
+    // branches as well as bitmap leaves.
+    fn scattered_keys(n: u32) -> Vec<u32> {
+        (0..n).map(|i| i.wrapping_mul(0x9e37_79b9) ^ (i << 3)).collect()
+    }
+
+    #[test]
+    fn test_iter_empty() {
+        let map = RudyMap::<u32, u32>::new();
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.iter().next_back(), None);
+    }
+
+    #[test]
+    fn test_iter_root_leaves() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut expected = BTreeMap::new();
+        for &key in [17, 3, 250, 4, 90, 1, 1000, 2, 65].iter() {
+            map.insert(key, key * 2);
+            expected.insert(key, key * 2);
+            let found: Vec<_> = map.iter().map(|(k, &v)| (k, v)).collect();
+            let wanted: Vec<_> = expected.iter().map(|(&k, &v)| (k, v)).collect();
+            assert_eq!(found, wanted);
+        }
+    }
+
+    #[test]
+    fn test_iter_jpm() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut expected = BTreeMap::new();
+        for key in scattered_keys(5000) {
+            map.insert(key, !key);
+            expected.insert(key, !key);
+        }
+        let found: Vec<_> = map.iter().map(|(k, &v)| (k, v)).collect();
+        let wanted: Vec<_> = expected.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(found, wanted);
+    }
+
+    #[test]
+    fn test_iter_dense() {
+        let mut map = RudyMap::<u16, u16>::new();
+        for key in (0..2000).rev() {
+            map.insert(key, key);
+        }
+        let keys: Vec<u16> = map.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (0..2000).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn test_iter_extreme_keys() {
+        let mut map = RudyMap::<u8, ()>::new();
+        for key in 0..=255 {
+            map.insert(key, ());
+        }
+        assert_eq!(map.iter().count(), 256);
+        assert_eq!(map.iter().next().map(|(k, _)| k), Some(0));
+        assert_eq!(map.iter().next_back().map(|(k, _)| k), Some(255));
+    }
+
+    #[test]
+    fn test_iter_double_ended() {
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut keys = scattered_keys(100);
+        for &key in keys.iter() {
+            map.insert(key, key);
+        }
+        keys.sort();
+        let reversed: Vec<u32> = map.iter().rev().map(|(k, _)| k).collect();
+        assert_eq!(reversed, keys.iter().cloned().rev().collect::<Vec<u32>>());
+
+        let mut iter = map.iter();
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            front.push(key);
+            match iter.next_back() {
+                Some((key, _)) => back.push(key),
+                None => break
+            }
+        }
+        assert_eq!(iter.next(), None);
+        back.reverse();
+        front.extend(back);
+        assert_eq!(front, keys);
+    }
+
+    #[test]
+    fn test_iter_len() {
+        let mut map = RudyMap::<u32, u32>::new();
+        for (count, key) in scattered_keys(1000).into_iter().enumerate() {
+            map.insert(key, key);
+            assert_eq!(map.iter().len(), count + 1);
+        }
+        let mut iter = map.iter_mut();
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.len(), 998);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut map = RudyMap::<u32, u32>::new();
+        let keys = scattered_keys(1000);
+        for &key in keys.iter() {
+            map.insert(key, 0);
+        }
+        for (key, value) in map.iter_mut() {
+            *value = key / 2;
+        }
+        for (key, value) in &mut map {
+            *value += 1;
+        }
+        for &key in keys.iter() {
+            assert_eq!(map.get(key), Some(&(key / 2 + 1)));
+        }
+        let mut previous = None;
+        for (key, &value) in &map {
+            assert!(previous < Some(key));
+            assert_eq!(value, key / 2 + 1);
+            previous = Some(key);
+        }
+    }
+
+    #[test]
+    fn test_iter_mut_disjoint() {
+        let mut map = RudyMap::<u32, u32>::new();
+        for key in scattered_keys(300).into_iter().chain(0..300) {
+            map.insert(key, key);
+        }
+        let len = map.len();
+        let mut iter = map.iter_mut();
+        let mut values = Vec::new();
+        while let Some((_, value)) = iter.next() {
+            values.push(value);
+            values.extend(iter.next_back().map(|(_, value)| value));
+        }
+        assert_eq!(values.len(), len);
+        for value in values {
+            *value = !*value;
+        }
+        for (key, &value) in &map {
+            assert_eq!(value, !key);
+        }
+    }
+
+    #[test]
+    fn test_range_alternating() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut expected = BTreeMap::new();
+        for key in scattered_keys(500).into_iter().chain(1000..1300) {
+            map.insert(key, key);
+            expected.insert(key, key);
+        }
+        let probes: Vec<u32> = expected.keys().cloned().step_by(37).chain(vec![0, 1100, !0]).collect();
+        for &lo in probes.iter() {
+            for &hi in probes.iter().filter(|&&hi| hi >= lo) {
+                let mut found = map.range_mut(lo..=hi);
+                let mut wanted = expected.range_mut(lo..=hi);
+                loop {
+                    let next = found.next().map(|(k, &mut v)| (k, v));
+                    assert_eq!(next, wanted.next().map(|(&k, &mut v)| (k, v)));
+                    let back = found.next_back().map(|(k, &mut v)| (k, v));
+                    assert_eq!(back, wanted.next_back().map(|(&k, &mut v)| (k, v)));
+                    if next.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_into_iter() {
+        use std::collections::BTreeMap;
+
+        for &n in [0, 1, 2, 20, 5000].iter() {
+            let mut map = RudyMap::<u32, String>::new();
+            let mut expected = BTreeMap::new();
+            for key in scattered_keys(n) {
+                map.insert(key, key.to_string());
+                expected.insert(key, key.to_string());
+            }
+            let found: Vec<_> = map.into_iter().collect();
+            let wanted: Vec<_> = expected.into_iter().collect();
+            assert_eq!(found, wanted);
+        }
+    }
+
+    #[test]
+    fn test_into_iter_len() {
+        for &n in [0, 1, 20, 5000].iter() {
+            let mut map: RudyMap<u32, u32> = scattered_keys(n as u32).into_iter()
+                .map(|key| (key, key))
+                .collect();
+            {
+                let mut drain = map.drain();
+                assert_eq!(drain.len(), n);
+                drain.next();
+                let rest = n.saturating_sub(1);
+                assert_eq!(drain.size_hint(), (rest, Some(rest)));
+            }
+            map.extend(scattered_keys(n as u32).into_iter().map(|key| (key, key)));
+
+            let mut iter = map.into_iter();
+            for remaining in (0..n).rev() {
+                iter.next().unwrap();
+                assert_eq!(iter.len(), remaining);
+            }
+            assert_eq!(iter.next(), None);
+            assert_eq!(iter.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_into_iter_drop() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use util::test::Droppable;
+
+        let drop_count = AtomicUsize::new(0);
+        for &n in [1, 20, 1000].iter() {
+            drop_count.store(0, Ordering::Release);
+            {
+                let mut map = RudyMap::<u32, Droppable>::new();
+                for key in scattered_keys(n) {
+                    map.insert(key, Droppable(&drop_count));
+                }
+                let mut iter = map.into_iter();
+                iter.next().unwrap();
+                assert_eq!(drop_count.load(Ordering::Acquire), 1);
+            }
+            // the rest are dropped along with the iterator
+            assert_eq!(drop_count.load(Ordering::Acquire), n as usize);
+        }
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut keys = scattered_keys(1000);
+        for &key in keys.iter() {
+            map.insert(key, key);
+        }
+        keys.sort();
+        let drained: Vec<u32> = map.drain().map(|(k, _)| k).collect();
+        assert_eq!(drained, keys);
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.iter().next(), None);
+        for &key in keys.iter() {
+            assert_eq!(map.get(key), None);
+        }
+
+        map.insert(4, 8);
+        assert_eq!(map.drain().collect::<Vec<_>>(), vec![(4, 8)]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        use std::collections::BTreeMap;
+        use std::ops::Bound;
+        use std::ops::Bound::*;
+
+        for &n in [1, 2, 20, 3000].iter() {
+            let mut map = RudyMap::<u32, u32>::new();
+            let mut expected = BTreeMap::new();
+            for key in scattered_keys(n) {
+                map.insert(key, key);
+                expected.insert(key, key);
+            }
+            let mut probes: Vec<u32> = expected.keys().cloned().step_by(1 + n as usize / 10).collect();
+            probes.extend(&[0, 1, 0x8000_0000, !0 - 1, !0]);
+            for &lo in probes.iter() {
+                for &hi in probes.iter() {
+                    if lo > hi {
+                        continue;
+                    }
+                    let bounds: [(Bound<u32>, Bound<u32>); 5] = [
+                        (Included(lo), Included(hi)),
+                        (Included(lo), Excluded(hi)),
+                        (Excluded(lo), Unbounded),
+                        (Unbounded, Excluded(hi)),
+                        (Unbounded, Unbounded)
+                    ];
+                    for &range in bounds.iter() {
+                        if range == (Excluded(lo), Excluded(hi)) && lo == hi {
+                            continue;
+                        }
+                        let found: Vec<_> = map.range(range).map(|(k, _)| k).collect();
+                        let wanted: Vec<_> = expected.range(range).map(|(&k, _)| k).collect();
+                        assert_eq!(found, wanted, "{:?}", range);
+                        let found: Vec<_> = map.range(range).rev().map(|(k, _)| k).collect();
+                        let wanted: Vec<_> = expected.range(range).rev().map(|(&k, _)| k).collect();
+                        assert_eq!(found, wanted, "{:?}", range);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_range_and_nth() {
+        use std::collections::BTreeMap;
+
+        for &n in [0, 1, 2, 20, 3000].iter() {
+            let mut map = RudyMap::<u32, u32>::new();
+            let mut expected = BTreeMap::new();
+            for key in scattered_keys(n) {
+                map.insert(key, key);
+                expected.insert(key, key);
+            }
+            // removals must keep the cached populations exact
+            for key in scattered_keys(n).into_iter().step_by(3) {
+                map.remove(key);
+                expected.remove(&key);
+            }
+
+            let keys: Vec<u32> = expected.keys().cloned().collect();
+            for (index, &key) in keys.iter().enumerate() {
+                assert_eq!(map.nth(index), Some((key, &key)));
+            }
+            assert_eq!(map.nth(keys.len()), None);
+
+            assert_eq!(map.count_range(..), keys.len());
+            let mut probes: Vec<u32> = keys.iter().cloned().step_by(1 + n as usize / 10).collect();
+            probes.extend(&[0, 1, 0x8000_0000, !0]);
+            for &lo in probes.iter() {
+                for &hi in probes.iter() {
+                    if lo > hi {
+                        continue;
+                    }
+                    assert_eq!(map.count_range(lo..=hi), expected.range(lo..=hi).count());
+                    assert_eq!(map.count_range(lo..hi), expected.range(lo..hi).count());
+                    assert_eq!(map.count_range(lo..), expected.range(lo..).count());
+                    assert_eq!(map.count_range(..hi), expected.range(..hi).count());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_entry() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<u32, u32>::new();
+        let mut expected = BTreeMap::new();
+        // revisit keys so every entry kind is exercised across each layout
+        for (i, key) in scattered_keys(500).into_iter().enumerate() {
+            for &key in [key, key % 97].iter() {
+                *map.entry(key).or_insert(0) += 1;
+                *expected.entry(key).or_insert(0) += 1;
+            }
+            map.entry(key).and_modify(|v| *v *= 2).or_default();
+            expected.entry(key).and_modify(|v| *v *= 2).or_default();
+            if i % 7 == 0 {
+                if let Entry::Occupied(entry) = map.entry(key % 97) {
+                    assert_eq!(entry.remove(), expected.remove(&(key % 97)).unwrap());
+                }
+            }
+        }
+        assert_eq!(map.len(), expected.len());
+        let found: Vec<_> = map.iter().collect();
+        let wanted: Vec<_> = expected.iter().map(|(&k, v)| (k, v)).collect();
+        assert_eq!(found, wanted);
+    }
+
+    #[test]
+    fn test_entry_variants() {
+        let mut map = RudyMap::<u64, String>::new();
+        match map.entry(5) {
+            Entry::Vacant(entry) => {
+                assert_eq!(entry.key(), 5);
+                entry.insert("five".to_string());
+            },
+            Entry::Occupied(_) => panic!("entry should be vacant")
+        }
+        let mut called = false;
+        map.entry(5).or_insert_with(|| { called = true; String::new() });
+        assert!(!called);
+        match map.entry(5) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.get(), "five");
+                assert_eq!(entry.insert("cinq".to_string()), "five");
+                entry.get_mut().push('!');
+            },
+            Entry::Vacant(_) => panic!("entry should be occupied")
+        }
+        assert_eq!(map.get(5).map(|s| s.as_str()), Some("cinq!"));
+        assert_eq!(map.entry(6).or_default(), "");
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_entry_insert_slot() {
+        // Sparse wide keys go through narrows and multi-byte leaves, and the
+        // dense run fills bitmap leaves, so vacant inserts hit every resize
+        let mut map = RudyMap::<u64, u64>::new();
+        let keys: Vec<u64> = scattered_keys(300).into_iter()
+            .map(|key| (key as u64) << 24)
+            .chain((1 << 60)..(1 << 60) + 300)
+            .collect();
+        for (index, &key) in keys.iter().enumerate() {
+            match map.entry(key) {
+                Entry::Vacant(entry) => *entry.insert(0) = key + 1,
+                Entry::Occupied(_) => panic!("entry should be vacant")
+            }
+            assert_eq!(map.len(), index + 1);
+        }
+        for &key in keys.iter() {
+            assert_eq!(map.get(key), Some(&(key + 1)));
+        }
+    }
+
+    #[test]
+    fn test_range_empty() {
+        let mut map = RudyMap::<u64, u64>::new();
+        for key in 0..100 {
+            map.insert(key * 1000, key);
+        }
+        assert_eq!(map.range(1..1000).next(), None);
+        assert_eq!(map.range(5..5).next(), None);
+        assert_eq!(map.range(100_000..).next(), None);
+        assert_eq!(map.range(..0).next_back(), None);
+    }
+
+    #[test]
+    fn test_range_mut() {
+        let mut map = RudyMap::<u64, u64>::new();
+        for key in 0..1000 {
+            map.insert(key << 20, 0);
+        }
+        for (key, value) in map.range_mut((10 << 20)..(20 << 20)) {
+            *value = key >> 20;
+        }
+        for key in 0..1000 {
+            let wanted = if (10..20).contains(&key) { key } else { 0 };
+            assert_eq!(map.get(key << 20), Some(&wanted));
+        }
+    }
+
+    #[test]
+    fn test_neighbours_empty() {
+        let map = RudyMap::<u32, u32>::new();
+        assert_eq!(map.first_key_value(), None);
+        assert_eq!(map.last_key_value(), None);
+        assert_eq!(map.next_after(0), None);
+        assert_eq!(map.next_at_or_after(0), None);
+        assert_eq!(map.prev_before(!0), None);
+        assert_eq!(map.prev_at_or_before(!0), None);
+    }
+
+    #[test]
+    fn test_neighbours() {
+        use std::collections::BTreeMap;
+        use std::ops::Bound::*;
+
+        for &n in [1, 2, 20, 2000].iter() {
+            let mut map = RudyMap::<u32, u32>::new();
+            let mut expected = BTreeMap::new();
+            for key in scattered_keys(n) {
+                map.insert(key, !key);
+                expected.insert(key, !key);
+            }
+            let found = |entry: Option<(u32, &u32)>| entry.map(|(k, &v)| (k, v));
+            let wanted = |entry: Option<(&u32, &u32)>| entry.map(|(&k, &v)| (k, v));
+            assert_eq!(found(map.first_key_value()), wanted(expected.iter().next()));
+            assert_eq!(found(map.last_key_value()), wanted(expected.iter().next_back()));
+
+            let mut probes: Vec<u32> = expected.keys().cloned().collect();
+            probes.extend(probes.clone().iter().map(|k| k.wrapping_add(1)));
+            probes.extend(&[0, !0]);
+            for &key in probes.iter() {
+                assert_eq!(found(map.next_after(key)),
+                           wanted(expected.range((Excluded(key), Unbounded)).next()));
+                assert_eq!(found(map.next_at_or_after(key)),
+                           wanted(expected.range(key..).next()));
+                assert_eq!(found(map.prev_before(key)),
+                           wanted(expected.range(..key).next_back()));
+                assert_eq!(found(map.prev_at_or_before(key)),
+                           wanted(expected.range(..=key).next_back()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_slots() {
+        use std::collections::BTreeSet;
+
+        // A mix of full leaves, gaps and lone keys.
+        let mut present = BTreeSet::new();
+        present.extend(0..700u32);
+        present.extend(1000..1003);
+        present.extend(0x0101_0000..0x0101_0200);
+        present.extend(0xffff_ff00..=0xffff_ffff);
+        present.extend(scattered_keys(300));
+        for count in [1, 2, 10, present.len()].iter() {
+            let mut map = RudyMap::<u32, ()>::new();
+            let members: Vec<u32> = present.iter().cloned().take(*count).collect();
+            for &key in members.iter() {
+                map.insert(key, ());
+            }
+            let members: BTreeSet<u32> = members.into_iter().collect();
+            let mut probes: Vec<u32> = members.iter().cloned().step_by(5).collect();
+            probes.extend(&[0, 699, 700, 999, 1003, 0x0101_01ff, 0xffff_ff00, !0]);
+            for &key in probes.iter() {
+                let next = (key..=!0).find(|k| !members.contains(k));
+                let prev = (0..=key).rev().find(|k| !members.contains(k));
+                assert_eq!(map.next_empty_at_or_after(key), next);
+                assert_eq!(map.prev_empty_at_or_before(key), prev);
+                if key != !0 {
+                    assert_eq!(map.next_empty_after(key),
+                               (key + 1..=!0).find(|k| !members.contains(k)));
+                } else {
+                    assert_eq!(map.next_empty_after(key), None);
+                }
+                if key != 0 {
+                    assert_eq!(map.prev_empty_before(key),
+                               (0..key).rev().find(|k| !members.contains(k)));
+                } else {
+                    assert_eq!(map.prev_empty_before(key), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_slots_full() {
+        let mut map = RudyMap::<u8, u8>::new();
+        assert_eq!(map.next_empty_at_or_after(0), Some(0));
+        assert_eq!(map.prev_empty_at_or_before(255), Some(255));
+        for key in 0..=255 {
+            map.insert(key, key);
+        }
+        assert_eq!(map.next_empty_at_or_after(0), None);
+        assert_eq!(map.prev_empty_at_or_before(255), None);
+        map.remove(128);
+        assert_eq!(map.next_empty_at_or_after(0), Some(128));
+        assert_eq!(map.prev_empty_at_or_before(255), Some(128));
+        assert_eq!(map.next_empty_after(128), None);
+        assert_eq!(map.prev_empty_before(128), None);
+    }
+
+    #[test]
+    fn test_remove_shrinks() {
+        use std::collections::BTreeMap;
+
+        let empty_usage = RudyMap::<u32, u32>::new().memory_usage();
+        // scattered keys build sparse branches, sequential ones full leaves.
+        // Sparse branches keep single entries inline, so their memory only
+        // drops once whole branches empty out.
+        let key_sets = [scattered_keys(3000), (0..3000).collect::<Vec<u32>>()];
+        for keys in key_sets.iter() {
+            let mut map = RudyMap::<u32, u32>::new();
+            let mut expected = BTreeMap::new();
+            for &key in keys.iter() {
+                map.insert(key, key);
+                expected.insert(key, key);
+            }
+            let peak_usage = map.memory_usage();
+
+            for (i, &key) in keys.iter().enumerate() {
+                assert_eq!(map.remove(key), expected.remove(&key));
+                assert_eq!(map.remove(key), None);
+                assert_eq!(map.len(), expected.len());
+                if i % 500 == 0 || expected.len() < 40 {
+                    let found: Vec<_> = map.iter().collect();
+                    let wanted: Vec<_> = expected.iter().map(|(&k, v)| (k, v)).collect();
+                    assert_eq!(found, wanted);
+                }
+                if i == keys.len() * 3 / 4 {
+                    assert!(map.memory_usage() < peak_usage);
+                }
+            }
+            assert_eq!(map.memory_usage(), empty_usage);
+            assert_eq!(map.iter().next(), None);
+        }
+    }
+
+    #[test]
+    fn test_remove_reinsert() {
+        let mut map = RudyMap::<u32, u32>::new();
+        let keys = scattered_keys(200);
+        // churn across the point where the trie collapses into a leaf
+        for round in 0..3 {
+            for &key in keys.iter() {
+                assert_eq!(map.insert(key, round), if round == 0 { None } else { Some(round - 1) });
+            }
+            for &key in keys[10..].iter() {
+                assert_eq!(map.remove(key), Some(round));
+            }
+            for &key in keys[..10].iter() {
+                assert_eq!(map.get(key), Some(&round));
+            }
+            for &key in keys[10..].iter() {
+                map.insert(key, round);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sparse_memory() {
+        let mut map = RudyMap::<u64, u64>::new();
+        for i in 0..1000u64 {
+            map.insert(i.wrapping_mul(0x9e37_79b9_7f4a_7c15), i);
+        }
+        // every key ends in its own leaf, which must not be a 256-slot bitmap,
+        // and below the top bytes there should be no chains of branches
+        let per_key = map.memory_usage() / 1000;
+        assert!(per_key < 128, "{} bytes per key", per_key);
+    }
+
+    /// Check every query against a `BTreeMap` holding the same entries.
+    fn check_against<K: Key>(map: &RudyMap<K, K>,
+                             expected: &::std::collections::BTreeMap<K, K>,
+                             probes: &[K]) {
+        assert_eq!(map.len(), expected.len());
+        let found: Vec<_> = map.iter().collect();
+        let wanted: Vec<_> = expected.iter().map(|(&k, v)| (k, v)).collect();
+        assert_eq!(found, wanted);
+        for &probe in probes.iter() {
+            assert_eq!(map.get(probe), expected.get(&probe));
+            assert_eq!(map.next_at_or_after(probe),
+                       expected.range(probe..).next().map(|(&k, v)| (k, v)));
+            assert_eq!(map.prev_at_or_before(probe),
+                       expected.range(..=probe).next_back().map(|(&k, v)| (k, v)));
+            assert_eq!(map.count_range(..probe), expected.range(..probe).count());
+        }
+        for (index, (&key, value)) in expected.iter().enumerate().step_by(7) {
+            assert_eq!(map.nth(index), Some((key, value)));
+        }
+    }
+
+    #[test]
+    fn test_clustered_keys() {
+        use std::collections::BTreeMap;
+
+        // two clusters sharing their top bytes, plus stragglers that split
+        // the shared prefix at different depths
+        let mut keys: Vec<u64> = scattered_keys(300).into_iter()
+            .map(|k| 0x0000_1234_5600_0000 | (k as u64 & 0xff_ffff))
+            .collect();
+        keys.extend((0..100).map(|k| 0x00ab_cdef_0000_0000 | (k * 3)));
+        keys.extend(&[0x0000_1234_5700_0000, 0x0000_1235_0000_0000, 0x0100_0000_0000_0000, !0]);
+
+        let mut map = RudyMap::<u64, u64>::new();
+        let mut expected = BTreeMap::new();
+        for &key in keys.iter() {
+            map.insert(key, key);
+            expected.insert(key, key);
+        }
+        let mut probes: Vec<u64> = keys.iter().step_by(13).flat_map(|&k| vec![k, k ^ 1, k.wrapping_add(1)]).collect();
+        probes.extend(&[0, 0x0000_1234_55ff_ffff, 0x0000_1234_5700_0001, 0x00ab_cdef_ffff_ffff]);
+        check_against(&map, &expected, &probes);
+
+        assert_eq!(map.next_empty_at_or_after(0x0000_1234_5700_0000), Some(0x0000_1234_5700_0001));
+        assert_eq!(map.prev_empty_at_or_before(!0), Some(!0 - 1));
+
+        for &key in keys.iter().step_by(2) {
+            assert_eq!(map.remove(key), expected.remove(&key));
+        }
+        check_against(&map, &expected, &probes);
+        for &key in keys.iter() {
+            assert_eq!(map.remove(key), expected.remove(&key));
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_signed_keys() {
+        use std::collections::BTreeMap;
+
+        // both sides of zero, the extremes, and enough keys to build a trie
+        let mut keys: Vec<i64> = scattered_keys(300).into_iter()
+            .map(|k| (k as i32 as i64) << 8)
+            .collect();
+        keys.extend(&[i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX]);
+
+        let mut map = RudyMap::<i64, i64>::new();
+        let mut expected = BTreeMap::new();
+        for &key in keys.iter() {
+            map.insert(key, key);
+            expected.insert(key, key);
+        }
+        let probes: Vec<i64> = keys.iter()
+            .flat_map(|&k| vec![k, k.wrapping_sub(1), k.wrapping_add(1)])
+            .collect();
+        check_against(&map, &expected, &probes);
+
+        let found: Vec<i64> = map.range(-1000..1000).map(|(k, _)| k).collect();
+        let wanted: Vec<i64> = expected.range(-1000..1000).map(|(&k, _)| k).collect();
+        assert_eq!(found, wanted);
+        let found: Vec<i64> = map.range(..0).rev().map(|(k, _)| k).collect();
+        let wanted: Vec<i64> = expected.range(..0).rev().map(|(&k, _)| k).collect();
+        assert_eq!(found, wanted);
+        assert_eq!(map.count_range(..0), expected.range(..0).count());
+    }
+
+    #[test]
+    fn test_small_signed_keys() {
+        let mut map = RudyMap::<i8, ()>::new();
+        for key in i8::MIN..=i8::MAX {
+            map.insert(key, ());
+        }
+        let keys: Vec<i8> = map.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (i8::MIN..=i8::MAX).collect::<Vec<_>>());
+        assert_eq!(map.next_empty_at_or_after(i8::MIN), None);
+        map.remove(-3);
+        assert_eq!(map.next_empty_at_or_after(i8::MIN), Some(-3));
+        assert_eq!(map.prev_empty_at_or_before(i8::MAX), Some(-3));
+
+        let mut map = RudyMap::<i16, i16>::new();
+        for key in (-500i16..500).step_by(7) {
+            map.insert(key, key);
+        }
+        assert_eq!(map.next_at_or_after(-5), Some((-3, &-3)));
+        assert_eq!(map.prev_at_or_before(-5), Some((-10, &-10)));
+        assert_eq!(map.nth(0), Some((-500, &-500)));
+    }
+
+    #[test]
+    #[cfg(feature = "i128")]
+    fn test_wide_keys() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<u128, u128>::new();
+        let mut expected = BTreeMap::new();
+        for key in scattered_keys(200) {
+            let key = (key as u128) << 100 | (key as u128 & 0xf) << 40 | 7;
+            map.insert(key, key);
+            expected.insert(key, key);
+        }
+        let probes: Vec<u128> = expected.keys().flat_map(|&k| vec![k, k - 1, k + 1]).collect();
+        check_against(&map, &expected, &probes);
+        let pairs: Vec<(u128, u128)> = map.into_iter().collect();
+        assert_eq!(pairs, expected.into_iter().collect::<Vec<_>>());
+
+        let mut map = RudyMap::<i128, ()>::new();
+        for &key in [i128::MIN, -1, 0, 1, i128::MAX].iter().rev() {
+            map.insert(key, ());
+        }
+        let keys: Vec<i128> = map.range(-1..).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![-1, 0, 1, i128::MAX]);
+        assert_eq!(map.iter().next(), Some((i128::MIN, &())));
+    }
+
+    #[test]
+    fn test_byte_array_keys() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<[u8; 32], [u8; 32]>::new();
+        let mut expected = BTreeMap::new();
+        for key in scattered_keys(200) {
+            let mut bytes = [0x55; 32];
+            bytes[..4].copy_from_slice(&key.to_be_bytes());
+            bytes[31] = key as u8;
+            map.insert(bytes, bytes);
+            expected.insert(bytes, bytes);
+        }
+        let probes: Vec<[u8; 32]> = expected.keys().step_by(3)
+            .flat_map(|&k| {
+                let mut above = k;
+                above[31] = above[31].wrapping_add(1);
+                vec![k, above]
+            })
+            .collect();
+        check_against(&map, &expected, &probes);
+
+        let mut map = RudyMap::<[u8; 3], usize>::new();
+        for (index, word) in [*b"cat", *b"cab", *b"dog", *b"ant"].iter().enumerate() {
+            map.insert(*word, index);
+        }
+        let words: Vec<[u8; 3]> = map.iter().map(|(k, _)| k).collect();
+        assert_eq!(words, vec![*b"ant", *b"cab", *b"cat", *b"dog"]);
+        assert_eq!(map.next_empty_after(*b"cab"), Some(*b"cac"));
+    }
+
+    #[test]
+    fn test_tuple_keys() {
+        use std::collections::BTreeMap;
+
+        let mut map = RudyMap::<(u32, u32), (u32, u32)>::new();
+        let mut expected = BTreeMap::new();
+        for key in scattered_keys(300) {
+            let key = (key % 7, key);
+            map.insert(key, key);
+            expected.insert(key, key);
+        }
+        let probes: Vec<(u32, u32)> = expected.keys().step_by(5)
+            .flat_map(|&(a, b)| vec![(a, b), (a, b.wrapping_add(1)), (a + 1, 0)])
+            .collect();
+        check_against(&map, &expected, &probes);
+        let found: Vec<(u32, u32)> = map.range((3, 0)..(4, 0)).map(|(k, _)| k).collect();
+        let wanted: Vec<(u32, u32)> = expected.range((3, 0)..(4, 0)).map(|(&k, _)| k).collect();
+        assert_eq!(found, wanted);
+
+        let mut map = RudyMap::<(u16, u64), ()>::new();
+        for a in 0..50u16 {
+            map.insert((a, u64::MAX), ());
+            map.insert((a + 1, 0), ());
+        }
+        assert_eq!(map.next_after((3, u64::MAX)), Some(((4, 0), &())));
+        assert_eq!(map.prev_before((4, 0)), Some(((3, u64::MAX), &())));
+        assert_eq!(map.next_empty_after((3, u64::MAX)), Some((4, 1)));
+        assert_eq!(map.prev_empty_before((4, 0)), Some((3, u64::MAX - 1)));
+    }
+
+    #[test]
+    fn test_gapped_keys() {
+        // searches for empty slots must skip byte strings that are not keys
+        let mut map = RudyMap::<(bool, bool), ()>::new();
+        map.insert((false, false), ());
+        map.insert((false, true), ());
+        map.insert((true, false), ());
+        assert_eq!(map.next_empty_after((false, false)), Some((true, true)));
+        map.insert((true, true), ());
+        assert_eq!(map.next_empty_after((false, false)), None);
+        map.remove((false, false));
+        assert_eq!(map.prev_empty_at_or_before((true, true)), Some((false, false)));
+
+        // enough chars to build a trie around the surrogates
+
+        let mut map = RudyMap::<char, ()>::new();
+        for code in 0xd000..0xd800 {
+            map.insert(char::from_u32(code).unwrap(), ());
+        }
+        assert_eq!(map.next_empty_after('\u{d7ff}'), Some('\u{e000}'));
+        map.insert('\u{e000}', ());
+        assert_eq!(map.next_empty_after('\u{d7ff}'), Some('\u{e001}'));
+        assert_eq!(map.prev_empty_before('\u{e001}'), Some('\u{cfff}'));
+        map.insert(char::MAX, ());
+        assert_eq!(map.next_empty_at_or_after(char::MAX), None);
+        assert_eq!(map.prev_empty_at_or_before(char::MAX), Some('\u{10fffe}'));
+        let keys: Vec<char> = map.range('\u{d7fe}'..).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!['\u{d7fe}', '\u{d7ff}', '\u{e000}', char::MAX]);
+
+        let mut map = RudyMap::<bool, u8>::new();
+        map.insert(false, 0);
+        assert_eq!(map.next_empty_after(false), Some(true));
+        map.insert(true, 1);
+        assert_eq!(map.next_empty_after(false), None);
+        assert_eq!(map.next_empty_after(true), None);
+    }
+
+    #[test]
+    fn test_prefix() {
+        use std::collections::BTreeMap;
+
+        for &count in [5, 3000].iter() {
+            let mut map = RudyMap::<(u16, u32), u32>::new();
+            let mut expected = BTreeMap::new();
+            for key in scattered_keys(count) {
+                let key = ((key % 5) as u16 | (key & 0x300) as u16, key >> 3);
+                map.insert(key, key.1);
+                expected.insert(key.into_bytes().as_ref().to_vec(), (key, key.1));
+            }
+            let mut prefixes: Vec<Vec<u8>> = vec![vec![], vec![0], vec![3], vec![0, 2], vec![1, 4]];
+            for bytes in expected.keys().step_by(97) {
+                for len in 1..=7 {
+                    prefixes.push(bytes[..len.min(bytes.len())].to_vec());
+                }
+            }
+            prefixes.push(vec![0; 7]);
+            for prefix in prefixes.iter() {
+                let wanted: Vec<((u16, u32), u32)> = expected.iter()
+                    .filter(|(bytes, _)| bytes.starts_with(prefix) && prefix.len() <= 6)
+                    .map(|(_, &entry)| entry)
+                    .collect();
+                let found: Vec<((u16, u32), u32)> = map.prefix_iter(prefix)
+                    .map(|(k, &v)| (k, v))
+                    .collect();
+                assert_eq!(found, wanted, "prefix {:?}", prefix);
+                assert_eq!(map.prefix_len(prefix), wanted.len(), "prefix {:?}", prefix);
+            }
+            for key in expected.values().step_by(2).map(|&(k, _)| k) {
+                map.remove(key);
+            }
+            assert_eq!(map.prefix_len(&[]), map.len());
+            let tenant = 2u16.to_be_bytes();
+            assert_eq!(map.prefix_len(&tenant), map.prefix_iter(&tenant).count());
+        }
+
+        // a prefix that only covers gaps in a key type has no keys
+        let mut map = RudyMap::<char, ()>::new();
+        map.insert('a', ());
+        assert_eq!(map.prefix_len(&[0x00, 0xd9]), 0);
+        assert_eq!(map.prefix_iter(&[0x00, 0xd9]).count(), 0);
+        assert_eq!(map.prefix_iter(&[0x00, 0x00]).count(), 1);
+        assert_eq!(map.prefix_iter(&[0x11]).count(), 0);
+    }
+
+    #[test]
+    fn test_bulk_set_operations() {
+        use std::collections::BTreeMap;
+
+        // Owned values catch anything moved twice or dropped early
+        let build = |keys: &[u32], tag: &str| -> (RudyMap<u32, String>, BTreeMap<u32, String>) {
+            let entries: Vec<(u32, String)> = keys.iter()
+                .map(|&key| (key, format!("{}{}", tag, key)))
+                .collect();
+            let mut map = RudyMap::new();
+            for (key, value) in entries.iter() {
+                map.insert(*key, value.clone());
+            }
+            (map, entries.into_iter().collect())
+        };
+        let contents = |map: &RudyMap<u32, String>| -> Vec<(u32, String)> {
+            map.iter().map(|(k, v)| (k, v.clone())).collect()
+        };
+
+        let dense: Vec<u32> = (0..3000).map(|n| n * 2).collect();
+        let scattered: Vec<u32> = scattered_keys(3000).into_iter()
+            .chain((0..2000).map(|n| n * 3))
+            .collect();
+        let (a, expected_a) = build(&dense, "a");
+        let (b, expected_b) = build(&scattered, "b");
+
+        let mut union = a;
+        union.union_with(b);
+        let mut expected = expected_b.clone();
+        expected.extend(expected_a.clone());
+        assert_eq!(contents(&union), expected.into_iter().collect::<Vec<_>>());
+        assert_eq!(union.len(), union.iter().count());
+
+        let (mut common, _) = build(&dense, "a");
+        let (b, _) = build(&scattered, "b");
+        common.intersect_with(&b);
+        let wanted: Vec<(u32, String)> = expected_a.iter()
+            .filter(|&(key, _)| expected_b.contains_key(key))
+            .map(|(&k, v)| (k, v.clone()))
+            .collect();
+        assert_eq!(contents(&common), wanted);
+        assert_eq!(common.len(), wanted.len());
+
+        let (mut rest, _) = build(&dense, "a");
+        rest.difference_with(&b);
+        let wanted: Vec<(u32, String)> = expected_a.iter()
+            .filter(|&(key, _)| !expected_b.contains_key(key))
+            .map(|(&k, v)| (k, v.clone()))
+            .collect();
+        assert_eq!(contents(&rest), wanted);
+        assert_eq!(rest.len(), wanted.len());
+        let mut remaining = RudyMap::new();
+        for (key, _) in rest.iter() {
+            remaining.insert(key, ());
+        }
+        rest.difference_with(&remaining);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        let mut scattered = scattered_keys(5000);
+        scattered.sort();
+        let key_sets: Vec<Vec<u32>> = vec![
+            vec![],
+            (0..31).collect(),
+            (0..32).collect(),
+            (0..100_000).collect(),
+            (0..5000).map(|n| n * 300).collect(),
+            scattered
+        ];
+        for keys in key_sets.iter() {
+            let map = RudyMap::from_sorted_iter(keys.iter().map(|&key| (key, key.to_string())));
+            let mut inserted = RudyMap::new();
+            for &key in keys.iter() {
+                inserted.insert(key, key.to_string());
+            }
+            assert_eq!(map.len(), keys.len());
+            assert!(map.iter().eq(inserted.iter()));
+            assert!(map.iter().rev().eq(inserted.iter().rev()));
+            assert_eq!(map.memory_usage(), inserted.memory_usage(), "{} keys", keys.len());
+        }
+    }
+
+    #[test]
+    fn test_from_sorted_iter_repeated_keys() {
+        let entries = (0..1000u32).flat_map(|key| vec![(key, 0), (key, key)]);
+        let mut map = RudyMap::from_sorted_iter(entries);
+        assert_eq!(map.len(), 1000);
+        assert!(map.iter().all(|(key, &value)| key == value));
+        for key in 0..990 {
+            assert_eq!(map.remove(key), Some(key));
+        }
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.iter().next(), Some((990, &990)));
+    }
+
+    #[test]
+    #[should_panic(expected = "ascending")]
+    fn test_from_sorted_iter_unsorted() {
+        RudyMap::from_sorted_iter((0..100u32).rev().map(|key| (key, ())));
+    }
+
+    #[test]
+    fn test_extend_sorted() {
+        use std::collections::BTreeMap;
+
+        let mut map: RudyMap<u32, u32> = RudyMap::new();
+        let mut expected = BTreeMap::new();
+        let batches = vec![
+            (0..10).collect::<Vec<u32>>(),
+            (5..2000).map(|n| n * 2).collect(),
+            scattered_keys(3000),
+            (0..100).collect()
+        ];
+        for (tag, mut batch) in batches.into_iter().enumerate() {
+            batch.sort();
+            let tag = tag as u32;
+            map.extend_sorted(batch.iter().map(|&key| (key, tag)));
+            expected.extend(batch.iter().map(|&key| (key, tag)));
+            assert_eq!(map.len(), expected.len());
+            assert!(map.iter().eq(expected.iter().map(|(&k, v)| (k, v))));
+        }
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut scattered = scattered_keys(3000);
+        scattered.extend((0..5000).map(|n| n * 2));
+        let key_sets: Vec<Vec<u32>> = vec![
+            vec![],
+            vec![7],
+            vec![7, 8],
+            (0..20).collect(),
+            (0..100_000).collect(),
+            scattered
+        ];
+        for keys in key_sets.iter() {
+            // Small values sit in immediates; owned ones catch a shallow copy
+            let small: RudyMap<u32, u16> = keys.iter().map(|&key| (key, key as u16)).collect();
+            let copy = small.clone();
+            assert!(copy == small);
+            assert_eq!(copy.memory_usage(), small.memory_usage());
+
+            let mut owned: RudyMap<u32, String> = keys.iter()
+                .map(|&key| (key, key.to_string()))
+                .collect();
+            let copy = owned.clone();
+            for (_, value) in owned.iter_mut() {
+                value.push('!');
+            }
+            assert_eq!(copy.len(), owned.len());
+            assert!(copy.iter().all(|(key, value)| *value == key.to_string()));
+            drop(owned);
+            assert!(copy.iter().all(|(key, value)| *value == key.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_std_traits() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::BTreeMap;
+
+        let hash = |map: &RudyMap<u32, u32>| {
+            let mut hasher = DefaultHasher::new();
+            map.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let mut map: RudyMap<u32, u32> = (0..1000).rev().map(|n| (n * 3, n)).collect();
+        let btree: BTreeMap<u32, u32> = (0..1000).map(|n| (n * 3, n)).collect();
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map[300], 100);
+        assert_eq!(format!("{:?}", map), format!("{:?}", btree));
+        assert_eq!(format!("{:?}", RudyMap::<u8, ()>::new()), "{}");
+
+        let mut other = RudyMap::new();
+        other.extend(btree.iter().map(|(&k, v)| (k, v)));
+        assert!(map == other);
+        assert_eq!(hash(&map), hash(&other));
+        other.extend(vec![(1, 1)]);
+        assert!(map != other);
+        map.insert(1, 2);
+        assert!(map != other);
+        assert_ne!(hash(&map), hash(&other));
+        map.extend(vec![(1, 1)]);
+        assert!(map == other);
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry")]
+    fn test_index_missing() {
+        let map: RudyMap<u32, u32> = (0..10).map(|n| (n, n)).collect();
+        let _ = map[10];
+    }
 }
@@ -22,6 +22,15 @@ impl<V> InsertResult<V> {
     }
 }
 
+/// The outcome of inserting a key that is not present yet.
+#[must_use]
+pub enum EntryResult<'a, V: 'a> {
+    /// The slot the value went into
+    Success(&'a mut V),
+    /// The node is full and hands the value back
+    Resize(V)
+}
+
 #[must_use]
 pub enum RemoveResult<V> {
     /// The entry was removed without needing a downsize
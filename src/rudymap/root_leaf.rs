@@ -1,18 +1,106 @@
 use std::marker::PhantomData;
 use std::mem;
 use std::ptr;
+use std::ops::Bound;
 use util::locksteparray;
-use util::SliceExt;
+use util::{SliceExt, above_lower, below_upper, increment, decrement};
+use key::{lower_bytes, upper_bytes};
 use super::jpm::jpm_root::Jpm;
 use ::Key;
-use ::rudymap::results::{InsertResult, RemoveResult};
+use ::rudymap::results::{InsertResult, EntryResult, RemoveResult};
 use std::iter;
+use std::slice;
 use super::rootptr::RootPtr;
 
 pub trait RootLeaf<K: Key, V> {
     fn get(&self, key: K) -> Option<&V>;
     fn get_mut(&mut self, key: K) -> Option<&mut V>;
+    /// Find the entry with the smallest key inside the lower bound `from`.
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)>;
+    /// Find the entry with the largest key inside the upper bound `to`.
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)>;
+
+    /// Find the smallest absent key inside the lower bound `from`. Small
+    /// leaves simply probe one key after another.
+    fn first_empty(&self, from: Bound<K>) -> Option<K> {
+        let mut bytes = lower_bytes(from)?;
+        loop {
+            if !K::round_up(bytes.as_mut()) {
+                return None;
+            }
+            let key = K::from_bytes(bytes.clone());
+            if self.get(key).is_none() {
+                return Some(key);
+            }
+            if !increment(bytes.as_mut()) {
+                return None;
+            }
+        }
+    }
+
+    /// Find the largest absent key inside the upper bound `to`.
+    fn last_empty(&self, to: Bound<K>) -> Option<K> {
+        let mut bytes = upper_bytes(to)?;
+        loop {
+            if !K::round_down(bytes.as_mut()) {
+                return None;
+            }
+            let key = K::from_bytes(bytes.clone());
+            if self.get(key).is_none() {
+                return Some(key);
+            }
+            if !decrement(bytes.as_mut()) {
+                return None;
+            }
+        }
+    }
+
+    /// Count the entries with keys between `from` and `to`. Small leaves
+    /// walk their entries in order.
+    fn count(&self, from: Bound<K>, to: Bound<K>) -> usize {
+        let upper = match upper_bytes(to) {
+            Some(upper) => upper,
+            None => return 0
+        };
+        let mut count = 0;
+        let mut bound = from;
+        while let Some((key, _)) = self.first(bound) {
+            if key.into_bytes().as_ref() > upper.as_ref() {
+                break;
+            }
+            count += 1;
+            bound = Bound::Excluded(key);
+        }
+        count
+    }
+
+    /// Count the entries whose keys start with `prefix`, which is shorter
+    /// than the keys. Small leaves check each of their entries.
+    fn count_prefix(&self, prefix: &[u8]) -> usize {
+        let mut count = 0;
+        let mut bound = Bound::Unbounded;
+        while let Some((key, _)) = self.first(bound) {
+            if key.into_bytes().as_ref().starts_with(prefix) {
+                count += 1;
+            }
+            bound = Bound::Excluded(key);
+        }
+        count
+    }
+
+    /// Find the entry at position `index` in key order.
+    fn nth(&self, index: usize) -> Option<(K, &V)> {
+        let mut bound = Bound::Unbounded;
+        for _ in 0..index {
+            let (key, _) = self.first(bound)?;
+            bound = Bound::Excluded(key);
+        }
+        self.first(bound)
+    }
     fn insert(&mut self, key: K, value: V) -> InsertResult<V>;
+    /// Insert an entry for `key`, which must not be present yet, returning
+    /// the slot the value went into.
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V>;
     fn expand(self, key: K, value: V) -> RootPtr<K, V>;
     fn remove(&mut self, key: K) -> RemoveResult<V>;
     fn shrink_remove(self, key: K) -> (RootPtr<K, V>, V);
@@ -40,10 +128,22 @@ impl<K: Key, V> RootLeaf<K, V> for Empty<K, V> {
         None
     }
 
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+        None
+    }
+
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+        None
+    }
+
     fn insert(&mut self, key: K, value: V) -> InsertResult<V> {
         InsertResult::Resize(value)
     }
 
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V> {
+        EntryResult::Resize(value)
+    }
+
     fn remove(&mut self, key: K) -> RemoveResult<V> {
         RemoveResult::Success(None)
     }
@@ -75,6 +175,8 @@ impl<'a, K: Key + 'a, V: 'a> IntoIterator for &'a Empty<K, V> {
     }
 }
 
+#[derive(Clone)]
+#[repr(align(8))]
 pub struct Leaf1<K: Key, V> {
     key: K,
     value: V
@@ -84,6 +186,14 @@ impl<K: Key, V> Leaf1<K, V> {
     pub fn new(key: K, value: V) -> Leaf1<K, V> {
         Leaf1 { key, value }
     }
+
+    pub fn entries(&self) -> (&[K], &[V]) {
+        (slice::from_ref(&self.key), slice::from_ref(&self.value))
+    }
+
+    pub fn entries_mut(&mut self) -> (&[K], &mut [V]) {
+        (slice::from_ref(&self.key), slice::from_mut(&mut self.value))
+    }
 }
 
 impl<'a, K: Key + 'a, V: 'a> IntoIterator for &'a Leaf1<K, V> {
@@ -112,6 +222,22 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf1<K, V> {
         }
     }
 
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+        if above_lower(from.as_ref(), &self.key) {
+            Some((self.key, &self.value))
+        } else {
+            None
+        }
+    }
+
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+        if below_upper(to.as_ref(), &self.key) {
+            Some((self.key, &self.value))
+        } else {
+            None
+        }
+    }
+
     fn insert(&mut self, key: K, value: V) -> InsertResult<V> {
         if self.key == key {
             InsertResult::replace(&mut self.value, value)
@@ -120,6 +246,11 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf1<K, V> {
         }
     }
 
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V> {
+        debug_assert!(self.key != key);
+        EntryResult::Resize(value)
+    }
+
     fn expand(self, key: K, value: V) -> RootPtr<K, V> {
         Box::new(Leaf2::new(self.key, self.value, key, value)).into()
     }
@@ -144,6 +275,8 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf1<K, V> {
     }
 }
 
+#[derive(Clone)]
+#[repr(align(8))]
 pub struct Leaf2<K: Key, V> {
     keys: [K; 2],
     values: [V; 2]
@@ -163,6 +296,14 @@ impl<K: Key, V> Leaf2<K, V> {
             }
         }
     }
+
+    pub fn entries(&self) -> (&[K], &[V]) {
+        (&self.keys, &self.values)
+    }
+
+    pub fn entries_mut(&mut self) -> (&[K], &mut [V]) {
+        (&self.keys, &mut self.values)
+    }
 }
 
 impl<K: Key, V> RootLeaf<K, V> for Leaf2<K, V> {
@@ -180,6 +321,21 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf2<K, V> {
             .map(|(key, value)| value)
     }
 
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+        self.keys.iter()
+            .zip(self.values.iter())
+            .find(|&(leaf_key, _)| above_lower(from.as_ref(), leaf_key))
+            .map(|(&key, value)| (key, value))
+    }
+
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+        self.keys.iter()
+            .zip(self.values.iter())
+            .rev()
+            .find(|&(leaf_key, _)| below_upper(to.as_ref(), leaf_key))
+            .map(|(&key, value)| (key, value))
+    }
+
     /// Attempt to insert, fail if we didn't find a key to replace
     fn insert(&mut self, key: K, value: V) -> InsertResult<V> {
         for (i, leaf_key) in self.keys.iter().enumerate() {
@@ -190,6 +346,11 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf2<K, V> {
         InsertResult::Resize(value)
     }
 
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V> {
+        debug_assert!(!self.keys.contains(&key));
+        EntryResult::Resize(value)
+    }
+
     fn expand(self, key: K, value: V) -> RootPtr<K, V> {
         let Leaf2 { keys, values } = self;
         let mut leaf = Box::new(VecLeaf::from_arrays(keys, values));
@@ -205,13 +366,13 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf2<K, V> {
     }
 
     fn shrink_remove(self, key: K) -> (RootPtr<K, V>, V) {
-        let Leaf2 { keys, mut values } = self;
+        let Leaf2 { keys, values } = self;
         let key1 = keys[0];
         let key2 = keys[1];
         let (value1, value2);
         unsafe {
-            value1 = ptr::read(&mut values[0]);
-            value2 = ptr::read(&mut values[1]);
+            value1 = ptr::read(&values[0]);
+            value2 = ptr::read(&values[1]);
             mem::forget(values);
         }
         if key1 == key {
@@ -228,18 +389,28 @@ impl<K: Key, V> RootLeaf<K, V> for Leaf2<K, V> {
     }
 }
 
+#[derive(Clone)]
+#[repr(align(8))]
 pub struct VecLeaf<K: Key, V> {
     array: locksteparray::LockstepArray<[K; 31], [V; 31]>
 }
 
 impl<K: Key, V> VecLeaf<K, V> {
-    fn new() -> VecLeaf<K, V> {
+    pub fn new() -> VecLeaf<K, V> {
         // TODO Copy memory from values
         VecLeaf {
             array: locksteparray::LockstepArray::new()
         }
     }
 
+    pub fn entries(&self) -> (&[K], &[V]) {
+        (self.array.array1(), self.array.array2())
+    }
+
+    pub fn entries_mut(&mut self) -> (&[K], &mut [V]) {
+        self.array.split_mut()
+    }
+
     fn from_arrays(keys: [K; 2], values: [V; 2]) -> VecLeaf<K, V> {
         VecLeaf {
             array: locksteparray::LockstepArray::from_arrays(keys, values)
@@ -247,6 +418,33 @@ impl<K: Key, V> VecLeaf<K, V> {
     }
 }
 
+impl<K: Key, V> From<Leaf1<K, V>> for VecLeaf<K, V> {
+    fn from(leaf: Leaf1<K, V>) -> VecLeaf<K, V> {
+        VecLeaf {
+            array: locksteparray::LockstepArray::from_arrays([leaf.key], [leaf.value])
+        }
+    }
+}
+
+impl<K: Key, V> From<Leaf2<K, V>> for VecLeaf<K, V> {
+    fn from(leaf: Leaf2<K, V>) -> VecLeaf<K, V> {
+        VecLeaf::from_arrays(leaf.keys, leaf.values)
+    }
+}
+
+impl<K: Key, V> iter::FromIterator<(K, V)> for VecLeaf<K, V> {
+    /// Build a leaf from entries that are already sorted by key.
+    fn from_iter<I>(iter: I) -> VecLeaf<K, V> where I: IntoIterator<Item=(K, V)> {
+        let mut leaf = VecLeaf::new();
+        for (key, value) in iter {
+            if leaf.array.push(key, value).is_err() {
+                panic!("Too many entries for a VecLeaf");
+            }
+        }
+        leaf
+    }
+}
+
 impl<K: Key, V> IntoIterator for VecLeaf<K, V> {
     type Item = (K, V);
     type IntoIter = locksteparray::IntoIter<[K; 31], [V; 31]>;
@@ -272,6 +470,20 @@ impl<K: Key, V> RootLeaf<K, V> for VecLeaf<K, V> {
             .map(move |index| &mut self.array.array2_mut()[index])
     }
 
+    fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+        self.array.array1()
+            .iter()
+            .position(|leaf_key| above_lower(from.as_ref(), leaf_key))
+            .map(|index| (self.array.array1()[index], &self.array.array2()[index]))
+    }
+
+    fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+        self.array.array1()
+            .iter()
+            .rposition(|leaf_key| below_upper(to.as_ref(), leaf_key))
+            .map(|index| (self.array.array1()[index], &self.array.array2()[index]))
+    }
+
     fn insert(&mut self, key: K, value: V) -> InsertResult<V> {
         match self.array.array1().linear_search(&key) {
             Ok(replace) => {
@@ -290,6 +502,18 @@ impl<K: Key, V> RootLeaf<K, V> for VecLeaf<K, V> {
         }
     }
 
+    fn insert_entry(&mut self, key: K, value: V) -> EntryResult<'_, V> {
+        let insert = match self.array.array1().linear_search(&key) {
+            Ok(_) => unreachable!(),
+            Err(insert) => insert
+        };
+        match self.array.insert(insert, key, value) {
+            Ok(()) => EntryResult::Success(&mut self.array.array2_mut()[insert]),
+            Err(locksteparray::InsertError::Overflow(_, value)) => EntryResult::Resize(value),
+            Err(locksteparray::InsertError::OutOfBounds(..)) => unreachable!()
+        }
+    }
+
     fn expand(self, key: K, value: V) -> RootPtr<K, V> {
         let mut jpm: Jpm<K, V> = self.into_iter().collect();
         jpm.insert(key, value).success();
@@ -297,16 +521,27 @@ impl<K: Key, V> RootLeaf<K, V> for VecLeaf<K, V> {
     }
 
     fn remove(&mut self, key: K) -> RemoveResult<V> {
-        let evicted = self.array.array1_mut()
-            .iter()
-            .position(|&k| k == key)
-            .and_then(|index| self.array.remove(index))
-            .map(|(_, value)| value);
+        let index = match self.array.array1().iter().position(|&k| k == key) {
+            Some(index) => index,
+            None => return RemoveResult::Success(None)
+        };
+        if self.array.len() == 3 {
+            return RemoveResult::Downsize;
+        }
+        let evicted = self.array.remove(index).map(|(_, value)| value);
         RemoveResult::Success(evicted)
     }
 
-    fn shrink_remove(self, key: K) -> (RootPtr<K, V>, V) {
-        unreachable!()
+    fn shrink_remove(mut self, key: K) -> (RootPtr<K, V>, V) {
+        let index = self.array.array1()
+            .iter()
+            .position(|&k| k == key)
+            .unwrap();
+        let (_, value) = self.array.remove(index).unwrap();
+        let (key2, value2) = self.array.pop().unwrap();
+        let (key1, value1) = self.array.pop().unwrap();
+        let ptr = Box::new(Leaf2::new(key1, value1, key2, value2)).into();
+        (ptr, value)
     }
 
     fn len(&self) -> usize {
@@ -1,10 +1,12 @@
 use std::ptr;
 use super::root_leaf::{RootLeaf, Empty, Leaf1, Leaf2, VecLeaf};
 use super::jpm::Jpm;
+use super::jpm::jpm_root::SHRINK_TO_LEAF;
 use ::Key;
 use std::marker::PhantomData;
 use std::mem;
-use super::results::{InsertResult, RemoveResult};
+use std::ops::Bound;
+use super::results::{InsertResult, EntryResult, RemoveResult};
 use util::NonZeroUsize;
 
 fn into_raw<T>(node: Box<T>) -> *mut () {
@@ -54,7 +56,7 @@ macro_rules! impl_root_ptr {
 
         impl<K: Key, V> RootPtr<K, V> {
             unsafe fn new(ptr: *mut(), type_code: usize) -> RootPtr<K, V> {
-                debug_assert!(type_code <= TYPE_CODE_MASK!(),
+                debug_assert!(type_code & !TYPE_CODE_MASK!() == 0,
                               "Type code was larger than largest allowed value: {:?} > {:?}",
                               type_code, TYPE_CODE_MASK!());
                 debug_assert_eq!(ptr as usize & TYPE_CODE_MASK!(), 0,
@@ -71,7 +73,7 @@ macro_rules! impl_root_ptr {
                 }
             }
 
-            pub fn as_ref(&self) -> RootRef<K, V> {
+            pub fn as_ref(&self) -> RootRef<'_, K, V> {
                 match self.type_code() {
                     TYPE_CODE_EMPTY => RootRef::Empty(Empty::new()),
                     $(
@@ -83,7 +85,7 @@ macro_rules! impl_root_ptr {
                 }
             }
 
-            pub fn as_mut(&mut self) -> RootMut<K, V> {
+            pub fn as_mut(&mut self) -> RootMut<'_, K, V> {
                 match self.type_code() {
                     TYPE_CODE_EMPTY => RootMut::Empty(Empty::new()),
                     $(
@@ -95,12 +97,33 @@ macro_rules! impl_root_ptr {
                 }
             }
 
+            /// Insert an entry for `key`, which must not be present yet,
+            /// returning the slot the value went into.
+            pub fn insert_entry(&mut self, key: K, value: V) -> &mut V {
+                // As in `InnerPtr::insert_entry`, the borrow checker cannot
+                // tell that the slot is only returned when nothing is replaced
+                let this: *mut RootPtr<K, V> = self;
+                let result = match unsafe { &mut *this }.as_mut() {
+                    RootMut::Empty(_) => EntryResult::Resize(value),
+                    $(
+                        RootMut::$type_name(node) => node.insert_entry(key, value),
+                    )*
+                };
+                match result {
+                    EntryResult::Success(slot) => slot,
+                    EntryResult::Resize(value) => {
+                        *self = self.take().expand(key, value);
+                        self.get_mut(key).unwrap()
+                    }
+                }
+            }
+
             pub fn into_owned(self) -> RootOwned<K, V> {
                 let ptr = self.ptr_mut();
                 let type_code = self.type_code();
                 ::std::mem::forget(self);
                 match type_code {
-                    TYPE_CODE_EMPTY => RootOwned::Empty(Box::new(Empty::new())),
+                    TYPE_CODE_EMPTY => RootOwned::Empty(Box::default()),
                     $(
                         $type_code => RootOwned::$type_name(
                             unsafe {
@@ -130,6 +153,33 @@ macro_rules! impl_root_ptr {
                 }
             }
 
+            pub fn first(&self, from: Bound<K>) -> Option<(K, &V)> {
+                match self.as_ref() {
+                    RootRef::Empty(_) => None,
+                    $(
+                        RootRef::$type_name(node) => node.first(from),
+                    )*
+                }
+            }
+
+            pub fn last(&self, to: Bound<K>) -> Option<(K, &V)> {
+                match self.as_ref() {
+                    RootRef::Empty(_) => None,
+                    $(
+                        RootRef::$type_name(node) => node.last(to),
+                    )*
+                }
+            }
+
+            pub fn nth(&self, index: usize) -> Option<(K, &V)> {
+                match self.as_ref() {
+                    RootRef::Empty(_) => None,
+                    $(
+                        RootRef::$type_name(node) => node.nth(index),
+                    )*
+                }
+            }
+
             fn type_code(&self) -> usize {
                 self.word.get() & TYPE_CODE_MASK!()
             }
@@ -158,6 +208,17 @@ macro_rules! impl_root_ptr {
             }
         }
 
+        impl<K: Key, V: Clone> Clone for RootPtr<K, V> {
+            fn clone(&self) -> RootPtr<K, V> {
+                match self.as_ref() {
+                    RootRef::Empty(_) => RootPtr::empty(),
+                    $(
+                        RootRef::$type_name(node) => Box::new(node.clone()).into(),
+                    )*
+                }
+            }
+        }
+
         $(
             impl<K: Key, V> From<Box<$type_name<K, V>>> for RootPtr<K, V> {
                 fn from(src: Box<$type_name<K, V>>) -> RootPtr<K, V> {
@@ -187,10 +248,42 @@ macro_rules! impl_root_ptr_dispatch {
                 }
             }
 
+            pub fn count(&self, from: Bound<K>, to: Bound<K>) -> usize {
+                match self.as_ref() {
+                    $(
+                        RootRef::$type_name(node) => node.count(from, to),
+                    )*
+                }
+            }
+
+            pub fn count_prefix(&self, prefix: &[u8]) -> usize {
+                match self.as_ref() {
+                    $(
+                        RootRef::$type_name(node) => node.count_prefix(prefix),
+                    )*
+                }
+            }
+
+            pub fn first_empty(&self, from: Bound<K>) -> Option<K> {
+                match self.as_ref() {
+                    $(
+                        RootRef::$type_name(node) => node.first_empty(from),
+                    )*
+                }
+            }
+
+            pub fn last_empty(&self, to: Bound<K>) -> Option<K> {
+                match self.as_ref() {
+                    $(
+                        RootRef::$type_name(node) => node.last_empty(to),
+                    )*
+                }
+            }
+
             pub fn insert(&mut self, key: K, value: V) -> Option<V> {
                 let result = match self.as_mut() {
                     $(
-                        RootMut::$type_name(mut node) => node.insert(key, value),
+                        RootMut::$type_name(ref mut node) => node.insert(key, value),
                     )*
                 };
                 match result {
@@ -217,7 +310,7 @@ macro_rules! impl_root_ptr_dispatch {
             pub fn remove(&mut self, key: K) -> Option<V> {
                 let result = match self.as_mut() {
                     $(
-                        RootMut::$type_name(mut node) => node.remove(key),
+                        RootMut::$type_name(ref mut node) => node.remove(key),
                     )*
                 };
                 match result {
@@ -249,9 +342,49 @@ macro_rules! impl_root_ptr_dispatch {
     }
 }
 
+// The root nodes are all `#[repr(align(8))]`, which keeps the low three bits
+// of a pointer to one free for the type code.
 impl_root_ptr!(
     2 => Leaf1,
     3 => Leaf2,
     4 => VecLeaf,
     5 => Jpm
 );
+
+impl<K: Key, V> RootPtr<K, V> {
+    /// The trie behind this root, if the map has grown into one.
+    pub fn jpm(&self) -> Option<&Jpm<K, V>> {
+        match self.as_ref() {
+            RootRef::Jpm(jpm) => Some(jpm),
+            _ => None
+        }
+    }
+
+    pub fn jpm_mut(&mut self) -> Option<&mut Jpm<K, V>> {
+        match self.as_mut() {
+            RootMut::Jpm(jpm) => Some(jpm),
+            _ => None
+        }
+    }
+
+    /// Move the trie out, leaving the root empty, if the map has grown into
+    /// one.
+    pub fn take_jpm(&mut self) -> Option<Box<Jpm<K, V>>> {
+        self.jpm()?;
+        match self.take().into_owned() {
+            RootOwned::Jpm(jpm) => Some(jpm),
+            _ => unreachable!()
+        }
+    }
+
+    /// Rebuild a trie that has lost entries in bulk as a leaf once it is
+    /// small enough, as removing them one at a time would have.
+    pub fn shrink(&mut self) {
+        if self.jpm().is_some_and(|jpm| jpm.len() <= SHRINK_TO_LEAF) {
+            let jpm = self.take_jpm().unwrap();
+            for (key, value) in *jpm {
+                self.insert(key, value);
+            }
+        }
+    }
+}
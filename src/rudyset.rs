@@ -1,5 +1,10 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, Peekable};
+use std::ops::RangeBounds;
 use ::Key;
-use ::rudymap::RudyMap;
+use ::rudymap::{self, RudyMap};
 
 pub struct RudySet<K: Key> {
     map: RudyMap<K, ()>
@@ -119,55 +124,547 @@ impl<K: Key> RudySet<K> {
         self.map.len()
     }
 
-    /*
-    pub fn iter(&self) -> Iter<'a, K> {
+    /// The number of bytes used by the set. The set shares the map's node
+    /// layouts, but its bitmap leaves carry no values, so a dense run of
+    /// members costs little more than a bit each.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let set: RudySet<u32> = (0..1 << 16).collect();
+    /// assert!(set.memory_usage() * 8 < 2 * set.len());
+    /// ```
+    pub fn memory_usage(&self) -> usize {
+        self.map.memory_usage()
+    }
+
+    /// Return the smallest value strictly greater than `value` that is not
+    /// in the set, like `Judy1NextEmpty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// set.insert(4);
+    /// set.insert(5);
+    /// assert_eq!(set.next_empty_after(3), Some(6));
+    /// ```
+    pub fn next_empty_after(&self, value: K) -> Option<K> {
+        self.map.next_empty_after(value)
+    }
+
+    /// Return the smallest value greater than or equal to `value` that is
+    /// not in the set, like `Judy1FirstEmpty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// set.insert(0);
+    /// set.insert(1);
+    /// assert_eq!(set.next_empty_at_or_after(0), Some(2));
+    /// ```
+    pub fn next_empty_at_or_after(&self, value: K) -> Option<K> {
+        self.map.next_empty_at_or_after(value)
+    }
+
+    /// Return the largest value strictly less than `value` that is not in
+    /// the set, like `Judy1PrevEmpty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// set.insert(4);
+    /// set.insert(5);
+    /// assert_eq!(set.prev_empty_before(6), Some(3));
+    /// ```
+    pub fn prev_empty_before(&self, value: K) -> Option<K> {
+        self.map.prev_empty_before(value)
+    }
+
+    /// Return the largest value less than or equal to `value` that is not
+    /// in the set, like `Judy1LastEmpty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// set.insert(4);
+    /// assert_eq!(set.prev_empty_at_or_before(4), Some(3));
+    /// assert_eq!(set.prev_empty_at_or_before(3), Some(3));
+    /// ```
+    pub fn prev_empty_at_or_before(&self, value: K) -> Option<K> {
+        self.map.prev_empty_at_or_before(value)
+    }
+
+    /// Iterate over the values in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// set.insert(9);
+    /// set.insert(3);
+    /// assert_eq!(set.iter().collect::<Vec<_>>(), vec![3, 9]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K> {
         Iter { iter: self.map.iter() }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<'a, K> {
-        IterMut { iter: self.map.iter() }
+    /// Iterate over the values inside `range` in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set = RudySet::<u32>::new();
+    /// for value in 0..10 {
+    ///     set.insert(value * 10);
+    /// }
+    /// assert_eq!(set.range(25..=50).collect::<Vec<_>>(), vec![30, 40, 50]);
+    /// ```
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K> {
+        Iter { iter: self.map.range(range) }
+    }
+
+    /// Iterate over the values in either set, in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// assert_eq!(a.union(&b).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    /// ```
+    pub fn union<'a>(&'a self, other: &'a RudySet<K>) -> Union<'a, K> {
+        Union {
+            a: self.iter().peekable(),
+            b: other.iter().peekable()
+        }
+    }
+
+    /// Iterate over the values in both sets, in ascending order. The smaller
+    /// set is walked and each of its values looked up in the larger one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// assert_eq!(a.intersection(&b).collect::<Vec<_>>(), vec![3]);
+    /// ```
+    pub fn intersection<'a>(&'a self, other: &'a RudySet<K>) -> Intersection<'a, K> {
+        let (small, large) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection {
+            iter: small.iter(),
+            other: large
+        }
+    }
+
+    /// Iterate over the values in this set but not in `other`, in ascending
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// assert_eq!(a.difference(&b).collect::<Vec<_>>(), vec![1, 2]);
+    /// ```
+    pub fn difference<'a>(&'a self, other: &'a RudySet<K>) -> Difference<'a, K> {
+        Difference {
+            iter: self.iter(),
+            other
+        }
+    }
+
+    /// Iterate over the values in exactly one of the sets, in ascending
+    /// order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// assert_eq!(a.symmetric_difference(&b).collect::<Vec<_>>(), vec![1, 2, 4]);
+    /// ```
+    pub fn symmetric_difference<'a>(&'a self, other: &'a RudySet<K>)
+                                    -> SymmetricDifference<'a, K> {
+        SymmetricDifference {
+            a: self.iter().peekable(),
+            b: other.iter().peekable()
+        }
+    }
+
+    /// Return `true` if every value in this set is also in `other`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2].iter().cloned().collect();
+    /// let b: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// assert_eq!(a.is_subset(&b), true);
+    /// assert_eq!(b.is_subset(&a), false);
+    /// ```
+    pub fn is_subset(&self, other: &RudySet<K>) -> bool {
+        self.len() <= other.len() && self.iter().all(|value| other.contains(value))
+    }
+
+    /// Return `true` if every value in `other` is also in this set.
+    pub fn is_superset(&self, other: &RudySet<K>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Return `true` if the sets have no values in common.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let a: RudySet<u32> = [1, 2].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3].iter().cloned().collect();
+    /// assert_eq!(a.is_disjoint(&b), true);
+    /// ```
+    pub fn is_disjoint(&self, other: &RudySet<K>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+    /// Build a set from values in ascending order, building each trie node
+    /// once rather than growing it one insertion at a time. Repeated values
+    /// are allowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a value is smaller than the one before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let set = RudySet::from_sorted_iter(0u32..1000);
+    /// assert_eq!(set.len(), 1000);
+    /// assert!(set.contains(999));
+    /// ```
+    pub fn from_sorted_iter<I: IntoIterator<Item=K>>(iter: I) -> RudySet<K> {
+        RudySet {
+            map: RudyMap::from_sorted_iter(iter.into_iter().map(|value| (value, ())))
+        }
+    }
+
+    /// Add values in ascending order, as `from_sorted_iter` would build
+    /// them, to this set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a value is smaller than the one before it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut set: RudySet<u32> = [5, 50].iter().cloned().collect();
+    /// set.extend_sorted(10..20);
+    /// assert_eq!(set.len(), 12);
+    /// ```
+    pub fn extend_sorted<I: IntoIterator<Item=K>>(&mut self, iter: I) {
+        self.map.extend_sorted(iter.into_iter().map(|value| (value, ())));
+    }
+
+    /// Add every value in `other` to this set. Unlike `union`, large sets
+    /// are merged trie node by trie node, taking over whole subtrees that
+    /// only `other` has rather than inserting their values one at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// a.union_with(b);
+    /// assert_eq!(a.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    /// ```
+    pub fn union_with(&mut self, other: RudySet<K>) {
+        self.map.union_with(other.map);
+    }
+
+    /// Keep only the values that are also in `other`. Large sets are walked
+    /// together, dropping whole subtrees that `other` lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// a.intersect_with(&b);
+    /// assert_eq!(a.iter().collect::<Vec<_>>(), vec![3]);
+    /// ```
+    pub fn intersect_with(&mut self, other: &RudySet<K>) {
+        self.map.intersect_with(&other.map);
+    }
+
+    /// Remove every value that is in `other`. Large sets are walked
+    /// together, keeping whole subtrees that `other` lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ::rudy::RudySet;
+    ///
+    /// let mut a: RudySet<u32> = [1, 2, 3].iter().cloned().collect();
+    /// let b: RudySet<u32> = [3, 4].iter().cloned().collect();
+    /// a.difference_with(&b);
+    /// assert_eq!(a.iter().collect::<Vec<_>>(), vec![1, 2]);
+    /// ```
+    pub fn difference_with(&mut self, other: &RudySet<K>) {
+        self.map.difference_with(&other.map);
+    }
+}
+
+impl<K: Key> Default for RudySet<K> {
+    fn default() -> RudySet<K> {
+        RudySet::new()
+    }
+}
+
+impl<K: Key> FromIterator<K> for RudySet<K> {
+    fn from_iter<I: IntoIterator<Item=K>>(iter: I) -> RudySet<K> {
+        let mut set = RudySet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<K: Key> Extend<K> for RudySet<K> {
+    fn extend<I: IntoIterator<Item=K>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<K: Key> IntoIterator for RudySet<K> {
+    type Item = K;
+    type IntoIter = IntoIter<K>;
+
+    fn into_iter(self) -> IntoIter<K> {
+        IntoIter { iter: self.map.into_iter() }
+    }
+}
+
+impl<'a, K: Key + 'a> IntoIterator for &'a RudySet<K> {
+    type Item = K;
+    type IntoIter = Iter<'a, K>;
+
+    fn into_iter(self) -> Iter<'a, K> {
+        self.iter()
+    }
+}
+
+impl<K: Key> PartialEq for RudySet<K> {
+    fn eq(&self, other: &RudySet<K>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Key> Eq for RudySet<K> {}
+
+impl<K: Key> PartialOrd for RudySet<K> {
+    fn partial_cmp(&self, other: &RudySet<K>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Sets compare lexicographically by their values in ascending order, like
+/// `BTreeSet`.
+impl<K: Key> Ord for RudySet<K> {
+    fn cmp(&self, other: &RudySet<K>) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K: Key + Hash> Hash for RudySet<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for value in self.iter() {
+            value.hash(state);
+        }
     }
-    */
 }
 
-/*
-struct Iter<'a, K> {
+impl<K: Key> fmt::Debug for RudySet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the values of a `RudySet` in ascending order.
+pub struct Iter<'a, K: Key + 'a> {
     iter: rudymap::Iter<'a, K, ()>
 }
 
-impl<'a, K: Key> Iterator<K> for Iter<'a, K> {
+impl<'a, K: Key + 'a> Iterator for Iter<'a, K> {
+    type Item = K;
+
     #[inline]
     fn next(&mut self) -> Option<K> {
         self.iter.next().map(|(k, _)| k)
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
 }
 
-struct IterMut<'a, K> {
-    iter: rudymap::IterMut<'a, K, ()>
+impl<'a, K: Key + 'a> ExactSizeIterator for Iter<'a, K> {}
+
+impl<'a, K: Key + 'a> DoubleEndedIterator for Iter<'a, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<K> {
+        self.iter.next_back().map(|(k, _)| k)
+    }
+}
+
+/// An owning iterator over the values of a `RudySet` in ascending order.
+pub struct IntoIter<K: Key> {
+    iter: rudymap::IntoIter<K, ()>
 }
 
-impl<'a, K: Key> Iterator<K> for IterMut<'a, K> {
+impl<K: Key> Iterator for IntoIter<K> {
+    type Item = K;
+
     #[inline]
     fn next(&mut self) -> Option<K> {
         self.iter.next().map(|(k, _)| k)
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
 }
 
-impl<K: Key + PartialEq> PartialEq for RudySet<K> {
-    fn eq(&self, other: &RudySet<K>) -> bool {
-        if self.len() != other.len() {
-            return false;
+impl<K: Key> ExactSizeIterator for IntoIter<K> {}
+
+/// The values in either of two sets. See `RudySet::union`.
+pub struct Union<'a, K: Key + 'a> {
+    a: Peekable<Iter<'a, K>>,
+    b: Peekable<Iter<'a, K>>
+}
+
+impl<'a, K: Key + 'a> Iterator for Union<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        match (self.a.peek(), self.b.peek()) {
+            (Some(a), Some(b)) => match a.cmp(b) {
+                Ordering::Less => self.a.next(),
+                Ordering::Greater => self.b.next(),
+                Ordering::Equal => {
+                    self.b.next();
+                    self.a.next()
+                }
+            },
+            (Some(_), None) => self.a.next(),
+            (None, _) => self.b.next()
         }
-        self.iter().zip(other.iter())
-            .all(|(a, b)| a == b)
     }
 }
 
-impl<K: Key + Eq> Eq for RudySet<K> {}
+/// The values in both of two sets. See `RudySet::intersection`.
+pub struct Intersection<'a, K: Key + 'a> {
+    iter: Iter<'a, K>,
+    other: &'a RudySet<K>
+}
+
+impl<'a, K: Key + 'a> Iterator for Intersection<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        let other = self.other;
+        self.iter.find(|&value| other.contains(value))
+    }
+}
+
+/// The values in one set but not another. See `RudySet::difference`.
+pub struct Difference<'a, K: Key + 'a> {
+    iter: Iter<'a, K>,
+    other: &'a RudySet<K>
+}
+
+impl<'a, K: Key + 'a> Iterator for Difference<'a, K> {
+    type Item = K;
 
- */
+    fn next(&mut self) -> Option<K> {
+        let other = self.other;
+        self.iter.find(|&value| !other.contains(value))
+    }
+}
+
+/// The values in exactly one of two sets. See
+/// `RudySet::symmetric_difference`.
+pub struct SymmetricDifference<'a, K: Key + 'a> {
+    a: Peekable<Iter<'a, K>>,
+    b: Peekable<Iter<'a, K>>
+}
+
+impl<'a, K: Key + 'a> Iterator for SymmetricDifference<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        loop {
+            match (self.a.peek(), self.b.peek()) {
+                (Some(a), Some(b)) => match a.cmp(b) {
+                    Ordering::Less => return self.a.next(),
+                    Ordering::Greater => return self.b.next(),
+                    Ordering::Equal => {
+                        self.a.next();
+                        self.b.next();
+                    }
+                },
+                (Some(_), None) => return self.a.next(),
+                (None, _) => return self.b.next()
+            }
+        }
+    }
+}
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use super::*;
 
@@ -267,4 +764,198 @@ mod test {
         assert_eq!(set.len(), 0);
         assert_eq!(set.is_empty(), true);
     }
+
+    #[test]
+    fn test_iter() {
+        let values = [7u32, 0, 1 << 20, 3, !0, 1000];
+        let set: RudySet<u32> = values.iter().cloned().collect();
+        let mut sorted = values.to_vec();
+        sorted.sort();
+        assert_eq!(set.iter().collect::<Vec<_>>(), sorted);
+        assert_eq!(set.iter().rev().collect::<Vec<_>>(),
+                   sorted.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!((&set).into_iter().count(), sorted.len());
+        assert_eq!(set.range(3..1000).collect::<Vec<_>>(), vec![3, 7]);
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), sorted);
+    }
+
+    #[test]
+    fn test_iter_len() {
+        let set: RudySet<u32> = (0..5000).map(|i| i * 37).collect();
+        let mut iter = set.iter();
+        assert_eq!(iter.len(), 5000);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.size_hint(), (4998, Some(4998)));
+        assert_eq!(set.range(37..370).len(), 9);
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        use std::collections::BTreeSet;
+
+        // big enough that both sets build tries
+        let a: Vec<u32> = (0..3000).map(|n| n * 3).collect();
+        let b: Vec<u32> = (0..2000).map(|n| n * 5 + 1).collect();
+        let set_a: RudySet<u32> = a.iter().cloned().collect();
+        let set_b: RudySet<u32> = b.iter().cloned().collect();
+        let tree_a: BTreeSet<u32> = a.iter().cloned().collect();
+        let tree_b: BTreeSet<u32> = b.iter().cloned().collect();
+
+        assert_eq!(set_a.union(&set_b).collect::<Vec<_>>(),
+                   tree_a.union(&tree_b).cloned().collect::<Vec<_>>());
+        assert_eq!(set_a.intersection(&set_b).collect::<Vec<_>>(),
+                   tree_a.intersection(&tree_b).cloned().collect::<Vec<_>>());
+        assert_eq!(set_b.intersection(&set_a).collect::<Vec<_>>(),
+                   tree_a.intersection(&tree_b).cloned().collect::<Vec<_>>());
+        assert_eq!(set_a.difference(&set_b).collect::<Vec<_>>(),
+                   tree_a.difference(&tree_b).cloned().collect::<Vec<_>>());
+        assert_eq!(set_b.difference(&set_a).collect::<Vec<_>>(),
+                   tree_b.difference(&tree_a).cloned().collect::<Vec<_>>());
+        assert_eq!(set_a.symmetric_difference(&set_b).collect::<Vec<_>>(),
+                   tree_a.symmetric_difference(&tree_b).cloned().collect::<Vec<_>>());
+
+        let empty = RudySet::new();
+        assert_eq!(set_a.union(&empty).count(), set_a.len());
+        assert_eq!(empty.intersection(&set_a).count(), 0);
+        assert_eq!(set_a.difference(&empty).count(), set_a.len());
+    }
+
+    #[test]
+    fn test_in_place_algebra() {
+        use std::collections::BTreeSet;
+
+        fn check<K: Key>(a: &[K], b: &[K]) {
+            let tree_a: BTreeSet<K> = a.iter().cloned().collect();
+            let tree_b: BTreeSet<K> = b.iter().cloned().collect();
+            let set = |values: &[K]| values.iter().cloned().collect::<RudySet<K>>();
+
+            let mut union = set(a);
+            union.union_with(set(b));
+            assert_eq!(union.iter().collect::<Vec<_>>(),
+                       tree_a.union(&tree_b).cloned().collect::<Vec<_>>());
+            assert_eq!(union.len(), tree_a.union(&tree_b).count());
+
+            let mut intersection = set(a);
+            intersection.intersect_with(&set(b));
+            assert_eq!(intersection.iter().collect::<Vec<_>>(),
+                       tree_a.intersection(&tree_b).cloned().collect::<Vec<_>>());
+            assert_eq!(intersection.len(), tree_a.intersection(&tree_b).count());
+
+            let mut difference = set(a);
+            difference.difference_with(&set(b));
+            assert_eq!(difference.iter().collect::<Vec<_>>(),
+                       tree_a.difference(&tree_b).cloned().collect::<Vec<_>>());
+            assert_eq!(difference.len(), tree_a.difference(&tree_b).count());
+        }
+
+        // dense runs meet as bitmap leaves
+        let evens: Vec<u32> = (0..5000).map(|n| n * 2).collect();
+        let thirds: Vec<u32> = (0..4000).map(|n| n * 3).collect();
+        check(&evens, &thirds);
+        check(&thirds, &evens);
+        // sparse keys spread over the whole range sit behind narrows
+        let sparse: Vec<u64> = (0..3000u64).map(|n| n.wrapping_mul(0x9e37_79b9_7f4a_7c15)).collect();
+        let shifted: Vec<u64> = sparse.iter().step_by(2).map(|&n| n ^ (n & 1)).collect();
+        check(&sparse, &shifted);
+        check(&shifted, &sparse);
+        // disjoint halves move whole subtrees across
+        let low: Vec<u32> = (0..2000).collect();
+        let high: Vec<u32> = (1 << 24..(1 << 24) + 2000).collect();
+        check(&low, &high);
+        // small sets are still leaves rather than tries
+        check(&evens, &[4, 5, 6]);
+        check(&[4, 5, 6], &evens);
+        check(&[1u32, 2], &[2, 3]);
+        check(&evens, &[]);
+        check(&[], &evens);
+    }
+
+    #[test]
+    fn test_in_place_shrinks() {
+        let mut few: RudySet<u32> = (0..10000).collect();
+        few.difference_with(&(3..10000).collect());
+        assert_eq!(few.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        // back to the leaf that inserting them would have built
+        let inserted: RudySet<u32> = (0..3).collect();
+        assert_eq!(few.map.memory_usage(), inserted.map.memory_usage());
+        let mut none: RudySet<u32> = (0..10000).collect();
+        none.intersect_with(&(20000..30000).collect());
+        assert!(none.is_empty());
+        assert_eq!(none.iter().next(), None);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        // a dense set is a bitmap leaf per 256 values plus a word pointing
+        // at each leaf, and a little for the branches further up
+        for &step in [1u32, 2, 16].iter() {
+            let set: RudySet<u32> = (0..1 << 16).map(|value| value * step).collect();
+            let possible = (1 << 16) * step as usize;
+            assert!(set.memory_usage() * 8 < possible * 13 / 10,
+                    "{} bytes for {} possible values", set.memory_usage(), possible);
+        }
+    }
+
+    #[test]
+    fn test_from_sorted_iter() {
+        let sorted = RudySet::from_sorted_iter((0..1 << 16).map(|value: u32| value * 3));
+        let inserted: RudySet<u32> = (0..1 << 16).map(|value| value * 3).collect();
+        assert_eq!(sorted, inserted);
+        assert_eq!(sorted.memory_usage(), inserted.memory_usage());
+
+        let mut extended = RudySet::from_sorted_iter(vec![1u32, 1, 2, 2, 3]);
+        assert_eq!(extended.len(), 3);
+        extended.extend_sorted(0..1000);
+        assert_eq!(extended, (0..1000).collect());
+    }
+
+    #[test]
+    fn test_subset_and_disjoint() {
+        let small: RudySet<u16> = (0..100).map(|n| n * 2).collect();
+        let large: RudySet<u16> = (0..400).collect();
+        let odd: RudySet<u16> = (0..100).map(|n| n * 2 + 1).collect();
+        assert!(small.is_subset(&large));
+        assert!(!large.is_subset(&small));
+        assert!(large.is_superset(&small));
+        assert!(small.is_subset(&small));
+        assert!(RudySet::new().is_subset(&small));
+        assert!(small.is_disjoint(&odd));
+        assert!(!small.is_disjoint(&large));
+        assert!(!odd.is_subset(&small));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::BTreeSet;
+
+        fn hash_of(set: &RudySet<u32>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            set.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let forwards: RudySet<u32> = (0..500).collect();
+        let backwards: RudySet<u32> = (0..500).rev().collect();
+        assert_eq!(forwards, backwards);
+        assert_eq!(hash_of(&forwards), hash_of(&backwards));
+        let mut fewer = backwards;
+        fewer.remove(250);
+        assert_ne!(forwards, fewer);
+        assert_ne!(hash_of(&forwards), hash_of(&fewer));
+
+        let sets: Vec<Vec<u32>> = vec![vec![], vec![1], vec![1, 2], vec![1, 3], vec![2]];
+        for a in sets.iter() {
+            for b in sets.iter() {
+                let rudy = (a.iter().cloned().collect::<RudySet<u32>>(),
+                            b.iter().cloned().collect::<RudySet<u32>>());
+                let tree = (a.iter().collect::<BTreeSet<_>>(),
+                            b.iter().collect::<BTreeSet<_>>());
+                assert_eq!(rudy.0.cmp(&rudy.1), tree.0.cmp(&tree.1));
+                assert_eq!(rudy.0.partial_cmp(&rudy.1), tree.0.partial_cmp(&tree.1));
+            }
+        }
+        assert_eq!(format!("{:?}", sets[3].iter().cloned().collect::<RudySet<u32>>()), "{1, 3}");
+    }
 }
@@ -0,0 +1,513 @@
+//! A map from byte strings of any length, after JudySL.
+//!
+//! Keys are cut into eight byte chunks. Each level of the tree is a
+//! `RudyMap` keyed by one chunk, so the levels are ordinary JPM tries. A
+//! chunk is padded with zeros and followed by a count of how many of its
+//! bytes are real. Ordering by chunk, then by count, orders keys
+//! lexicographically, and zero bytes inside keys are allowed.
+//!
+//! A count below eight means the key ends in that chunk and the slot holds
+//! its value. A full chunk leads on to the next level. As in JudySL, a full
+//! chunk that only one key passes through keeps the rest of that key
+//! inline instead of starting a level for it.
+
+use std::mem;
+use std::ops::Bound;
+use rudymap::{self, RudyMap};
+
+const CHUNK_LEN: usize = 8;
+
+/// Up to eight key bytes, padded with zeros, and then how many are real.
+type Chunk = [u8; CHUNK_LEN + 1];
+
+type Level<V> = RudyMap<Chunk, Slot<V>>;
+
+enum Slot<V> {
+    /// A key that ends in this chunk.
+    Value(V),
+    /// The only key that continues past this chunk, along with its
+    /// remaining bytes.
+    Suffix(Box<[u8]>, V),
+    /// The next level, holding keys that continue past this chunk.
+    Node(Level<V>)
+}
+
+/// Split off the first chunk of `key`, returning it along with the rest of
+/// the key.
+fn split_chunk(key: &[u8]) -> (Chunk, &[u8]) {
+    let len = key.len().min(CHUNK_LEN);
+    let mut chunk = [0; CHUNK_LEN + 1];
+    chunk[..len].copy_from_slice(&key[..len]);
+    chunk[CHUNK_LEN] = len as u8;
+    (chunk, &key[len..])
+}
+
+fn is_full(chunk: &Chunk) -> bool {
+    chunk[CHUNK_LEN] as usize == CHUNK_LEN
+}
+
+fn chunk_bytes(chunk: &Chunk) -> &[u8] {
+    &chunk[..chunk[CHUNK_LEN] as usize]
+}
+
+pub struct RudyStrMap<V> {
+    root: Level<V>,
+    len: usize
+}
+
+impl<V> RudyStrMap<V> {
+    pub fn new() -> RudyStrMap<V> {
+        RudyStrMap {
+            root: RudyMap::new(),
+            len: 0
+        }
+    }
+
+    /// Insert a value, returning the one it replaced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudystrmap::RudyStrMap;
+    ///
+    /// let mut map = RudyStrMap::new();
+    /// assert_eq!(map.insert("/usr/bin", 1), None);
+    /// assert_eq!(map.insert(b"/usr/bin", 2), Some(1));
+    /// assert_eq!(map.get("/usr/bin"), Some(&2));
+    /// ```
+    pub fn insert<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q, value: V) -> Option<V> {
+        let evicted = insert_at(&mut self.root, key.as_ref(), value);
+        if evicted.is_none() {
+            self.len += 1;
+        }
+        evicted
+    }
+
+    /// Remove a key, returning its value.
+    pub fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Option<V> {
+        let evicted = remove_at(&mut self.root, key.as_ref());
+        if evicted.is_some() {
+            self.len -= 1;
+        }
+        evicted
+    }
+
+    pub fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Option<&V> {
+        let mut level = &self.root;
+        let mut key = key.as_ref();
+        loop {
+            let (chunk, rest) = split_chunk(key);
+            match *level.get(chunk)? {
+                Slot::Value(ref value) => return Some(value),
+                Slot::Suffix(ref suffix, ref value) => {
+                    return if **suffix == *rest { Some(value) } else { None };
+                },
+                Slot::Node(ref child) => {
+                    level = child;
+                    key = rest;
+                }
+            }
+        }
+    }
+
+    pub fn get_mut<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Option<&mut V> {
+        get_mut_at(&mut self.root, key.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the entries in lexicographic key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudystrmap::RudyStrMap;
+    ///
+    /// let mut map = RudyStrMap::new();
+    /// map.insert("pear", 2);
+    /// map.insert("apple", 1);
+    /// let keys: Vec<Vec<u8>> = map.iter().map(|(k, _)| k).collect();
+    /// assert_eq!(keys, vec![b"apple".to_vec(), b"pear".to_vec()]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter::new(Vec::new(), self.root.iter(), 0)
+    }
+
+    /// Iterate over the entries whose keys start with `prefix`, in key
+    /// order. Only the levels under the prefix are visited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rudy::rudystrmap::RudyStrMap;
+    ///
+    /// let mut map = RudyStrMap::new();
+    /// for path in ["/etc/hosts", "/usr/bin/cc", "/usr/bin/ld", "/usr/lib"].iter() {
+    ///     map.insert(*path, ());
+    /// }
+    /// let found: Vec<Vec<u8>> = map.prefix_iter("/usr/bin/").map(|(k, _)| k).collect();
+    /// assert_eq!(found, vec![b"/usr/bin/cc".to_vec(), b"/usr/bin/ld".to_vec()]);
+    /// ```
+    pub fn prefix_iter<Q: AsRef<[u8]> + ?Sized>(&self, prefix: &Q) -> Iter<'_, V> {
+        let mut level = &self.root;
+        let mut prefix = prefix.as_ref();
+        let mut path = Vec::new();
+        while prefix.len() >= CHUNK_LEN {
+            let (chunk, rest) = split_chunk(prefix);
+            match level.get(chunk) {
+                Some(Slot::Node(child)) => {
+                    path.extend_from_slice(&chunk[..CHUNK_LEN]);
+                    level = child;
+                    prefix = rest;
+                },
+                Some(Slot::Suffix(suffix, value)) if suffix.starts_with(rest) => {
+                    path.extend_from_slice(&chunk[..CHUNK_LEN]);
+                    path.extend_from_slice(suffix);
+                    return Iter::single(path, value);
+                },
+                _ => return Iter::empty()
+            }
+        }
+        // Every chunk starting with the rest of the prefix, skipping keys
+        // that end before the prefix does
+        let mut low = [0; CHUNK_LEN + 1];
+        let mut high = [0xff; CHUNK_LEN + 1];
+        low[..prefix.len()].copy_from_slice(prefix);
+        high[..prefix.len()].copy_from_slice(prefix);
+        let range = level.range((Bound::Included(low), Bound::Included(high)));
+        Iter::new(path, range, prefix.len())
+    }
+
+    /// The number of bytes used by the map, including every level and the
+    /// key suffixes kept inline.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + level_memory_usage(&self.root)
+    }
+}
+
+impl<V> Default for RudyStrMap<V> {
+    fn default() -> RudyStrMap<V> {
+        RudyStrMap::new()
+    }
+}
+
+impl<'a, V: 'a> IntoIterator for &'a RudyStrMap<V> {
+    type Item = (Vec<u8>, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+fn insert_at<V>(level: &mut Level<V>, key: &[u8], value: V) -> Option<V> {
+    let (chunk, rest) = split_chunk(key);
+    if !is_full(&chunk) {
+        return level.insert(chunk, Slot::Value(value)).map(Slot::into_value);
+    }
+    let slot = match level.get_mut(chunk) {
+        Some(slot) => slot,
+        None => {
+            level.insert(chunk, Slot::Suffix(rest.into(), value));
+            return None;
+        }
+    };
+    match *slot {
+        Slot::Node(ref mut child) => return insert_at(child, rest, value),
+        Slot::Suffix(ref suffix, ref mut old) if **suffix == *rest => {
+            return Some(mem::replace(old, value));
+        },
+        Slot::Suffix(..) => {},
+        Slot::Value(_) => unreachable!()
+    }
+    // A second key through this chunk, so the inline suffix becomes a level
+    let mut child = RudyMap::new();
+    if let Slot::Suffix(suffix, old) = mem::replace(slot, Slot::Node(RudyMap::new())) {
+        insert_at(&mut child, &suffix, old);
+    }
+    insert_at(&mut child, rest, value);
+    *slot = Slot::Node(child);
+    None
+}
+
+fn get_mut_at<'a, V>(level: &'a mut Level<V>, key: &[u8]) -> Option<&'a mut V> {
+    let (chunk, rest) = split_chunk(key);
+    match *level.get_mut(chunk)? {
+        Slot::Value(ref mut value) => Some(value),
+        Slot::Suffix(ref suffix, ref mut value) => {
+            if **suffix == *rest { Some(value) } else { None }
+        },
+        Slot::Node(ref mut child) => get_mut_at(child, rest)
+    }
+}
+
+fn remove_at<V>(level: &mut Level<V>, key: &[u8]) -> Option<V> {
+    let (chunk, rest) = split_chunk(key);
+    if !is_full(&chunk) {
+        return level.remove(chunk).map(Slot::into_value);
+    }
+    let slot = level.get_mut(chunk)?;
+    let evicted = match *slot {
+        Slot::Suffix(ref suffix, _) if **suffix != *rest => return None,
+        Slot::Suffix(..) => None,
+        Slot::Node(ref mut child) => Some(remove_at(child, rest)?),
+        Slot::Value(_) => unreachable!()
+    };
+    let evicted = match evicted {
+        Some(evicted) => evicted,
+        None => return level.remove(chunk).map(Slot::into_value)
+    };
+    // Once the level under this chunk is down to one key, that key goes
+    // back inline
+    let lone = match *slot {
+        Slot::Node(ref child) => is_lone_key(child),
+        _ => false
+    };
+    if lone {
+        if let Slot::Node(child) = mem::replace(slot, Slot::Node(RudyMap::new())) {
+            let (suffix, value) = into_lone_key(child);
+            *slot = Slot::Suffix(suffix, value);
+        }
+    }
+    Some(evicted)
+}
+
+/// Whether `level` holds exactly one key and no levels below it.
+fn is_lone_key<V>(level: &Level<V>) -> bool {
+    if level.len() != 1 {
+        return false;
+    }
+    !matches!(level.first_key_value(), Some((_, &Slot::Node(_))))
+}
+
+/// Take apart a level that `is_lone_key`, returning the key bytes and value.
+fn into_lone_key<V>(level: Level<V>) -> (Box<[u8]>, V) {
+    let (chunk, slot) = level.into_iter().next().unwrap();
+    match slot {
+        Slot::Value(value) => (chunk_bytes(&chunk).into(), value),
+        Slot::Suffix(suffix, value) => {
+            let mut key = chunk[..CHUNK_LEN].to_vec();
+            key.extend_from_slice(&suffix);
+            (key.into_boxed_slice(), value)
+        },
+        Slot::Node(_) => unreachable!()
+    }
+}
+
+fn level_memory_usage<V>(level: &Level<V>) -> usize {
+    let mut bytes = level.memory_usage();
+    for (_, slot) in level.iter() {
+        match *slot {
+            Slot::Suffix(ref suffix, _) => bytes += suffix.len(),
+            Slot::Node(ref child) => bytes += level_memory_usage(child),
+            Slot::Value(_) => {}
+        }
+    }
+    bytes
+}
+
+impl<V> Slot<V> {
+    fn into_value(self) -> V {
+        match self {
+            Slot::Value(value) | Slot::Suffix(_, value) => value,
+            Slot::Node(_) => unreachable!()
+        }
+    }
+}
+
+/// An iterator over the entries of a `RudyStrMap` in key order. Keys are
+/// rebuilt from their chunks as they are visited.
+pub struct Iter<'a, V: 'a> {
+    /// The key bytes of the full chunks leading to the deepest level.
+    path: Vec<u8>,
+    /// One iterator per level on the way down.
+    levels: Vec<rudymap::Iter<'a, Chunk, Slot<V>>>,
+    /// At the top level, keys ending with fewer bytes than this in their
+    /// chunk are skipped.
+    min_len: usize,
+    /// A lone entry found without iterating.
+    single: Option<(Vec<u8>, &'a V)>
+}
+
+impl<'a, V: 'a> Iter<'a, V> {
+    fn new(path: Vec<u8>, top: rudymap::Iter<'a, Chunk, Slot<V>>, min_len: usize) -> Iter<'a, V> {
+        Iter {
+            path,
+            levels: vec![top],
+            min_len,
+            single: None
+        }
+    }
+
+    fn single(key: Vec<u8>, value: &'a V) -> Iter<'a, V> {
+        Iter {
+            path: Vec::new(),
+            levels: Vec::new(),
+            min_len: 0,
+            single: Some((key, value))
+        }
+    }
+
+    fn empty() -> Iter<'a, V> {
+        Iter {
+            path: Vec::new(),
+            levels: Vec::new(),
+            min_len: 0,
+            single: None
+        }
+    }
+}
+
+impl<'a, V: 'a> Iterator for Iter<'a, V> {
+    type Item = (Vec<u8>, &'a V);
+
+    fn next(&mut self) -> Option<(Vec<u8>, &'a V)> {
+        if let Some(single) = self.single.take() {
+            return Some(single);
+        }
+        loop {
+            let depth = self.levels.len();
+            let (chunk, slot) = match self.levels.last_mut()?.next() {
+                Some(entry) => entry,
+                None => {
+                    self.levels.pop();
+                    if !self.levels.is_empty() {
+                        let len = self.path.len() - CHUNK_LEN;
+                        self.path.truncate(len);
+                    }
+                    continue;
+                }
+            };
+            if depth == 1 && (chunk[CHUNK_LEN] as usize) < self.min_len {
+                continue;
+            }
+            match *slot {
+                Slot::Value(ref value) => {
+                    let mut key = self.path.clone();
+                    key.extend_from_slice(chunk_bytes(&chunk));
+                    return Some((key, value));
+                },
+                Slot::Suffix(ref suffix, ref value) => {
+                    let mut key = self.path.clone();
+                    key.extend_from_slice(&chunk[..CHUNK_LEN]);
+                    key.extend_from_slice(suffix);
+                    return Some((key, value));
+                },
+                Slot::Node(ref child) => {
+                    self.path.extend_from_slice(&chunk[..CHUNK_LEN]);
+                    self.levels.push(child.iter());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Keys of every length up to three chunks, sharing long prefixes and
+    /// holding zero bytes.
+    fn test_keys() -> Vec<Vec<u8>> {
+        let mut keys = vec![Vec::new(), vec![0], vec![0, 0]];
+        let mut state = 12345u32;
+        for index in 0..600 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let len = (state >> 16) as usize % 25;
+            let mut key = b"/srv/data/".to_vec();
+            key.truncate(index % 11);
+            for byte in 0..len {
+                key.push(b"ab\0"[(state >> byte) as usize % 3]);
+            }
+            keys.push(key);
+        }
+        keys
+    }
+
+    fn check_against(map: &RudyStrMap<usize>, expected: &BTreeMap<Vec<u8>, usize>) {
+        assert_eq!(map.len(), expected.len());
+        let found: Vec<(Vec<u8>, &usize)> = map.iter().collect();
+        let wanted: Vec<(Vec<u8>, &usize)> = expected.iter().map(|(k, v)| (k.clone(), v)).collect();
+        assert_eq!(found, wanted);
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = RudyStrMap::new();
+        let mut expected = BTreeMap::new();
+        let keys = test_keys();
+        for (index, key) in keys.iter().enumerate() {
+            assert_eq!(map.insert(key, index), expected.insert(key.clone(), index));
+        }
+        check_against(&map, &expected);
+        for key in keys.iter() {
+            assert_eq!(map.get(key), expected.get(key));
+            let mut longer = key.clone();
+            longer.push(0);
+            assert_eq!(map.get(&longer), expected.get(&longer));
+        }
+        assert_eq!(map.get_mut("/srv/data/c"), None);
+        *map.get_mut(&keys[10]).unwrap() = 999;
+        *expected.get_mut(&keys[10]).unwrap() = 999;
+
+        for key in keys.iter().step_by(2) {
+            assert_eq!(map.remove(key), expected.remove(key));
+            assert_eq!(map.remove(key), None);
+        }
+        check_against(&map, &expected);
+        for key in keys.iter() {
+            assert_eq!(map.contains_key(key), expected.contains_key(key));
+            assert_eq!(map.remove(key), expected.remove(key));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+    }
+
+    #[test]
+    fn test_prefix_iter() {
+        let mut map = RudyStrMap::new();
+        let mut expected = BTreeMap::new();
+        for (index, key) in test_keys().into_iter().enumerate() {
+            map.insert(&key, index);
+            expected.insert(key, index);
+        }
+        let prefixes: [&[u8]; 9] = [b"", b"/", b"/srv/da", b"/srv/dat", b"/srv/data",
+                                    b"/srv/data/ab", b"/srv/data/a\0b\0", b"\0", b"zzz"];
+        for prefix in prefixes.iter() {
+            let found: Vec<(Vec<u8>, &usize)> = map.prefix_iter(prefix).collect();
+            let wanted: Vec<(Vec<u8>, &usize)> = expected.iter()
+                .filter(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| (k.clone(), v))
+                .collect();
+            assert_eq!(found, wanted, "prefix {:?}", prefix);
+        }
+    }
+
+    #[test]
+    fn test_suffix_inline() {
+        let mut map = RudyStrMap::new();
+        map.insert("a fairly long key that only one entry uses", 1);
+        let lone = map.memory_usage();
+        map.insert("a fairly long key that two entries use", 2);
+        assert!(map.memory_usage() > lone);
+        let found: Vec<Vec<u8>> = map.prefix_iter("a fairly long key that o").map(|(k, _)| k).collect();
+        assert_eq!(found, vec![b"a fairly long key that only one entry uses".to_vec()]);
+
+        // removing the second key folds its levels back into a suffix
+        assert_eq!(map.remove("a fairly long key that two entries use"), Some(2));
+        assert_eq!(map.memory_usage(), lone);
+        assert_eq!(map.get("a fairly long key that only one entry uses"), Some(&1));
+        assert_eq!(map.get("a fairly long key"), None);
+    }
+}
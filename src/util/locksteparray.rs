@@ -1,10 +1,9 @@
-use std::mem;
+use std::mem::{self, MaybeUninit};
 use std::ops;
 use std::ptr;
 use std::slice;
 use std::iter;
 
-use nodrop::NoDrop;
 
 use num_traits::{Unsigned, Zero, One};
 
@@ -17,6 +16,10 @@ pub enum InsertError<T1, T2> {
     OutOfBounds(T1, T2)
 }
 
+/// # Safety
+///
+/// Implementors must be laid out as `capacity()` contiguous `Item`s, with
+/// `as_ptr` and `as_mut_ptr` pointing at the first one.
 pub unsafe trait Array {
     type Item;
     type Index: Index;
@@ -51,6 +54,16 @@ impl_index!(u16);
 impl_index!(u32);
 impl_index!(usize);
 
+#[inline(always)]
+fn items<A: Array>(array: &MaybeUninit<A>) -> *const A::Item {
+    array.as_ptr() as *const A::Item
+}
+
+#[inline(always)]
+fn items_mut<A: Array>(array: &mut MaybeUninit<A>) -> *mut A::Item {
+    array.as_mut_ptr() as *mut A::Item
+}
+
 macro_rules! impl_array {
     ($index_type:ty => $($len:expr),+ $(,)*) => (
         $(
@@ -76,12 +89,12 @@ macro_rules! impl_array {
     )
 }
 
-impl_array!(u8 => 1, 2, 7, 31, 256);
+impl_array!(u8 => 1, 2, 6, 7, 31, 256);
 
 pub struct LockstepArray<A1: Array, A2: Array> {
     len: A1::Index,
-    array1: NoDrop<A1>,
-    array2: NoDrop<A2>
+    array1: MaybeUninit<A1>,
+    array2: MaybeUninit<A2>
 }
 
 impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
@@ -90,8 +103,8 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
         assert_eq!(A1::capacity(), A2::capacity());
         LockstepArray {
             len: Zero::zero(),
-            array1: NoDrop::new(unsafe {mem::uninitialized()}),
-            array2: NoDrop::new(unsafe {mem::uninitialized()})
+            array1: MaybeUninit::uninit(),
+            array2: MaybeUninit::uninit()
         }
     }
 
@@ -105,11 +118,11 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
         let len = A1::Index::from_usize(B1::capacity());
         unsafe {
             ptr::copy_nonoverlapping(array1.as_ptr(),
-                                     lockstep.array1.as_mut_ptr(),
+                                     items_mut(&mut lockstep.array1),
                                      B1::capacity());
             mem::forget(array1);
             ptr::copy_nonoverlapping(array2.as_ptr(),
-                                     lockstep.array2.as_mut_ptr(),
+                                     items_mut(&mut lockstep.array2),
                                      B2::capacity());
             mem::forget(array2);
         }
@@ -123,10 +136,10 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
             return Err(OverflowError(item1, item2));
         }
         unsafe {
-            let p1 = self.array1.as_mut_ptr();
-            ptr::write(p1.offset(self.len.as_usize() as isize), item1);
-            let p2 = self.array2.as_mut_ptr();
-            ptr::write(p2.offset(self.len.as_usize() as isize), item2);
+            let p1 = items_mut(&mut self.array1);
+            ptr::write(p1.add(self.len.as_usize()), item1);
+            let p2 = items_mut(&mut self.array2);
+            ptr::write(p2.add(self.len.as_usize()), item2);
         }
         self.len += One::one();
         Ok(())
@@ -138,13 +151,13 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
         } else {
             unsafe {
                 // Calculate item 1's pointer
-                let src1 = self.array1.as_mut_ptr()
-                    .offset(self.len.as_usize() as isize - 1);
+                let src1 = items_mut(&mut self.array1)
+                    .add(self.len.as_usize() - 1);
                 // Move item 1 out of the pointer
                 let item1 = ptr::read(src1);
                 // Calculate item 2's pointer
-                let src2 = self.array2.as_mut_ptr()
-                    .offset(self.len.as_usize() as isize - 1);
+                let src2 = items_mut(&mut self.array2)
+                    .add(self.len.as_usize() - 1);
                 // Move item 2 out of the pointer
                 let item2 = ptr::read(src2);
                 // Update length
@@ -163,11 +176,11 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
         } else {
             unsafe {
                 // TODO: Tricky math, check indices and such
-                let dest1 = self.array1.as_mut_ptr().offset(index as isize);
-                ptr::copy(dest1, dest1.offset(1), self.len.as_usize() - index);
+                let dest1 = items_mut(&mut self.array1).add(index);
+                ptr::copy(dest1, dest1.add(1), self.len.as_usize() - index);
                 ptr::write(dest1, item1);
-                let dest2 = self.array2.as_mut_ptr().offset(index as isize);
-                ptr::copy(dest2, dest2.offset(1), self.len.as_usize() - index);
+                let dest2 = items_mut(&mut self.array2).add(index);
+                ptr::copy(dest2, dest2.add(1), self.len.as_usize() - index);
                 ptr::write(dest2, item2);
             }
             self.len += One::one();
@@ -183,17 +196,17 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
             let item2;
             unsafe {
                 // Calculate item 1's pointer
-                let src1 = self.array1.as_mut_ptr().offset(index as isize);
+                let src1 = items_mut(&mut self.array1).add(index);
                 // Move item 1 out of the pointer
                 item1 = ptr::read(src1);
                 // Shift down array 1 down by 1
-                ptr::copy(src1.offset(1), src1, self.len.as_usize() - index - 1);
+                ptr::copy(src1.add(1), src1, self.len.as_usize() - index - 1);
                 // Calculate item 2's pointer
-                let src2 = self.array2.as_mut_ptr().offset(index as isize);
+                let src2 = items_mut(&mut self.array2).add(index);
                 // Move item 2 out of the pointer
                 item2 = ptr::read(src2);
                 // Shift down array 2 down by 1
-                ptr::copy(src2.offset(1), src2, self.len.as_usize() - index - 1);
+                ptr::copy(src2.add(1), src2, self.len.as_usize() - index - 1);
                 // Update length
                 self.len -= One::one();
             }
@@ -214,10 +227,10 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
             None
         } else {
             let item1 = unsafe {
-                &*self.array1.as_ptr().offset(index as isize)
+                &*items(&self.array1).add(index)
             };
             let item2 = unsafe {
-                &*self.array2.as_ptr().offset(index as isize)
+                &*items(&self.array2).add(index)
             };
             Some((item1, item2))
         }
@@ -225,7 +238,7 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
 
     pub fn array1(&self) -> &[A1::Item] {
         unsafe {
-            let ptr = self.array1.as_ptr();
+            let ptr = items(&self.array1);
             let len = self.len.as_usize();
             slice::from_raw_parts(ptr, len)
         }
@@ -233,7 +246,7 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
 
     pub fn array2(&self) -> &[A2::Item] {
         unsafe {
-            let ptr = self.array2.as_ptr();
+            let ptr = items(&self.array2);
             let len = self.len.as_usize();
             slice::from_raw_parts(ptr, len)
         }
@@ -241,7 +254,7 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
 
     pub fn array1_mut(&mut self) -> &mut [A1::Item] {
         unsafe {
-            let ptr = self.array1.as_mut_ptr();
+            let ptr = items_mut(&mut self.array1);
             let len = self.len.as_usize();
             slice::from_raw_parts_mut(ptr, len)
         }
@@ -249,12 +262,21 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
 
     pub fn array2_mut(&mut self) -> &mut [A2::Item] {
         unsafe {
-            let ptr = self.array2.as_mut_ptr();
+            let ptr = items_mut(&mut self.array2);
             let len = self.len.as_usize();
             slice::from_raw_parts_mut(ptr, len)
         }
     }
 
+    /// Both arrays at once, with only the second one mutable.
+    pub fn split_mut(&mut self) -> (&[A1::Item], &mut [A2::Item]) {
+        let len = self.len.as_usize();
+        unsafe {
+            (slice::from_raw_parts(items(&self.array1), len),
+             slice::from_raw_parts_mut(items_mut(&mut self.array2), len))
+        }
+    }
+
     pub fn capacity(&self) -> usize {
         A1::capacity()
     }
@@ -262,15 +284,28 @@ impl<A1, A2> LockstepArray<A1, A2> where A1: Array, A2: Array {
 
 impl<A1, A2> Drop for LockstepArray<A1, A2> where A1: Array, A2: Array {
     fn drop(&mut self) {
-        for i in 0..self.len.as_usize() as isize {
+        for i in 0..self.len.as_usize() {
             unsafe {
-                ptr::drop_in_place(self.array1.as_mut_ptr().offset(i));
-                ptr::drop_in_place(self.array2.as_mut_ptr().offset(i));
+                ptr::drop_in_place(items_mut(&mut self.array1).add(i));
+                ptr::drop_in_place(items_mut(&mut self.array2).add(i));
             }
         }
     }
 }
 
+impl<A1, A2> Clone for LockstepArray<A1, A2>
+    where A1: Array, A2: Array, A1::Item: Clone, A2::Item: Clone {
+    fn clone(&self) -> Self {
+        let mut lockstep = LockstepArray::new();
+        for (item1, item2) in self.array1().iter().zip(self.array2()) {
+            if lockstep.push(item1.clone(), item2.clone()).is_err() {
+                unreachable!("a copy has the same capacity");
+            }
+        }
+        lockstep
+    }
+}
+
 impl<A1, A2> Default for LockstepArray<A1, A2> where A1: Array, A2: Array {
     fn default() -> Self {
         Self::new()
@@ -283,23 +318,15 @@ impl<A1, A2> IntoIterator for LockstepArray<A1, A2> where A1: Array, A2: Array {
     fn into_iter(mut self) -> Self::IntoIter {
         // LockstepArray implements Drop, so we can't move out of it
         // We can, however, copy its arrays and zero its length, in which case drop() does nothing
-        let mut array1: NoDrop<A1> = NoDrop::new(unsafe { mem::uninitialized() });
-        let mut array2: NoDrop<A2> = NoDrop::new(unsafe { mem::uninitialized() });
-        unsafe {
-            ptr::copy_nonoverlapping(self.array1.as_ptr(),
-                                     array1.as_mut_ptr(),
-                                     A1::capacity());
-            ptr::copy_nonoverlapping(self.array2.as_ptr(),
-                                     array2.as_mut_ptr(),
-                                     A2::capacity());
-        }
+        let array1 = unsafe { ptr::read(&self.array1) };
+        let array2 = unsafe { ptr::read(&self.array2) };
         let mut len = A1::Index::from_usize(0);
         mem::swap(&mut len, &mut self.len);
 
         IntoIter {
             len: len.as_usize(),
-            array1: array1,
-            array2: array2,
+            array1,
+            array2,
             pos: 0
         }
     }
@@ -307,8 +334,8 @@ impl<A1, A2> IntoIterator for LockstepArray<A1, A2> where A1: Array, A2: Array {
 
 pub struct IntoIter<A1: Array, A2: Array> {
     len: usize,
-    array1: NoDrop<A1>,
-    array2: NoDrop<A2>,
+    array1: MaybeUninit<A1>,
+    array2: MaybeUninit<A2>,
     pos: usize
 }
 
@@ -319,9 +346,9 @@ impl<A1: Array, A2: Array> Iterator for IntoIter<A1, A2> {
             None
         } else {
             unsafe {
-                let ptr1 = self.array1.as_mut_ptr().offset(self.pos as isize);
+                let ptr1 = items_mut(&mut self.array1).add(self.pos);
                 let item1 = ptr::read(ptr1);
-                let ptr2 = self.array2.as_mut_ptr().offset(self.pos as isize);
+                let ptr2 = items_mut(&mut self.array2).add(self.pos);
                 let item2 = ptr::read(ptr2);
                 self.pos += 1;
                 Some((item1, item2))
@@ -335,8 +362,8 @@ impl<A1: Array, A2: Array> Drop for IntoIter<A1, A2> {
         // drop any remaining items
         for i in self.pos..self.len {
             unsafe {
-                ptr::drop_in_place(self.array1.as_mut_ptr().offset(i as isize));
-                ptr::drop_in_place(self.array2.as_mut_ptr().offset(i as isize));
+                ptr::drop_in_place(items_mut(&mut self.array1).add(i));
+                ptr::drop_in_place(items_mut(&mut self.array2).add(i));
             }
         }
     }
@@ -1,5 +1,7 @@
 use std::mem::size_of;
 use std::cmp::Ordering;
+use std::iter::Enumerate;
+use std::ops::Bound;
 
 pub mod locksteparray;
 
@@ -37,6 +39,98 @@ pub fn partial_write(array: &mut [u8], mut value: usize) {
     debug_assert_eq!(value, 0, "Remaining value");
 }
 
+/// Treat `bytes` as a big-endian integer and add one to it. Returns `false`
+/// if it wrapped around.
+pub fn increment(bytes: &mut [u8]) -> bool {
+    for byte in bytes.iter_mut().rev() {
+        let (sum, carry) = byte.overflowing_add(1);
+        *byte = sum;
+        if !carry {
+            return true;
+        }
+    }
+    false
+}
+
+/// Treat `bytes` as a big-endian integer and subtract one from it. Returns
+/// `false` if it wrapped around.
+pub fn decrement(bytes: &mut [u8]) -> bool {
+    for byte in bytes.iter_mut().rev() {
+        let (difference, borrow) = byte.overflowing_sub(1);
+        *byte = difference;
+        if !borrow {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether `key` is on the inside of the lower bound `bound`.
+pub fn above_lower<T: Ord>(bound: Bound<&T>, key: &T) -> bool {
+    match bound {
+        Bound::Included(lower) => key >= lower,
+        Bound::Excluded(lower) => key > lower,
+        Bound::Unbounded => true
+    }
+}
+
+/// Whether `key` is on the inside of the upper bound `bound`.
+pub fn below_upper<T: Ord>(bound: Bound<&T>, key: &T) -> bool {
+    match bound {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true
+    }
+}
+
+/// The items of a 256-slot array whose bits are set in a bitmap, along with
+/// their positions, from either end.
+pub struct Masked<I> {
+    bitmap: [u8; 256 / 8],
+    base: u8,
+    iter: Enumerate<I>
+}
+
+impl<I: Iterator> Masked<I> {
+    /// Skip the slots of `iter` whose bits are clear in `bitmap`, numbering
+    /// the rest from `base`.
+    pub fn new(bitmap: [u8; 256 / 8], base: u8, iter: I) -> Masked<I> {
+        Masked {
+            bitmap,
+            base,
+            iter: iter.enumerate()
+        }
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.bitmap[index / 8] & (1 << (index % 8)) != 0
+    }
+}
+
+impl<I: Iterator> Iterator for Masked<I> {
+    type Item = (u8, I::Item);
+
+    fn next(&mut self) -> Option<(u8, I::Item)> {
+        while let Some((index, item)) = self.iter.next() {
+            if self.is_set(index) {
+                return Some((self.base + index as u8, item));
+            }
+        }
+        None
+    }
+}
+
+impl<I: DoubleEndedIterator + ExactSizeIterator> DoubleEndedIterator for Masked<I> {
+    fn next_back(&mut self) -> Option<(u8, I::Item)> {
+        while let Some((index, item)) = self.iter.next_back() {
+            if self.is_set(index) {
+                return Some((self.base + index as u8, item));
+            }
+        }
+        None
+    }
+}
+
 pub trait SliceExt {
     type Item;
     fn linear_search(&self, key: &Self::Item) -> Result<usize, usize>
@@ -90,6 +184,26 @@ fn test_read_write() {
     test_one(0x32659374, 4);
 }
 
+#[test]
+fn test_increment() {
+    let mut array = [0x01, 0xff];
+    assert!(increment(&mut array[..]));
+    assert_eq!(array, [0x02, 0x00]);
+    let mut array = [0xff, 0xff];
+    assert!(!increment(&mut array[..]));
+    assert_eq!(array, [0x00, 0x00]);
+}
+
+#[test]
+fn test_decrement() {
+    let mut array = [0x02, 0x00];
+    assert!(decrement(&mut array[..]));
+    assert_eq!(array, [0x01, 0xff]);
+    let mut array = [0x00, 0x00];
+    assert!(!decrement(&mut array[..]));
+    assert_eq!(array, [0xff, 0xff]);
+}
+
 #[test]
 fn test_find_item() {
     let array = [0, 1, 2, 3];
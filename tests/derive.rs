@@ -0,0 +1,91 @@
+#![cfg(feature = "derive")]
+
+extern crate rudy;
+
+use rudy::Key;
+use rudy::rudymap::RudyMap;
+
+#[derive(Key, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+struct UserId(u64);
+
+#[derive(Key, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+#[repr(u8)]
+enum Color {
+    #[default]
+    Red = 1,
+    Green = 5,
+    Blue = 9
+}
+
+#[derive(Key, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+struct Event {
+    color: Color,
+    user: UserId,
+    flagged: bool,
+    at: i32,
+    seq: u16
+}
+
+#[test]
+fn test_newtype() {
+    let mut map = RudyMap::new();
+    for id in (0..1000u64).rev() {
+        map.insert(UserId(id * 3), id);
+    }
+    assert_eq!(UserId(7).into_bytes(), 7u64.into_bytes());
+    assert_eq!(map.get(UserId(30)), Some(&10));
+    assert_eq!(map.next_after(UserId(30)), Some((UserId(33), &11)));
+    assert_eq!(map.next_empty_after(UserId(30)), Some(UserId(31)));
+    let ids: Vec<UserId> = map.iter().map(|(k, _)| k).take(3).collect();
+    assert_eq!(ids, vec![UserId(0), UserId(3), UserId(6)]);
+}
+
+#[test]
+fn test_enum() {
+    let mut map = RudyMap::new();
+    map.insert(Color::Blue, "blue");
+    map.insert(Color::Red, "red");
+    let colors: Vec<Color> = map.iter().map(|(k, _)| k).collect();
+    assert_eq!(colors, vec![Color::Red, Color::Blue]);
+    assert_eq!(Color::from_bytes(Color::Green.into_bytes()), Color::Green);
+
+    // the gaps between discriminants are skipped
+    assert_eq!(map.next_empty_after(Color::Red), Some(Color::Green));
+    assert_eq!(map.prev_empty_before(Color::Blue), Some(Color::Green));
+    map.insert(Color::Green, "green");
+    assert_eq!(map.next_empty_after(Color::Red), None);
+    assert_eq!(map.prev_empty_before(Color::Blue), None);
+}
+
+#[test]
+fn test_struct() {
+    let event = |color, user, at| Event {
+        color,
+        user: UserId(user),
+        flagged: user % 2 == 0,
+        at,
+        seq: 0
+    };
+    assert_eq!(event(Color::Red, 1, -5).into_bytes().as_ref().len(), 1 + 8 + 1 + 4 + 2);
+
+    let mut map = RudyMap::new();
+    let mut expected = Vec::new();
+    for user in 0..100 {
+        for &color in [Color::Blue, Color::Red].iter() {
+            let key = event(color, user, 50 - user as i32);
+            map.insert(key, user);
+            expected.push(key);
+        }
+    }
+    expected.sort();
+    let found: Vec<Event> = map.iter().map(|(k, _)| k).collect();
+    assert_eq!(found, expected);
+    assert_eq!(Event::from_bytes(expected[17].into_bytes()), expected[17]);
+
+    let mut after = expected[0];
+    after.seq = u16::MAX;
+    after.at = i32::MAX;
+    after.flagged = true;
+    assert_eq!(map.next_after(after).map(|(k, _)| k), Some(expected[1]));
+    assert_eq!(map.next_empty_after(expected[199]), Some(Event { seq: 1, ..expected[199] }));
+}
@@ -9,9 +9,18 @@ fn smoke_test() {
 
     for i in 0..n {
         assert!(map.insert(i, i).is_none());
+        assert_eq!(map.len(), i as usize + 1);
     }
 
     for i in 0..n {
-        assert_eq!(map.remove(i), Some(i));
+        assert_eq!(map.insert(i, i + 1), Some(i));
     }
+    assert_eq!(map.len(), n as usize);
+
+    for i in 0..n {
+        assert_eq!(map.remove(i), Some(i + 1));
+        assert_eq!(map.remove(i), None);
+        assert_eq!(map.len(), (n - i - 1) as usize);
+    }
+    assert!(map.is_empty());
 }